@@ -20,15 +20,30 @@ mod client {
     use url::Url;
 
     use crate::schemas::{
+        admin::{AdminError, AdminStatus},
         configuration::PublicConfig,
-        environments::{BaseResourceUsage, Environment, GetBaseResourceUsageError},
+        environments::{
+            BaseResourceUsage, Environment, GetBaseResourceUsageError, SelfTest, SelfTestError,
+        },
+        jobs::{CancelJobError, CreateJobError, CreateJobRequest, GetJobError, Job},
         programs::{
-            BuildError, BuildRequest, BuildResult, BuildRunError, BuildRunRequest, BuildRunResult,
-            RunError, RunRequest, RunResult,
+            BuildArchiveRequest, BuildError, BuildRequest, BuildResult, BuildRunError,
+            BuildRunRequest, BuildRunResult, CheckError, CheckResult, DownloadFilesError,
+            FormatError, FormatRequest, FormatResult, RunError, RunRequest, RunResult, TestError,
+            TestRequest, TestResult,
         },
         ErrorResponse,
     };
 
+    fn auth_headers(api_key: &str) -> reqwest::header::HeaderMap {
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {api_key}"))
+            .expect("invalid api key");
+        value.set_sensitive(true);
+        [(reqwest::header::AUTHORIZATION, value)]
+            .into_iter()
+            .collect()
+    }
+
     /// An asynchronous client for Sandkasten.
     #[derive(Debug, Clone)]
     pub struct SandkastenClient {
@@ -53,10 +68,84 @@ mod client {
             }
         }
 
+        /// Create a new client for the Sandkasten instance at `base_url` that
+        /// authenticates using the given api key.
+        ///
+        /// # Panics
+        /// Panics if the api key contains characters that are not allowed in
+        /// http headers.
+        pub fn with_api_key(base_url: Url, api_key: &str) -> Self {
+            Self {
+                base_url,
+                client: reqwest::Client::builder()
+                    .default_headers(auth_headers(api_key))
+                    .build()
+                    .unwrap(),
+            }
+        }
+
         /// Return the version of the sandkasten server.
         pub async fn version(&self) -> Result<String> {
             Ok(self.openapi_spec().await?.info.version)
         }
+
+        /// Download the files of a program that has previously been built as a
+        /// tar archive.
+        pub async fn download_files(
+            &self,
+            program_id: impl Display,
+        ) -> Result<Vec<u8>, DownloadFilesError> {
+            let response = self
+                .client
+                .get(
+                    self.base_url
+                        .join(&format!("programs/{program_id}/files"))?,
+                )
+                .send()
+                .await?;
+            if response.status().is_success() {
+                Ok(response.bytes().await?.into())
+            } else {
+                Err(Error::ErrorResponse(response.json().await?))
+            }
+        }
+
+        /// Upload a source archive and compile the program.
+        pub async fn build_archive(
+            &self,
+            data: &BuildArchiveRequest,
+        ) -> Result<BuildResult, BuildError> {
+            use reqwest::multipart::{Form, Part};
+
+            let mut form = Form::new()
+                .text("environment", data.environment.clone())
+                .part(
+                    "archive",
+                    Part::bytes(data.archive.clone()).file_name("archive"),
+                )
+                .text("env_vars", serde_json::to_string(&data.env_vars).unwrap())
+                .text("options", serde_json::to_string(&data.options).unwrap())
+                .text("packages", serde_json::to_string(&data.packages).unwrap())
+                .text(
+                    "compile_limits",
+                    serde_json::to_string(&data.compile_limits).unwrap(),
+                );
+            if let Some(main_file) = &data.main_file {
+                form = form.text("main_file", main_file.clone());
+            }
+
+            let response = self
+                .client
+                .post(self.base_url.join("programs")?)
+                .multipart(form)
+                .send()
+                .await?;
+            if response.status().is_success() {
+                Ok(response.json().await?)
+            } else {
+                Err(Error::ErrorResponse(response.json().await?))
+            }
+        }
     }
 
     #[cfg(feature = "blocking")]
@@ -69,10 +158,79 @@ mod client {
             }
         }
 
+        /// Create a new client for the Sandkasten instance at `base_url` that
+        /// authenticates using the given api key.
+        ///
+        /// # Panics
+        /// Panics if the api key contains characters that are not allowed in
+        /// http headers.
+        pub fn with_api_key(base_url: Url, api_key: &str) -> Self {
+            Self {
+                base_url,
+                client: reqwest::blocking::Client::builder()
+                    .default_headers(auth_headers(api_key))
+                    .build()
+                    .unwrap(),
+            }
+        }
+
         /// Return the version of the sandkasten server.
         pub fn version(&self) -> Result<String> {
             Ok(self.openapi_spec()?.info.version)
         }
+
+        /// Download the files of a program that has previously been built as a
+        /// tar archive.
+        pub fn download_files(
+            &self,
+            program_id: impl Display,
+        ) -> Result<Vec<u8>, DownloadFilesError> {
+            let response = self
+                .client
+                .get(
+                    self.base_url
+                        .join(&format!("programs/{program_id}/files"))?,
+                )
+                .send()?;
+            if response.status().is_success() {
+                Ok(response.bytes()?.into())
+            } else {
+                Err(Error::ErrorResponse(response.json()?))
+            }
+        }
+
+        /// Upload a source archive and compile the program.
+        pub fn build_archive(&self, data: &BuildArchiveRequest) -> Result<BuildResult, BuildError> {
+            use reqwest::blocking::multipart::{Form, Part};
+
+            let mut form = Form::new()
+                .text("environment", data.environment.clone())
+                .part(
+                    "archive",
+                    Part::bytes(data.archive.clone()).file_name("archive"),
+                )
+                .text("env_vars", serde_json::to_string(&data.env_vars).unwrap())
+                .text("options", serde_json::to_string(&data.options).unwrap())
+                .text("packages", serde_json::to_string(&data.packages).unwrap())
+                .text(
+                    "compile_limits",
+                    serde_json::to_string(&data.compile_limits).unwrap(),
+                );
+            if let Some(main_file) = &data.main_file {
+                form = form.text("main_file", main_file.clone());
+            }
+
+            let response = self
+                .client
+                .post(self.base_url.join("programs")?)
+                .multipart(form)
+                .send()?;
+            if response.status().is_success() {
+                Ok(response.json()?)
+            } else {
+                Err(Error::ErrorResponse(response.json()?))
+            }
+        }
     }
 
     /// The errors that may occur when using the client.
@@ -141,13 +299,35 @@ mod client {
         pub list_environments(): get "environments" => HashMap<String, Environment>;
         /// Return the base resource usage of an environment when running just a very basic program.
         pub get_base_resource_usage(path: environment): get "environments/{environment}/resource_usage" => BaseResourceUsage, GetBaseResourceUsageError;
+        /// Build and run the test program of an environment and mark the environment as unhealthy if it fails.
+        pub selftest(path: environment): post "environments/{environment}/selftest" => SelfTest, SelfTestError;
         /// Build and immediately run a program.
         pub build_and_run(json: BuildRunRequest): post "run" => BuildRunResult, BuildRunError;
         /// Upload and compile a program.
         pub build(json: BuildRequest): post "programs" => BuildResult, BuildError;
+        /// Check a program without building or storing it.
+        pub check(json: BuildRequest): post "check" => CheckResult, CheckError;
+        /// Format source files using the formatter of an environment.
+        pub format(json: FormatRequest): post "format" => FormatResult, FormatError;
         /// Run a program that has previously been built.
         pub run(path: program_id, json: RunRequest): post "programs/{program_id}/run" => RunResult, RunError;
 
+        /// Run the tests of a program that has previously been built.
+        pub test(path: program_id, json: TestRequest): post "programs/{program_id}/test" => TestResult, TestError;
+        /// Submit an asynchronous job that builds and runs a program.
+        pub create_job(json: CreateJobRequest): post "jobs" => Job, CreateJobError;
+        /// Return the status and the results of an asynchronous job.
+        pub get_job(path: job_id): get "jobs/{job_id}" => Job, GetJobError;
+        /// Cancel an asynchronous job and kill the processes it has started.
+        pub cancel_job(path: job_id): delete "jobs/{job_id}" => Job, CancelJobError;
+
+        /// Return the maintenance status of Sandkasten.
+        pub get_admin_status(): get "admin/status" => AdminStatus, AdminError;
+        /// Enable drain mode, i.e. reject new requests.
+        pub start_drain(): put "admin/drain" => AdminStatus, AdminError;
+        /// Disable drain mode.
+        pub stop_drain(): delete "admin/drain" => AdminStatus, AdminError;
+
         openapi_spec(): get "openapi.json" => OpenAPISpec;
     }
 
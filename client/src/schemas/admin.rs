@@ -0,0 +1,25 @@
+//! Schemas for admin endpoints.
+
+#[cfg(feature = "poem-openapi")]
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+/// The maintenance status of a Sandkasten instance.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct AdminStatus {
+    /// Whether the instance is in drain mode, i.e. new requests are rejected.
+    pub draining: bool,
+    /// The number of jobs that are currently running.
+    pub running_jobs: u64,
+    /// The number of requests that are waiting for free resources.
+    pub waiting_requests: u64,
+}
+
+/// The error responses that may be returned by admin endpoints.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum AdminError {
+    /// The api key is not allowed to use admin endpoints.
+    Forbidden,
+}
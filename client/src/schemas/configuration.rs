@@ -12,6 +12,8 @@ use super::programs::Limits;
 pub struct PublicConfig {
     /// The time to live for programs in seconds.
     pub program_ttl: u64,
+    /// The time to live for finished asynchronous jobs in seconds.
+    pub job_ttl: u64,
 
     /// The maximum number of jobs that can run at the same time.
     pub max_concurrent_jobs: usize,
@@ -1,9 +1,9 @@
 //! Schemas for environments endpoints.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[cfg(feature = "poem-openapi")]
-use poem_openapi::{types::Example, NewType, Object};
+use poem_openapi::{types::Example, NewType, Object, Union};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -24,6 +24,94 @@ pub struct Environment {
     pub example: Option<String>,
     /// Additional metadata specific to the environment.
     pub meta: Value,
+    /// The optional packages that can be selected in build requests.
+    pub packages: BTreeMap<String, EnvironmentPackage>,
+    /// The options that can be set in build requests.
+    pub build_options: BTreeMap<String, EnvironmentOption>,
+    /// The options that can be set in run requests.
+    pub run_options: BTreeMap<String, EnvironmentOption>,
+    /// The result of the most recent self-test of the environment (if it has
+    /// been tested). Environments whose self-test has failed cannot be used to
+    /// build programs.
+    pub selftest: Option<SelfTest>,
+}
+
+/// An optional package of an environment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct EnvironmentPackage {
+    /// The version of the package.
+    pub version: Option<String>,
+    /// A short description of the package.
+    pub description: Option<String>,
+}
+
+/// A typed option that can be set in build or run requests. Options are passed
+/// to the compile/run script as environment variables named
+/// `SANDKASTEN_OPTION_<NAME>` (with the name in uppercase).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Union))]
+#[cfg_attr(feature = "poem-openapi", oai(discriminator_name = "type"))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EnvironmentOption {
+    /// A boolean option (passed as `true` or `false`).
+    #[cfg_attr(feature = "poem-openapi", oai(mapping = "bool"))]
+    Bool(BoolOption),
+    /// An integer option.
+    #[cfg_attr(feature = "poem-openapi", oai(mapping = "int"))]
+    Int(IntOption),
+    /// An option that takes one of a fixed set of values.
+    #[cfg_attr(feature = "poem-openapi", oai(mapping = "enum"))]
+    Enum(EnumOption),
+}
+
+/// A boolean option.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct BoolOption {
+    /// A description of the option.
+    pub description: Option<String>,
+    /// The value that is used if the option is not set.
+    pub default: bool,
+}
+
+/// An integer option.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct IntOption {
+    /// A description of the option.
+    pub description: Option<String>,
+    /// The value that is used if the option is not set.
+    pub default: i64,
+    /// The minimum allowed value.
+    pub min: Option<i64>,
+    /// The maximum allowed value.
+    pub max: Option<i64>,
+}
+
+/// An option that takes one of a fixed set of values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct EnumOption {
+    /// A description of the option.
+    pub description: Option<String>,
+    /// The value that is used if the option is not set.
+    pub default: String,
+    /// The allowed values.
+    pub values: Vec<String>,
+}
+
+/// The result of running the test program of an environment.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct SelfTest {
+    /// Whether the test program has been built and run successfully and has
+    /// produced the expected output.
+    pub passed: bool,
+    /// The reason why the self-test has failed.
+    pub message: Option<String>,
+    /// The unix timestamp of when the self-test has finished.
+    pub tested_at: u64,
 }
 
 /// A map of environments where the key represents the id of the environment.
@@ -81,6 +169,17 @@ pub enum GetBaseResourceUsageError {
     EnvironmentNotFound,
 }
 
+/// The error responses that may be returned when running the self-test of an
+/// environment.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum SelfTestError {
+    /// The api key is not allowed to run self-tests.
+    Forbidden,
+    /// Environment does not exist.
+    EnvironmentNotFound,
+}
+
 #[cfg(feature = "poem-openapi")]
 impl Example for ListEnvironmentsResponse {
     fn example() -> Self {
@@ -95,6 +194,22 @@ impl Example for ListEnvironmentsResponse {
                     meta: serde_json::json!({
                         "homepage": "https://www.rust-lang.org/"
                     }),
+                    packages: BTreeMap::new(),
+                    build_options: BTreeMap::from([(
+                        "opt_level".into(),
+                        EnvironmentOption::Int(IntOption {
+                            description: Some("The optimization level.".into()),
+                            default: 3,
+                            min: Some(0),
+                            max: Some(3),
+                        }),
+                    )]),
+                    run_options: BTreeMap::new(),
+                    selftest: Some(SelfTest {
+                        passed: true,
+                        message: None,
+                        tested_at: 1700000000,
+                    }),
                 },
             ),
             (
@@ -107,6 +222,22 @@ impl Example for ListEnvironmentsResponse {
                     meta: serde_json::json!({
                         "packages": ["numpy", "pandas"]
                     }),
+                    packages: BTreeMap::from([(
+                        "pandas".into(),
+                        EnvironmentPackage {
+                            version: Some("2.2.3".into()),
+                            description: Some("Powerful data structures for data analysis".into()),
+                        },
+                    )]),
+                    build_options: BTreeMap::new(),
+                    run_options: BTreeMap::from([(
+                        "dev_mode".into(),
+                        EnvironmentOption::Bool(BoolOption {
+                            description: Some("Enable the Python development mode.".into()),
+                            default: false,
+                        }),
+                    )]),
+                    selftest: None,
                 },
             ),
         ]))
@@ -0,0 +1,36 @@
+//! Schemas for health endpoints.
+
+#[cfg(feature = "poem-openapi")]
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+/// The liveness of a Sandkasten instance.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct Liveness {
+    /// The version of Sandkasten.
+    pub version: String,
+}
+
+/// The readiness of a Sandkasten instance.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct Readiness {
+    /// Whether all checks have passed, i.e. whether the instance is able to
+    /// execute code.
+    pub ready: bool,
+    /// The results of the individual checks.
+    pub checks: Vec<HealthCheck>,
+}
+
+/// The result of a single readiness check.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct HealthCheck {
+    /// The name of the check.
+    pub name: String,
+    /// Whether the check has passed.
+    pub healthy: bool,
+    /// The reason why the check has failed.
+    pub message: Option<String>,
+}
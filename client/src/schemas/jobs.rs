@@ -0,0 +1,103 @@
+//! Schemas for jobs endpoints.
+
+#[cfg(feature = "poem-openapi")]
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::programs::{BuildRunRequest, BuildRunResult};
+
+/// The request data for submitting an asynchronous job.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct CreateJobRequest {
+    /// The data for building and running the program.
+    #[serde(flatten)]
+    #[cfg_attr(feature = "poem-openapi", oai(flatten))]
+    pub request: BuildRunRequest,
+    /// A URL the final [`Job`] is posted to when the job has finished or has
+    /// been cancelled. Must match one of the allowed webhook URLs in the
+    /// server config.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+}
+
+/// An asynchronous job that builds and runs a program.
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct Job {
+    /// The unique identifier of the job.
+    pub id: Uuid,
+    /// The current status of the job.
+    pub status: JobStatus,
+    /// The unix timestamp of when the job has been submitted.
+    pub created_at: u64,
+    /// The unix timestamp of when the job has started running.
+    pub started_at: Option<u64>,
+    /// The unix timestamp of when the job has finished or has been cancelled.
+    pub finished_at: Option<u64>,
+    /// The number of seconds a finished job is retained before it is deleted.
+    pub ttl: u64,
+    /// The results of building and running the program. Only available if the
+    /// job has finished successfully or has been cancelled while running (in
+    /// which case the killed processes report the `cancelled` termination
+    /// reason).
+    pub result: Option<BuildRunResult>,
+    /// The error response the `/run` endpoint would have returned. Only
+    /// available if the job has failed (or has been cancelled while building
+    /// the program).
+    pub error: Option<JobError>,
+}
+
+/// The status of a [`Job`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Enum))]
+#[cfg_attr(feature = "poem-openapi", oai(rename_all = "snake_case"))]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// The job is waiting for other jobs to finish.
+    Queued,
+    /// The job is running.
+    Running,
+    /// The job has finished (either successfully or with an error).
+    Finished,
+    /// The job has been cancelled before it could finish.
+    Cancelled,
+}
+
+/// The error of a failed [`Job`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct JobError {
+    /// The http status code of the error response.
+    pub status: u16,
+    /// The body of the error response (see
+    /// [`BuildRunError`](super::programs::BuildRunError) and
+    /// [`GeneralError`](super::GeneralError)).
+    pub response: Value,
+}
+
+/// The error responses that may be returned when submitting a job.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum CreateJobError {
+    /// The callback url does not match any of the allowed webhook urls.
+    CallbackUrlNotAllowed,
+}
+
+/// The error responses that may be returned when requesting a job.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum GetJobError {
+    /// Job does not exist.
+    JobNotFound,
+}
+
+/// The error responses that may be returned when cancelling a job.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum CancelJobError {
+    /// Job does not exist.
+    JobNotFound,
+}
@@ -1,9 +1,14 @@
 //! Sandkasten request and response schemas.
 
-use serde::Deserialize;
+#[cfg(feature = "poem-openapi")]
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
 
+pub mod admin;
 pub mod configuration;
 pub mod environments;
+pub mod health;
+pub mod jobs;
 pub mod programs;
 
 /// The error responses that any endpoint may return.
@@ -12,8 +17,53 @@ pub mod programs;
 pub enum GeneralError {
     /// 422 Unprocessable Content
     UnprocessableContent(String),
+    /// 429 Too Many Requests
+    TooManyRequests(RateLimitExceeded),
     /// 500 Internal Server Error
     InternalServerError,
+    /// 503 Service Unavailable
+    ServiceUnavailable(Overload),
+}
+
+/// The reason why a request has been rejected because the server is
+/// overloaded.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Enum))]
+#[cfg_attr(feature = "poem-openapi", oai(rename_all = "snake_case"))]
+#[serde(rename_all = "snake_case")]
+pub enum Overload {
+    /// Too many requests are already waiting for free job slots.
+    QueueFull,
+    /// The request has waited too long for free job slots.
+    QueueTimeout,
+    /// The server is in drain mode and does not accept new requests.
+    Draining,
+}
+
+/// Information about a rate limit or resource budget that has been exceeded.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct RateLimitExceeded {
+    /// The limit that has been exceeded.
+    pub limit: RateLimit,
+    /// The number of seconds to wait before sending the next request. Also
+    /// available in the `Retry-After` header.
+    pub retry_after: u64,
+}
+
+/// The rate limits and resource budgets of a client.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Enum))]
+#[cfg_attr(feature = "poem-openapi", oai(rename_all = "snake_case"))]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimit {
+    /// The number of requests per second.
+    Requests,
+    /// The cpu time used by the client's programs within the budget window.
+    CpuTime,
+    /// The memory-time (memory usage multiplied by cpu time) used by the
+    /// client's programs within the budget window.
+    MemoryTime,
 }
 
 /// The error responses that Sandkasten may return.
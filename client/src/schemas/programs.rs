@@ -1,7 +1,9 @@
 //! Schemas for programs endpoints.
 
+use std::collections::BTreeMap;
+
 #[cfg(feature = "poem-openapi")]
-use poem_openapi::Object;
+use poem_openapi::{Enum, Object, Union};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -33,6 +35,35 @@ pub struct BuildRequest {
     /// Limits to set on the compilation process.
     #[cfg_attr(feature = "poem-openapi", oai(default))]
     pub compile_limits: LimitsOpt,
+    /// Values for the build options of the selected environment.
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub options: BTreeMap<String, OptionValue>,
+    /// The optional packages of the selected environment to make available
+    /// during the build and run steps.
+    #[cfg_attr(feature = "poem-openapi", oai(default, validator(max_items = 32)))]
+    pub packages: Vec<String>,
+}
+
+/// The request data for building a program from a tar, tar.gz or zip archive
+/// that contains the source files.
+#[derive(Debug, Clone, Default)]
+pub struct BuildArchiveRequest {
+    /// The environment to use for building and running the program.
+    pub environment: String,
+    /// The path of the main source file in the archive. If omitted, the
+    /// default main file name of the selected environment is used.
+    pub main_file: Option<String>,
+    /// The content of the archive.
+    pub archive: Vec<u8>,
+    /// A list of environment variables to set during the build step.
+    pub env_vars: Vec<EnvVar>,
+    /// Limits to set on the compilation process.
+    pub compile_limits: LimitsOpt,
+    /// Values for the build options of the selected environment.
+    pub options: BTreeMap<String, OptionValue>,
+    /// The optional packages of the selected environment to make available
+    /// during the build and run steps.
+    pub packages: Vec<String>,
 }
 
 /// The request data for running a program.
@@ -58,16 +89,73 @@ pub struct RunRequest {
     /// Limits to set on the process.
     #[cfg_attr(feature = "poem-openapi", oai(default))]
     pub run_limits: LimitsOpt,
+    /// Values for the run options of the program's environment.
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub options: BTreeMap<String, OptionValue>,
+}
+
+/// The request data for running the tests of a program.
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct TestRequest {
+    /// A list of test files that are put in the working directory of the
+    /// process and passed to the test script.
+    #[cfg_attr(feature = "poem-openapi", oai(default, validator(max_items = 10)))]
+    pub files: Vec<File>,
+    /// A list of environment variables to set during the test run.
+    #[cfg_attr(feature = "poem-openapi", oai(default, validator(max_items = 16)))]
+    pub env_vars: Vec<EnvVar>,
+    /// Limits to set on the process.
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub run_limits: LimitsOpt,
+    /// Values for the run options of the program's environment.
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub options: BTreeMap<String, OptionValue>,
+}
+
+/// The request data for formatting source files.
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct FormatRequest {
+    /// The environment whose formatter should be used.
+    pub environment: String,
+    /// The main source file.
+    pub main_file: MainFile,
+    /// A list of additional source files.
+    #[cfg_attr(feature = "poem-openapi", oai(default, validator(max_items = 10)))]
+    pub files: Vec<File>,
+    /// Limits to set on the formatter process (checked against the compile
+    /// limits).
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub limits: LimitsOpt,
+}
+
+/// The value of a build or run option.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Union))]
+#[serde(untagged)]
+pub enum OptionValue {
+    /// The value of a boolean option.
+    Bool(bool),
+    /// The value of an integer option.
+    Int(i64),
+    /// The value of an enum option.
+    String(String),
 }
 
 /// A file that is put in the working directory of the build/run process.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "poem-openapi", derive(Object))]
 pub struct File {
-    /// The name of the file.
+    /// The name of the file. May be a relative path with up to 8 components
+    /// (e.g. `com/example/Main.java`), in which case the parent directories
+    /// are created automatically.
     #[cfg_attr(
         feature = "poem-openapi",
-        oai(validator(pattern = r"^[a-zA-Z0-9._-]{1,32}$"))
+        oai(validator(
+            max_length = 256,
+            pattern = r"^[a-zA-Z0-9._-]{1,64}(/[a-zA-Z0-9._-]{1,64}){0,7}$"
+        ))
     )]
     pub name: String,
     /// The content of the file.
@@ -81,10 +169,16 @@ pub struct File {
 #[cfg_attr(feature = "poem-openapi", derive(Object))]
 pub struct MainFile {
     /// The name of the file. If omitted, a default name is chosen based on the
-    /// selected environment.
+    /// selected environment. May be a relative path (see [`File::name`]).
     #[cfg_attr(
         feature = "poem-openapi",
-        oai(default, validator(pattern = r"^[a-zA-Z0-9._-]{1,32}$"))
+        oai(
+            default,
+            validator(
+                max_length = 256,
+                pattern = r"^[a-zA-Z0-9._-]{1,64}(/[a-zA-Z0-9._-]{1,64}){0,7}$"
+            )
+        )
     )]
     pub name: Option<String>,
     /// The content of the file.
@@ -96,7 +190,8 @@ pub struct MainFile {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "poem-openapi", derive(Object))]
 pub struct EnvVar {
-    /// The name of the environment variable.
+    /// The name of the environment variable. Names starting with `SANDKASTEN_`
+    /// are reserved.
     #[cfg_attr(
         feature = "poem-openapi",
         oai(validator(pattern = r"^[a-zA-Z0-9_]{1,64}$"))
@@ -121,6 +216,8 @@ pub struct BuildRunResult {
     /// The results of compiling the program. Empty iff programs don't need to
     /// be compiled in this environment.
     pub build: Option<RunResult>,
+    /// The diagnostics (e.g. warnings) reported by the compiler.
+    pub diagnostics: Vec<Diagnostic>,
     /// The results of running the program.
     pub run: RunResult,
 }
@@ -132,16 +229,24 @@ pub struct BuildRunResult {
 pub enum BuildRunError {
     /// Environment does not exist.
     EnvironmentNotFound,
+    /// The self-test of the environment has failed.
+    EnvironmentUnhealthy(String),
     /// Code could not be compiled.
-    CompileError(RunResult),
+    CompileError(CompileError),
     /// File names are not unique.
     InvalidFileNames,
     /// Environment variable names are not valid.
     InvalidEnvVars,
+    /// The specified options are not valid.
+    InvalidOptions(String),
+    /// A selected package does not exist.
+    UnknownPackage(String),
     /// The specified compile limits are too high.
     CompileLimitsExceeded(Vec<LimitExceeded>),
     /// The specified run limits are too high.
     RunLimitsExceeded(Vec<LimitExceeded>),
+    /// The program would exceed the storage quota of the api key.
+    StorageQuotaExceeded(u64),
 }
 
 /// The results of building a program.
@@ -158,22 +263,207 @@ pub struct BuildResult {
     /// The results of compiling the program. Empty iff programs don't need to
     /// be compiled in this environment.
     pub compile_result: Option<RunResult>,
+    /// The diagnostics (e.g. warnings) reported by the compiler.
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 /// The error responses that may be returned when building a program.
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "error", content = "details", rename_all = "snake_case")]
 pub enum BuildError {
+    /// The uploaded archive is invalid.
+    InvalidArchive(String),
     /// Environment does not exist.
     EnvironmentNotFound,
+    /// The self-test of the environment has failed.
+    EnvironmentUnhealthy(String),
     /// Code could not be compiled.
-    CompileError(RunResult),
+    CompileError(CompileError),
     /// File names are not unique.
     InvalidFileNames,
     /// Environment variable names are not valid.
     InvalidEnvVars,
+    /// The specified options are not valid.
+    InvalidOptions(String),
+    /// A selected package does not exist.
+    UnknownPackage(String),
     /// The specified compile limits are too high.
     CompileLimitsExceeded(Vec<LimitExceeded>),
+    /// The program would exceed the storage quota of the api key.
+    StorageQuotaExceeded(u64),
+}
+
+/// The results of compiling a program that could not be compiled.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct CompileError {
+    /// Whether the program has already been built before. Compile steps that
+    /// have been killed or have reached their time or memory limit are not
+    /// cached.
+    pub cached: bool,
+    /// The diagnostics (e.g. errors) reported by the compiler.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The results of compiling the program.
+    #[serde(flatten)]
+    #[cfg_attr(feature = "poem-openapi", oai(flatten))]
+    pub result: RunResult,
+}
+
+/// The results of checking a program.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct CheckResult {
+    /// The diagnostics reported by the check script.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The results of running the check script.
+    #[serde(flatten)]
+    #[cfg_attr(feature = "poem-openapi", oai(flatten))]
+    pub result: RunResult,
+}
+
+/// The error responses that may be returned when checking a program.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum CheckError {
+    /// Environment does not exist.
+    EnvironmentNotFound,
+    /// The environment does not support checking programs.
+    CheckNotSupported,
+    /// File names are not unique.
+    InvalidFileNames,
+    /// Environment variable names are not valid.
+    InvalidEnvVars,
+    /// The specified options are not valid.
+    InvalidOptions(String),
+    /// A selected package does not exist.
+    UnknownPackage(String),
+    /// The specified compile limits are too high.
+    CompileLimitsExceeded(Vec<LimitExceeded>),
+}
+
+/// The results of running the tests of a program.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct TestResult {
+    /// The test cases from the report written by the test script. Empty if
+    /// the test script did not write a (valid) report.
+    pub tests: Vec<TestCase>,
+    /// The results of running the test script.
+    #[serde(flatten)]
+    #[cfg_attr(feature = "poem-openapi", oai(flatten))]
+    pub result: RunResult,
+}
+
+/// A single test case.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct TestCase {
+    /// The name of the test case.
+    pub name: String,
+    /// Whether the test case passed.
+    pub status: TestStatus,
+    /// The number of **milliseconds** the test case ran (if reported).
+    pub duration: Option<u64>,
+    /// The failure message or the reason why the test case has been skipped.
+    pub message: Option<String>,
+}
+
+/// The status of a [`TestCase`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Enum))]
+#[cfg_attr(feature = "poem-openapi", oai(rename_all = "snake_case"))]
+#[serde(rename_all = "snake_case")]
+pub enum TestStatus {
+    /// The test case passed.
+    Passed,
+    /// An assertion of the test case failed.
+    Failed,
+    /// The test case could not be executed (e.g. because of an unexpected
+    /// exception).
+    Error,
+    /// The test case has been skipped.
+    Skipped,
+}
+
+/// The error responses that may be returned when running the tests of a
+/// program.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum TestError {
+    /// File names are not unique.
+    InvalidFileNames,
+    /// Environment variable names are not valid.
+    InvalidEnvVars,
+    /// The specified options are not valid.
+    InvalidOptions(String),
+    /// Program does not exist.
+    ProgramNotFound,
+    /// The environment of the program does not support running tests.
+    TestsNotSupported,
+    /// The specified run limits are too high.
+    RunLimitsExceeded(Vec<LimitExceeded>),
+}
+
+/// The reformatted source files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct FormatResult {
+    /// The reformatted main source file.
+    pub main_file: File,
+    /// The reformatted additional source files.
+    pub files: Vec<File>,
+}
+
+/// The error responses that may be returned when formatting source files.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum FormatError {
+    /// Environment does not exist.
+    EnvironmentNotFound,
+    /// The environment does not support formatting source files.
+    FormatNotSupported,
+    /// The formatter failed (e.g. because of a syntax error).
+    FormatFailed(RunResult),
+    /// File names are not unique.
+    InvalidFileNames,
+    /// The specified limits are too high.
+    LimitsExceeded(Vec<LimitExceeded>),
+}
+
+/// A diagnostic message reported by the compiler. Only available for
+/// environments that declare the output format of their compiler.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Object))]
+pub struct Diagnostic {
+    /// The path of the file the diagnostic refers to (relative to the working
+    /// directory of the build step).
+    pub file: Option<String>,
+    /// The line number (starting at 1).
+    pub line: Option<u32>,
+    /// The column number (starting at 1).
+    pub column: Option<u32>,
+    /// The severity of the diagnostic.
+    pub severity: Severity,
+    /// The message of the diagnostic.
+    pub message: String,
+    /// The error code or warning flag (e.g. `E0308` or `-Wunused-variable`).
+    pub code: Option<String>,
+}
+
+/// The severity of a [`Diagnostic`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Enum))]
+#[cfg_attr(feature = "poem-openapi", oai(rename_all = "snake_case"))]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// An error that prevents the program from being compiled.
+    Error,
+    /// A warning.
+    Warning,
+    /// Additional information about another diagnostic.
+    Note,
+    /// A suggestion on how to fix another diagnostic.
+    Help,
 }
 
 /// The results of running (or compiling) a program.
@@ -190,6 +480,20 @@ pub struct RunResult {
     pub resource_usage: ResourceUsage,
     /// The limits that applied to the process.
     pub limits: Limits,
+    /// The reason why the process has been killed by Sandkasten (if it did not
+    /// exit on its own).
+    #[serde(default)]
+    pub termination_reason: Option<TerminationReason>,
+}
+
+/// The reason why a process has been killed by Sandkasten.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "poem-openapi", derive(Enum))]
+#[cfg_attr(feature = "poem-openapi", oai(rename_all = "snake_case"))]
+#[serde(rename_all = "snake_case")]
+pub enum TerminationReason {
+    /// The job has been cancelled, e.g. because the client has disconnected.
+    Cancelled,
 }
 
 /// The error responses that may be returned when running a program.
@@ -200,12 +504,25 @@ pub enum RunError {
     InvalidFileNames,
     /// Environment variable names are not valid.
     InvalidEnvVars,
+    /// The specified options are not valid.
+    InvalidOptions(String),
     /// Program does not exist.
     ProgramNotFound,
     /// The specified run limits are too high.
     RunLimitsExceeded(Vec<LimitExceeded>),
 }
 
+/// The error responses that may be returned when downloading the files of a
+/// program.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "error", content = "details", rename_all = "snake_case")]
+pub enum DownloadFilesError {
+    /// Program does not exist.
+    ProgramNotFound,
+    /// The files of the program exceed the maximum archive size (in bytes).
+    FilesTooLarge(u64),
+}
+
 /// The amount of resources a process used.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[cfg_attr(feature = "poem-openapi", derive(Object))]
@@ -239,7 +556,7 @@ macro_rules! limits {
         }
 
         /// The resource limits of a process. Omit a value to use the default limit.
-        #[derive(Debug, Clone, Default, Serialize)]
+        #[derive(Debug, Clone, Default, Serialize, Deserialize)]
         #[cfg_attr(feature = "poem-openapi", derive(Object))]
         pub struct LimitsOpt {
             $(
@@ -280,6 +597,36 @@ macro_rules! limits {
                 }
             }
         }
+
+        impl Limits {
+            /// Lower each limit to the corresponding value of `max_limits`, if it is set.
+            pub fn restrict(&self, max_limits: &LimitsOpt) -> Limits {
+                Limits {
+                    $(
+                        $name : match max_limits.$name {
+                            Some(max) if max < self.$name => max,
+                            _ => self.$name,
+                        },
+                    )*
+                }
+            }
+
+            /// Check whether none of the limits is higher than the corresponding limit in
+            /// `other`.
+            pub fn within(&self, other: &Limits) -> bool {
+                true $( && self.$name <= other.$name )*
+            }
+        }
+
+        impl From<Limits> for LimitsOpt {
+            fn from(limits: Limits) -> Self {
+                Self {
+                    $(
+                        $name : Some(limits.$name),
+                    )*
+                }
+            }
+        }
     };
 }
 
@@ -64,8 +64,11 @@
         enable_metrics = true;
         programs_dir = "programs";
         jobs_dir = "jobs";
+        compile_cache_dir = "cache";
         program_ttl = 60;
         prune_programs_interval = 30;
+        webhook_allowed_urls = ["http://127.0.0.1:8001/"];
+        webhook_secret = "sandkasten";
         run_limits = config.run_limits // {network = true;};
         nsjail_path = ".nsjail";
         time_path = "${self.packages.${pkgs.system}.time}/bin/time";
@@ -73,7 +76,7 @@
   };
   test-script = pkgs.writeShellScript "integration-tests.sh" ''
     export PROPTEST_CASES=''${1:-256}
-    rm -rf programs jobs
+    rm -rf programs jobs cache
     RUST_LOG=info,poem::middleware::tracing_mw=off RUN_LIMITS__TIME=20 cargo llvm-cov run --lcov --output-path lcov-server.info --release --locked -F test_api &
     pid=$!
     while ! ${pkgs.curl}/bin/curl -so/dev/null localhost:8000; do
@@ -32,6 +32,7 @@ in
           serviceConfig = {
             ExecStart = "${self.packages.${pkgs.system}.sandkasten}/bin/sandkasten";
             StateDirectory = "sandkasten";
+            CacheDirectory = "sandkasten";
             PrivateTmp = true;
             Restart = lib.mkDefault "always";
             RestartSec = lib.mkDefault 1;
@@ -44,6 +45,7 @@ in
               environments_path = ["${self.packages.${pkgs.system}.packages.combined cfg.environments}/share/sandkasten/packages"];
               programs_dir = "/var/lib/sandkasten/programs";
               jobs_dir = "/tmp/sandkasten/jobs";
+              compile_cache_dir = "/var/cache/sandkasten";
               base_resource_usage_permits = (conf // cfg.settings).max_concurrent_jobs;
             };
           in {
@@ -9,8 +9,12 @@
     };
   };
   default_main_file_name = "code.c";
-  compile_script = ''${pkgs.gcc}/bin/gcc -std=c17 -O2 -o /program/binary "$1"'';
+  compile_script = ''CCACHE_DIR=/cache ${pkgs.ccache}/bin/ccache ${pkgs.gcc}/bin/gcc -std=c17 -O2 -o /program/binary "$1"'';
+  compile_cache.path = "/cache";
+  diagnostics = "gcc";
   run_script = ''shift; /program/binary "$@"'';
+  check_script = ''${pkgs.gcc}/bin/gcc -std=c17 -Wall -Wextra -fsyntax-only "$1"'';
+  format_script = ''${pkgs.clang-tools}/bin/clang-format -i "$@"'';
   example = ''
     #include <stdio.h>
 
@@ -9,8 +9,27 @@
     };
   };
   default_main_file_name = "code.cpp";
-  compile_script = ''${pkgs.gcc}/bin/g++ -std=c++20 -O2 -o /program/binary "$1"'';
+  compile_script = ''CCACHE_DIR=/cache ${pkgs.ccache}/bin/ccache ${pkgs.gcc}/bin/g++ -std=$SANDKASTEN_OPTION_STD -O$SANDKASTEN_OPTION_OPT_LEVEL -o /program/binary "$1"'';
+  build_options = {
+    std = {
+      type = "enum";
+      description = "The C++ standard.";
+      values = ["c++17" "c++20" "c++23"];
+      default = "c++20";
+    };
+    opt_level = {
+      type = "int";
+      description = "The optimization level.";
+      min = 0;
+      max = 3;
+      default = 2;
+    };
+  };
+  compile_cache.path = "/cache";
+  diagnostics = "gcc";
   run_script = ''shift; /program/binary "$@"'';
+  check_script = ''${pkgs.gcc}/bin/g++ -std=$SANDKASTEN_OPTION_STD -Wall -Wextra -fsyntax-only "$1"'';
+  format_script = ''${pkgs.clang-tools}/bin/clang-format -i "$@"'';
   example = ''
     #include <bits/stdc++.h>
 
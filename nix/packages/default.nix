@@ -22,21 +22,50 @@
       default_main_file_name,
       compile_script,
       run_script,
+      check_script ? null,
+      format_script ? null,
+      test_script ? null,
       example ? null,
+      compile_cache ? null,
+      diagnostics ? null,
+      build_options ? {},
+      run_options ? {},
       test,
       ...
     } @ v: let
       manifest = pkgs.writeText "sandkasten-${id}-${version}-manifest.json" (builtins.toJSON rec {
         sandkasten_version = lib.cargotoml.package.version;
-        inherit name version meta default_main_file_name example test;
+        inherit name version meta default_main_file_name example compile_cache diagnostics build_options run_options test;
         compile_script =
           if builtins.isNull v.compile_script
           then null
           else pkgs.writeShellScript "sandkasten-${id}-${version}-compile.sh" v.compile_script;
         run_script = pkgs.writeShellScript "sandkasten-${id}-${version}-run.sh" v.run_script;
+        check_script =
+          if builtins.isNull v.check_script
+          then null
+          else pkgs.writeShellScript "sandkasten-${id}-${version}-check.sh" v.check_script;
+        format_script =
+          if builtins.isNull v.format_script
+          then null
+          else pkgs.writeShellScript "sandkasten-${id}-${version}-format.sh" v.format_script;
+        test_script =
+          if builtins.isNull v.test_script
+          then null
+          else pkgs.writeShellScript "sandkasten-${id}-${version}-test.sh" v.test_script;
+        packages =
+          builtins.mapAttrs (_: p: {
+            inherit (p) version description;
+            env_vars = p.env_vars or {};
+            closure = "${pkgs.closureInfo {rootPaths = p.paths;}}/store-paths";
+          })
+          (v.packages or {});
         closure =
           (rootPaths: "${pkgs.closureInfo {inherit rootPaths;}}/store-paths") ([run_script]
-            ++ (pkgs.lib.optional (compile_script != null) compile_script));
+            ++ (pkgs.lib.optional (compile_script != null) compile_script)
+            ++ (pkgs.lib.optional (check_script != null) check_script)
+            ++ (pkgs.lib.optional (format_script != null) format_script)
+            ++ (pkgs.lib.optional (test_script != null) test_script));
       });
     in
       pkgs.stdenv.mkDerivation {
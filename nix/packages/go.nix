@@ -5,8 +5,11 @@
     inherit (pkgs.go.meta) description homepage;
   };
   default_main_file_name = "code.go";
-  compile_script = ''HOME=/tmp ${pkgs.go}/bin/go build -o /program/binary "$1"'';
+  compile_script = ''HOME=/tmp GOCACHE=/cache ${pkgs.go}/bin/go build -o /program/binary "$1"'';
+  compile_cache.path = "/cache";
+  diagnostics = "generic";
   run_script = ''shift; /program/binary "$@"'';
+  format_script = ''${pkgs.go}/bin/gofmt -w "$@"'';
   example = ''
     package main
 
@@ -21,6 +21,7 @@
     fi
     ${pkgs.jdk}/bin/javac -d /program "$@"
   '';
+  diagnostics = "javac";
   run_script = ''
     shift
     mem=$(${pkgs.gnugrep}/bin/grep 'address space' /proc/self/limits | ${pkgs.gawk}/bin/awk '{print $5}')
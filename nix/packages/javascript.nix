@@ -11,6 +11,8 @@
   default_main_file_name = "code.js";
   compile_script = null;
   run_script = ''${pkgs.nodejs}/bin/node /program/"$@"'';
+  test_script = ''shift; ${pkgs.nodejs}/bin/node --test --test-reporter=tap --test-reporter-destination="$SANDKASTEN_TEST_REPORT" "$@"'';
+  format_script = ''${pkgs.nodePackages.prettier}/bin/prettier --no-config --write "$@"'';
   example = ''
     let fs = require("fs");
 
@@ -6,6 +6,7 @@
   };
   default_main_file_name = "code.kt";
   compile_script = ''PATH=${pkgs.coreutils}/bin ${pkgs.kotlin}/bin/kotlinc -include-runtime -d /program/program.jar "$@"'';
+  diagnostics = "gcc";
   run_script = ''shift; PATH=${pkgs.coreutils}/bin ${pkgs.kotlin}/bin/kotlin /program/program.jar "$@"'';
   example = ''
     fun main() {
@@ -9,7 +9,36 @@ in {
   };
   default_main_file_name = "code.py";
   compile_script = null;
-  run_script = ''${pkgs.python311.withPackages py-pkgs}/bin/python /program/"$@"'';
+  check_script = ''PYTHONPYCACHEPREFIX=/tmp ${pkgs.python311}/bin/python -m py_compile "$@"'';
+  format_script = ''BLACK_CACHE_DIR=/tmp ${pkgs.black}/bin/black --quiet "$@"'';
+  run_script = ''
+    flags=()
+    [[ $SANDKASTEN_OPTION_DEV_MODE = true ]] && flags+=(-X dev)
+    ${pkgs.python311.withPackages py-pkgs}/bin/python "''${flags[@]}" /program/"$@"
+  '';
+  test_script = ''
+    shift
+    export PYTHONPATH=/program''${PYTHONPATH:+:$PYTHONPATH} PYTHONDONTWRITEBYTECODE=1
+    ${pkgs.python311.withPackages (p: py-pkgs p ++ [p.pytest])}/bin/python -m pytest \
+      -p no:cacheprovider -q --junitxml="$SANDKASTEN_TEST_REPORT" "$@"
+  '';
+  packages = let
+    mkPackage = p: {
+      inherit (p) version;
+      inherit (p.meta) description;
+      paths = [p];
+      env_vars.PYTHONPATH = pkgs.python311.pkgs.makePythonPath [p];
+    };
+  in
+    with pkgs.python311.pkgs; {
+      pandas = mkPackage pandas;
+      requests-mock = mkPackage requests-mock;
+    };
+  run_options.dev_mode = {
+    type = "bool";
+    description = "Enable the Python Development Mode.";
+    default = false;
+  };
   example = ''
     name = input()
     print(f"Hello, {name}!")
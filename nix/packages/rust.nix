@@ -5,8 +5,11 @@
     inherit (pkgs.rustc.meta) description homepage;
   };
   default_main_file_name = "code.rs";
-  compile_script = ''PATH=${pkgs.gcc}/bin/ ${pkgs.rustc}/bin/rustc --edition=2021 -O -o /program/binary "$1"'';
+  compile_script = ''PATH=${pkgs.gcc}/bin/ ${pkgs.rustc}/bin/rustc --edition=2021 --error-format=json -O -o /program/binary "$1"'';
+  diagnostics = "rustc_json";
   run_script = ''shift; /program/binary "$@"'';
+  check_script = ''${pkgs.rustc}/bin/rustc --edition=2021 --error-format=json --emit=metadata -o /tmp/check "$1"'';
+  format_script = ''${pkgs.rustfmt}/bin/rustfmt --edition=2021 "$@"'';
   example = ''
     fn main() {
       let mut name = String::new();
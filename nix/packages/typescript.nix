@@ -29,11 +29,13 @@ in {
     ${pkgs.coreutils}/bin/ln -s ${node_modules}/node_modules .
     ${pkgs.typescript}/bin/tsc -m node16 --outDir /program/ "$@"
   '';
+  diagnostics = "tsc";
   run_script = ''
     main=/program/$(${pkgs.coreutils}/bin/basename "$1" .ts).js
     shift
     ${pkgs.nodejs}/bin/node "$main" "$@"
   '';
+  format_script = ''${pkgs.nodePackages.prettier}/bin/prettier --no-config --write "$@"'';
   example = ''
     import * as fs from "fs";
 
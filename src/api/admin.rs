@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use poem_ext::response;
+use poem_openapi::OpenApi;
+use sandkasten_client::schemas::admin::AdminStatus;
+use tracing::info;
+
+use super::{auth::ApiAuth, Tags};
+use crate::queue::JobQueue;
+
+pub struct AdminApi {
+    pub job_queue: Arc<JobQueue>,
+}
+
+#[OpenApi(tag = "Tags::Admin")]
+impl AdminApi {
+    /// Return the maintenance status of Sandkasten.
+    #[oai(path = "/admin/status", method = "get")]
+    async fn get_status(&self, auth: ApiAuth) -> Admin::Response {
+        if !auth.0.admin {
+            return Admin::forbidden();
+        }
+        Admin::ok(self.status())
+    }
+
+    /// Enable drain mode.
+    ///
+    /// In drain mode, new requests that would start jobs are rejected with
+    /// `503 Service Unavailable`, while running jobs and requests that are
+    /// already waiting for free resources are completed. This can be used to
+    /// take an instance out of rotation.
+    #[oai(path = "/admin/drain", method = "put")]
+    async fn start_drain(&self, auth: ApiAuth) -> Admin::Response {
+        if !auth.0.admin {
+            return Admin::forbidden();
+        }
+        info!("Enabling drain mode");
+        self.job_queue.set_draining(true);
+        Admin::ok(self.status())
+    }
+
+    /// Disable drain mode.
+    #[oai(path = "/admin/drain", method = "delete")]
+    async fn stop_drain(&self, auth: ApiAuth) -> Admin::Response {
+        if !auth.0.admin {
+            return Admin::forbidden();
+        }
+        info!("Disabling drain mode");
+        self.job_queue.set_draining(false);
+        Admin::ok(self.status())
+    }
+}
+
+impl AdminApi {
+    fn status(&self) -> AdminStatus {
+        let (running_jobs, waiting_requests) = self.job_queue.status();
+        AdminStatus {
+            draining: self.job_queue.is_draining(),
+            running_jobs,
+            waiting_requests,
+        }
+    }
+}
+
+response!(Admin = {
+    /// The maintenance status of Sandkasten.
+    Ok(200) => AdminStatus,
+    /// The api key is not allowed to use admin endpoints.
+    Forbidden(403, error),
+});
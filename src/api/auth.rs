@@ -0,0 +1,56 @@
+use std::{sync::Arc, time::Instant};
+
+use poem::Request;
+use poem_ext::{custom_auth, response};
+use poem_openapi::auth::Bearer;
+
+use super::rate_limit::RateLimited;
+use crate::{auth::Caller, config::Config, metrics::Metrics, rate_limit::RateLimiter};
+
+/// Authenticate a request using an api key that is passed as a bearer token.
+/// If no api keys are configured, the token is ignored. Requests of clients
+/// that have exceeded their rate limit are rejected. The priority class of the
+/// request can be selected using the `X-Priority` header.
+pub struct ApiAuth(pub Caller);
+
+custom_auth!(ApiAuth, api_auth_check);
+
+async fn api_auth_check(
+    req: &Request,
+    token: Option<Bearer>,
+) -> Result<Caller, ApiAuthResult::raw::Response> {
+    let config = req.data::<Arc<Config>>().unwrap();
+    let address = match req.remote_addr().as_socket_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => req.remote_addr().to_string(),
+    };
+    let mut caller = Caller::authenticate(
+        config,
+        token.as_ref().map(|token| token.token.as_str()),
+        &address,
+    )
+    .ok_or_else(ApiAuthResult::raw::unauthorized)?;
+
+    if let Some(class) = req.header("X-Priority") {
+        if !caller.select_priority(config, class) {
+            return Err(ApiAuthResult::raw::invalid_priority());
+        }
+    }
+
+    let rate_limiter = req.data::<Arc<RateLimiter>>().unwrap();
+    if let Err(err) = rate_limiter.check_request(config, &caller.client, Instant::now()) {
+        let metrics = req.data::<Arc<Metrics>>().unwrap();
+        return Err(RateLimited::new(metrics, err).into());
+    }
+
+    Ok(caller)
+}
+
+response!(ApiAuthResult = {
+    /// The api key is missing or invalid.
+    Unauthorized(401, error),
+    /// The priority class selected in the `X-Priority` header does not exist or
+    /// is not allowed for the api key.
+    InvalidPriority(403, error),
+    ..RateLimited,
+});
@@ -4,7 +4,7 @@ use poem_ext::response;
 use poem_openapi::OpenApi;
 use sandkasten_client::schemas::configuration::PublicConfig;
 
-use super::Tags;
+use super::{auth::ApiAuth, Tags};
 use crate::{config::Config, metrics::MetricsData};
 
 pub struct ConfigurationApi {
@@ -14,14 +14,18 @@ pub struct ConfigurationApi {
 #[OpenApi(tag = "Tags::Configuration")]
 impl ConfigurationApi {
     /// Return the public configuration of Sandkasten.
+    ///
+    /// The returned limits are the effective maximum limits of the
+    /// authenticated client.
     #[oai(path = "/config", method = "get")]
-    async fn get_config(&self, metrics: MetricsData<'_>) -> GetConfig::Response {
+    async fn get_config(&self, metrics: MetricsData<'_>, auth: ApiAuth) -> GetConfig::Response {
         metrics.0.requests.config.inc();
         GetConfig::ok(PublicConfig {
             program_ttl: self.config.program_ttl,
+            job_ttl: self.config.job_ttl,
             max_concurrent_jobs: self.config.max_concurrent_jobs,
-            compile_limits: self.config.compile_limits.clone(),
-            run_limits: self.config.run_limits.clone(),
+            compile_limits: auth.0.compile_limits,
+            run_limits: auth.0.run_limits,
             base_resource_usage_runs: self.config.base_resource_usage_runs,
         })
     }
@@ -1,29 +1,43 @@
 use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use key_rwlock::KeyRwLock;
-use poem_ext::{response, responses::ErrorResponse, shield_mw::shield};
+use poem_ext::{response, responses::ErrorResponse};
 use poem_openapi::{param::Path, OpenApi};
 use sandkasten_client::schemas::{
-    environments::{BaseResourceUsage, Environment, ListEnvironmentsResponse, RunResourceUsage},
+    environments::{
+        BaseResourceUsage, Environment, EnvironmentPackage, ListEnvironmentsResponse,
+        RunResourceUsage, SelfTest,
+    },
     programs::BuildRequest,
 };
-use tokio::sync::{RwLock, Semaphore};
+use tokio::sync::RwLock;
 use uuid::Uuid;
 
-use super::Tags;
+use super::{auth::ApiAuth, queue::Overloaded, Tags};
 use crate::{
     config::Config,
     environments::{self, Environments},
     metrics::MetricsData,
-    program::{build::build_program, run::run_program},
+    program::{
+        build::build_program,
+        cache::CacheLock,
+        now,
+        run::run_program,
+        tenant::{StorageUsage, Tenant},
+    },
+    queue::{Demand, JobQueue, Pool, Resources},
+    selftest::{self, run_selftest, SelfTests},
 };
 
 pub struct EnvironmentsApi {
     pub environments: Arc<Environments>,
-    pub request_semaphore: Arc<Semaphore>,
+    pub selftests: Arc<SelfTests>,
+    pub storage_usage: Arc<StorageUsage>,
+    pub job_queue: Arc<JobQueue>,
     pub config: Arc<Config>,
     pub program_lock: Arc<KeyRwLock<Uuid>>,
     pub job_lock: Arc<KeyRwLock<Uuid>>,
+    pub cache_lock: Arc<CacheLock>,
     pub base_resource_usage_cache: Arc<BaseResourceUsageCache>,
 }
 
@@ -36,11 +50,16 @@ impl EnvironmentsApi {
     /// The keys represent the environment ids and the values contain additional
     /// information about the environments.
     #[oai(path = "/environments", method = "get")]
-    async fn list_environments(&self, metrics: MetricsData<'_>) -> ListEnvironments::Response {
+    async fn list_environments(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+    ) -> ListEnvironments::Response {
         metrics.0.requests.environments.inc();
         ListEnvironments::ok(ListEnvironmentsResponse(
             self.environments
                 .iter()
+                .filter(|(id, _)| auth.0.can_use_environment(id))
                 .map(|(id, env)| {
                     (
                         id.clone(),
@@ -50,6 +69,22 @@ impl EnvironmentsApi {
                             default_main_file_name: env.default_main_file_name.clone(),
                             example: env.example.clone(),
                             meta: env.meta.clone(),
+                            packages: env
+                                .packages
+                                .iter()
+                                .map(|(name, package)| {
+                                    (
+                                        name.clone(),
+                                        EnvironmentPackage {
+                                            version: package.version.clone(),
+                                            description: package.description.clone(),
+                                        },
+                                    )
+                                })
+                                .collect(),
+                            build_options: env.build_options.clone(),
+                            run_options: env.run_options.clone(),
+                            selftest: self.selftests.get(id),
                         },
                     )
                 })
@@ -63,14 +98,11 @@ impl EnvironmentsApi {
     /// very simple program in this environment that barely does anything. Note
     /// that the compile step is run only once as recompiling the same program
     /// again and again would take too much time in most cases.
-    #[oai(
-        path = "/environments/:name/resource_usage",
-        method = "get",
-        transform = "shield"
-    )]
+    #[oai(path = "/environments/:name/resource_usage", method = "get")]
     async fn get_base_resource_usage(
         &self,
         metrics: MetricsData<'_>,
+        auth: ApiAuth,
         name: Path<String>,
     ) -> GetBaseResourceUsage::Response {
         metrics
@@ -80,7 +112,11 @@ impl EnvironmentsApi {
             .with_label_values(&[&name.0])
             .inc();
 
-        let Some(environment) = self.environments.get(&name.0) else {
+        let Some(environment) = self
+            .environments
+            .get(&name.0)
+            .filter(|_| auth.0.can_use_environment(&name.0))
+        else {
             return GetBaseResourceUsage::environment_not_found();
         };
 
@@ -110,18 +146,30 @@ impl EnvironmentsApi {
             return GetBaseResourceUsage::ok(result);
         }
 
-        let _guard = self
-            .request_semaphore
-            .acquire_many(self.config.base_resource_usage_permits)
-            .await?;
+        let demand = Demand {
+            pool: Pool::Run,
+            priority: auth.0.priority,
+            resources: Resources {
+                jobs: self.config.base_resource_usage_permits as _,
+                ..Resources::job(&self.config.compile_limits)
+                    .max(Resources::job(&self.config.run_limits))
+            },
+        };
+        let reservation = match self.job_queue.acquire(metrics.0, demand).await {
+            Ok(reservation) => reservation,
+            Err(err) => return Ok(Overloaded::from(err).into()),
+        };
 
         let result = get_base_resource_usage(
             Arc::clone(&self.config),
             Arc::clone(&self.environments),
+            &self.storage_usage,
             Arc::clone(&self.program_lock),
             Arc::clone(&self.job_lock),
+            Arc::clone(&self.cache_lock),
             &name.0,
             environment,
+            reservation.cores(),
         )
         .await?;
 
@@ -129,6 +177,54 @@ impl EnvironmentsApi {
 
         GetBaseResourceUsage::ok(result)
     }
+
+    /// Run the self-test of an environment.
+    ///
+    /// Builds and runs the test program of the environment and checks whether
+    /// it produces the expected output. If the self-test fails, the environment
+    /// is marked as unhealthy and builds are rejected until it passes a
+    /// subsequent self-test. Concurrent requests for the same environment share
+    /// a single self-test. This endpoint requires admin permissions.
+    #[oai(path = "/environments/:name/selftest", method = "post")]
+    async fn selftest(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+        name: Path<String>,
+    ) -> RunSelfTest::Response {
+        if !auth.0.admin {
+            return RunSelfTest::forbidden();
+        }
+        let _guard = match self.selftests.lock(&name.0, now()).await {
+            Some(Ok(guard)) => guard,
+            Some(Err(result)) => return RunSelfTest::ok(result),
+            None => return RunSelfTest::environment_not_found(),
+        };
+
+        let reservation = match self
+            .job_queue
+            .acquire(metrics.0, selftest::demand(&self.config, auth.0.priority))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => return Ok(Overloaded::from(err).into()),
+        };
+
+        let result = run_selftest(
+            Arc::clone(&self.config),
+            Arc::clone(&self.environments),
+            &self.storage_usage,
+            Arc::clone(&self.program_lock),
+            Arc::clone(&self.job_lock),
+            Arc::clone(&self.cache_lock),
+            &name.0,
+            reservation.cores(),
+        )
+        .await;
+        self.selftests.set(&name.0, result.clone());
+
+        RunSelfTest::ok(result)
+    }
 }
 
 response!(ListEnvironments = {
@@ -141,16 +237,31 @@ response!(GetBaseResourceUsage = {
     Ok(200) => BaseResourceUsage,
     /// Environment does not exist.
     EnvironmentNotFound(404, error),
+    ..Overloaded,
+});
+
+response!(RunSelfTest = {
+    /// The result of the self-test.
+    Ok(200) => SelfTest,
+    /// The api key is not allowed to run self-tests.
+    Forbidden(403, error),
+    /// Environment does not exist.
+    EnvironmentNotFound(404, error),
+    ..Overloaded,
 });
 
 /// Measure the base resource usage of a given environment.
+#[allow(clippy::too_many_arguments)]
 async fn get_base_resource_usage(
     config: Arc<Config>,
     environments: Arc<Environments>,
+    storage_usage: &StorageUsage,
     program_lock: Arc<KeyRwLock<Uuid>>,
     job_lock: Arc<KeyRwLock<Uuid>>,
+    cache_lock: Arc<CacheLock>,
     environment_id: &str,
     environment: &environments::Environment,
+    cores: Option<&[usize]>,
 ) -> Result<BaseResourceUsage, ErrorResponse> {
     // compile the program once
     let (build, _guard) = build_program(
@@ -162,8 +273,12 @@ async fn get_base_resource_usage(
             files: environment.test.files.clone(),
             ..Default::default()
         },
+        Tenant::default(),
+        storage_usage,
         program_lock,
         Arc::clone(&job_lock),
+        cache_lock,
+        cores,
     )
     .await?;
 
@@ -175,8 +290,10 @@ async fn get_base_resource_usage(
                 Arc::clone(&config),
                 build.program_id,
                 Default::default(),
+                Tenant::default(),
                 &_guard,
                 Arc::clone(&job_lock),
+                cores,
             )
             .await?
             .resource_usage,
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use poem_ext::response;
+use poem_openapi::OpenApi;
+use sandkasten_client::schemas::health::{Liveness, Readiness};
+
+use super::Tags;
+use crate::{
+    config::Config, environments::Environments, health::check_readiness, queue::JobQueue,
+    selftest::SelfTests, VERSION,
+};
+
+pub struct HealthApi {
+    pub config: Arc<Config>,
+    pub environments: Arc<Environments>,
+    pub selftests: Arc<SelfTests>,
+    pub job_queue: Arc<JobQueue>,
+}
+
+#[OpenApi(tag = "Tags::Health")]
+impl HealthApi {
+    /// Check whether Sandkasten is running.
+    #[oai(path = "/health/live", method = "get")]
+    async fn live(&self) -> Live::Response {
+        Live::ok(Liveness {
+            version: VERSION.into(),
+        })
+    }
+
+    /// Check whether Sandkasten is able to execute code.
+    ///
+    /// Verifies that nsjail and time are executable, that the programs and
+    /// jobs directories are writable and have enough free space, that cgroup v2
+    /// is usable (if enabled), that at least one environment has passed its
+    /// self-test and that drain mode is disabled.
+    #[oai(path = "/health/ready", method = "get")]
+    async fn ready(&self) -> Ready::Response {
+        let config = Arc::clone(&self.config);
+        let environments = Arc::clone(&self.environments);
+        let selftests = Arc::clone(&self.selftests);
+        let job_queue = Arc::clone(&self.job_queue);
+        let checks = tokio::task::spawn_blocking(move || {
+            check_readiness(&config, &environments, &selftests, &job_queue)
+        })
+        .await?;
+
+        let ready = checks.iter().all(|check| check.healthy);
+        let readiness = Readiness { ready, checks };
+        if ready {
+            Ready::ok(readiness)
+        } else {
+            Ready::not_ready(readiness)
+        }
+    }
+}
+
+response!(Live = {
+    /// Sandkasten is running.
+    Ok(200) => Liveness,
+});
+
+response!(Ready = {
+    /// Sandkasten is ready to execute code.
+    Ok(200) => Readiness,
+    /// At least one of the checks has failed.
+    NotReady(503) => Readiness,
+});
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use poem::IntoResponse;
+use poem_ext::response;
+use poem_openapi::{param::Path, payload::Json, types::ToJSON, OpenApi};
+use sandkasten_client::schemas::{
+    jobs::{CreateJobRequest, Job, JobError, JobStatus},
+    programs::BuildRunResult,
+};
+use tokio::task::AbortHandle;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use super::{
+    auth::ApiAuth,
+    programs::{BuildRun, ProgramsApi},
+    queue::Overloaded,
+    rate_limit::RateLimited,
+    Tags,
+};
+use crate::{config::Config, metrics::MetricsData, program::now, sandbox::cancellable, webhook};
+
+/// The asynchronous jobs that are queued, running or have finished recently.
+pub type Jobs = Mutex<HashMap<Uuid, JobEntry>>;
+
+pub struct JobEntry {
+    job: Job,
+    /// The name of the tenant that has submitted the job (see
+    /// [`Tenant`](crate::program::tenant::Tenant)). Jobs of other tenants
+    /// cannot be accessed.
+    tenant: Option<String>,
+    /// Handle of the task that executes the job. Aborting the task kills all
+    /// processes that have been started by the job.
+    task: Option<AbortHandle>,
+    /// Cancelling this token kills the processes of a running job, which then
+    /// finishes with the results of the killed processes.
+    cancellation: CancellationToken,
+    callback_url: Option<String>,
+}
+
+pub struct JobsApi {
+    pub config: Arc<Config>,
+    pub programs: ProgramsApi,
+    pub jobs: Arc<Jobs>,
+    /// The http client used to deliver webhooks.
+    pub http: reqwest::Client,
+}
+
+#[OpenApi(tag = "Tags::Jobs")]
+impl JobsApi {
+    /// Submit an asynchronous job that builds and runs a program.
+    ///
+    /// Returns immediately with the id of the job, which can be used to poll
+    /// the status and the results of the job. If a callback url is specified,
+    /// the final job is also posted to this url as soon as the job has
+    /// finished or has been cancelled. The hex encoded HMAC-SHA256 signature
+    /// of the request body is sent in the `X-Sandkasten-Signature` header
+    /// (prefixed with `sha256=`). Failed deliveries are retried with an
+    /// exponential backoff.
+    #[oai(path = "/jobs", method = "post")]
+    async fn create_job(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+        data: Json<CreateJobRequest>,
+    ) -> CreateJob::Response {
+        if let Err(response) = self.programs.check_budget(metrics.0, &auth.0) {
+            return Ok(response.into());
+        }
+
+        metrics.0.requests.create_job.inc();
+
+        let CreateJobRequest {
+            request,
+            callback_url,
+        } = data.0;
+        if let Some(url) = &callback_url {
+            if !webhook::is_allowed_url(&self.config.webhook_allowed_urls, url) {
+                return CreateJob::callback_url_not_allowed();
+            }
+        }
+        if let Err(err) = self.programs.job_queue.check_draining(metrics.0) {
+            return Ok(Overloaded::from(err).into());
+        }
+
+        let job = Job {
+            id: Uuid::new_v4(),
+            status: JobStatus::Queued,
+            created_at: now(),
+            started_at: None,
+            finished_at: None,
+            ttl: self.config.job_ttl,
+            result: None,
+            error: None,
+        };
+        let id = job.id;
+        let cancellation = CancellationToken::new();
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            prune_jobs(&mut jobs, self.config.job_ttl);
+            jobs.insert(
+                id,
+                JobEntry {
+                    job: job.clone(),
+                    tenant: auth.0.name.clone(),
+                    task: None,
+                    cancellation: cancellation.clone(),
+                    callback_url: callback_url.clone(),
+                },
+            );
+        }
+
+        let programs = self.programs.clone();
+        let jobs = Arc::clone(&self.jobs);
+        let metrics = Arc::clone(metrics.0);
+        let config = Arc::clone(&self.config);
+        let http = self.http.clone();
+        let task = tokio::spawn(async move {
+            let result = cancellable(
+                cancellation,
+                programs.build_and_run(&metrics, &auth.0, request, || {
+                    update_job(&jobs, id, |job| {
+                        job.status = JobStatus::Running;
+                        job.started_at = Some(now());
+                    });
+                }),
+            )
+            .await;
+            let (result, error) = match result {
+                Ok(result) => (Some(result), None),
+                Err(response) => (None, Some(job_error(response).await)),
+            };
+            let job = finish_job(&jobs, id, result, error);
+            if let (Some(job), Some(url)) = (job, callback_url) {
+                send_webhook(config, http, url, &job);
+            }
+        });
+        // the job might have been cancelled (or aborted because of a shutdown)
+        // while it was still queued, before the handle of its task could be
+        // stored (jobs that are cancelled while running are stopped using
+        // their cancellation token instead)
+        if let Some(entry) = self.jobs.lock().unwrap().get_mut(&id) {
+            if entry.job.status == JobStatus::Cancelled && entry.job.started_at.is_none() {
+                task.abort();
+            } else {
+                entry.task = Some(task.abort_handle());
+            }
+        }
+
+        CreateJob::accepted(job)
+    }
+
+    /// Return the status and the results of an asynchronous job.
+    #[oai(path = "/jobs/:job_id", method = "get")]
+    async fn get_job(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+        job_id: Path<Uuid>,
+    ) -> GetJob::Response {
+        metrics.0.requests.get_job.inc();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        prune_jobs(&mut jobs, self.config.job_ttl);
+        match jobs
+            .get(&job_id.0)
+            .filter(|entry| entry.tenant == auth.0.name)
+        {
+            Some(entry) => GetJob::ok(entry.job.clone()),
+            None => GetJob::job_not_found(),
+        }
+    }
+
+    /// Cancel an asynchronous job.
+    ///
+    /// All processes that have been started by the job are killed. The results
+    /// of a running job are still stored (and delivered to its callback url)
+    /// as soon as its processes have been killed, with the `cancelled`
+    /// termination reason set on the killed processes. Jobs that have already
+    /// finished are not affected.
+    #[oai(path = "/jobs/:job_id", method = "delete")]
+    async fn cancel_job(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+        job_id: Path<Uuid>,
+    ) -> CancelJob::Response {
+        metrics.0.requests.cancel_job.inc();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        prune_jobs(&mut jobs, self.config.job_ttl);
+        let Some(entry) = jobs
+            .get_mut(&job_id.0)
+            .filter(|entry| entry.tenant == auth.0.name)
+        else {
+            return CancelJob::job_not_found();
+        };
+        match entry.job.status {
+            JobStatus::Queued => {
+                // the task might still be running until it reaches its next
+                // await point, but it does not update cancelled jobs anymore
+                if let Some(task) = entry.task.take() {
+                    task.abort();
+                }
+                entry.job.status = JobStatus::Cancelled;
+                entry.job.finished_at = Some(now());
+                if let Some(url) = entry.callback_url.clone() {
+                    send_webhook(Arc::clone(&self.config), self.http.clone(), url, &entry.job);
+                }
+            }
+            JobStatus::Running => {
+                // the task stores the results and delivers the webhook
+                entry.cancellation.cancel();
+                entry.job.status = JobStatus::Cancelled;
+                entry.job.finished_at = Some(now());
+            }
+            JobStatus::Finished | JobStatus::Cancelled => {}
+        }
+        CancelJob::ok(entry.job.clone())
+    }
+}
+
+/// Abort all asynchronous jobs that are still queued or running, which kills
+/// all processes that have been started by these jobs.
+pub fn abort_jobs(jobs: &Jobs) {
+    for entry in jobs.lock().unwrap().values_mut() {
+        if matches!(entry.job.status, JobStatus::Queued | JobStatus::Running) {
+            // the token also stops jobs whose task handle has not been stored yet
+            entry.cancellation.cancel();
+            if let Some(task) = entry.task.take() {
+                task.abort();
+            }
+            entry.job.status = JobStatus::Cancelled;
+            entry.job.finished_at = Some(now());
+        }
+    }
+}
+
+/// Remove all jobs that have finished more than `ttl` seconds ago.
+fn prune_jobs(jobs: &mut HashMap<Uuid, JobEntry>, ttl: u64) {
+    let now = now();
+    jobs.retain(|_, entry| !matches!(entry.job.finished_at, Some(t) if t + ttl <= now));
+}
+
+/// Update a job unless it has been cancelled in the meantime and return the
+/// updated job.
+fn update_job(jobs: &Jobs, id: Uuid, f: impl FnOnce(&mut Job)) -> Option<Job> {
+    let mut jobs = jobs.lock().unwrap();
+    let entry = jobs.get_mut(&id)?;
+    if entry.job.status == JobStatus::Cancelled {
+        return None;
+    }
+    f(&mut entry.job);
+    Some(entry.job.clone())
+}
+
+/// Store the results of a job and return the updated job. Jobs that have been
+/// cancelled while running keep their status, but still receive the results of
+/// their killed processes.
+fn finish_job(
+    jobs: &Jobs,
+    id: Uuid,
+    result: Option<BuildRunResult>,
+    error: Option<JobError>,
+) -> Option<Job> {
+    let mut jobs = jobs.lock().unwrap();
+    let entry = jobs.get_mut(&id)?;
+    if entry.job.status != JobStatus::Cancelled {
+        entry.job.status = JobStatus::Finished;
+        entry.job.finished_at = Some(now());
+    }
+    entry.job.result = result;
+    entry.job.error = error;
+    Some(entry.job.clone())
+}
+
+/// Deliver the final state of a job to its callback url in the background.
+fn send_webhook(config: Arc<Config>, http: reqwest::Client, url: String, job: &Job) {
+    let body = job.to_json_string();
+    tokio::spawn(async move { webhook::deliver(&http, &config, &url, body).await });
+}
+
+/// Extract the status code and the body of an error response.
+async fn job_error(response: BuildRun::Response) -> JobError {
+    let response = match response {
+        Ok(response) => response.into_response(),
+        Err(err) => err.into_response(),
+    };
+    JobError {
+        status: response.status().as_u16(),
+        response: response.into_body().into_json().await.unwrap_or_default(),
+    }
+}
+
+response!(CreateJob = {
+    /// The job has been submitted.
+    Accepted(202) => Job,
+    /// The callback url does not match any of the allowed webhook urls.
+    CallbackUrlNotAllowed(403, error),
+    ..RateLimited,
+    ..Overloaded,
+});
+
+response!(GetJob = {
+    /// The status and results of the job.
+    Ok(200) => Job,
+    /// Job does not exist.
+    JobNotFound(404, error),
+});
+
+response!(CancelJob = {
+    /// The job has been cancelled (or had already finished).
+    Ok(200) => Job,
+    /// Job does not exist.
+    JobNotFound(404, error),
+});
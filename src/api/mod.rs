@@ -2,53 +2,103 @@ use std::sync::Arc;
 
 use key_rwlock::KeyRwLock;
 use poem_openapi::OpenApi;
-use tokio::sync::Semaphore;
 use uuid::Uuid;
 
-use self::{configuration::ConfigurationApi, environments::EnvironmentsApi, programs::ProgramsApi};
-use crate::{config::Config, environments::Environments};
+use self::{
+    admin::AdminApi, configuration::ConfigurationApi, environments::EnvironmentsApi,
+    health::HealthApi, jobs::JobsApi, programs::ProgramsApi,
+};
+use crate::{
+    config::Config,
+    environments::Environments,
+    program::{cache::CacheLock, tenant::StorageUsage},
+    queue::JobQueue,
+    rate_limit::RateLimiter,
+    selftest::SelfTests,
+    webhook,
+};
 
+pub use self::jobs::{abort_jobs, Jobs};
+
+mod admin;
+mod auth;
 mod configuration;
 mod environments;
+mod health;
+mod jobs;
 mod programs;
+mod queue;
+mod rate_limit;
 
 #[derive(poem_openapi::Tags)]
 enum Tags {
     Configuration,
     Environments,
     Programs,
+    Jobs,
+    Admin,
+    Health,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn get_api(
     config: Arc<Config>,
     environments: Arc<Environments>,
     program_lock: Arc<KeyRwLock<Uuid>>,
     job_lock: Arc<KeyRwLock<Uuid>>,
+    cache_lock: Arc<CacheLock>,
+    rate_limiter: Arc<RateLimiter>,
+    job_queue: Arc<JobQueue>,
+    selftests: Arc<SelfTests>,
+    storage_usage: Arc<StorageUsage>,
+    jobs: Arc<Jobs>,
 ) -> impl OpenApi {
-    let request_semaphore = Arc::new(Semaphore::new(config.max_concurrent_jobs));
+    let programs = ProgramsApi {
+        job_queue: Arc::clone(&job_queue),
+        program_lock: Arc::clone(&program_lock),
+        job_lock: Arc::clone(&job_lock),
+        cache_lock: Arc::clone(&cache_lock),
+        config: Arc::clone(&config),
+        environments: Arc::clone(&environments),
+        selftests: Arc::clone(&selftests),
+        storage_usage: Arc::clone(&storage_usage),
+        rate_limiter,
+    };
     (
         ConfigurationApi {
             config: Arc::clone(&config),
         },
         EnvironmentsApi {
             environments: Arc::clone(&environments),
-            request_semaphore: Arc::clone(&request_semaphore),
+            selftests: Arc::clone(&selftests),
+            storage_usage,
+            job_queue: Arc::clone(&job_queue),
             program_lock: Arc::clone(&program_lock),
             job_lock: Arc::clone(&job_lock),
+            cache_lock: Arc::clone(&cache_lock),
             config: Arc::clone(&config),
             base_resource_usage_cache: Arc::new(
                 environments
-                    .iter()
-                    .map(|(id, _)| (id.clone(), Default::default()))
+                    .keys()
+                    .map(|id| (id.clone(), Default::default()))
                     .collect(),
             ),
         },
-        ProgramsApi {
-            request_semaphore,
-            program_lock,
-            job_lock,
+        programs.clone(),
+        JobsApi {
+            config: Arc::clone(&config),
+            programs,
+            jobs,
+            http: webhook::client(&config),
+        },
+        AdminApi {
+            job_queue: Arc::clone(&job_queue),
+        },
+        HealthApi {
             config,
             environments,
+            selftests,
+            job_queue,
         },
         #[cfg(feature = "test_api")]
         test_api::TestApi,
@@ -1,188 +1,334 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{collections::BTreeMap, sync::Arc, time::Instant};
 
 use key_rwlock::KeyRwLock;
-use poem_ext::{response, shield_mw::shield};
-use poem_openapi::{param::Path, payload::Json, OpenApi};
+use poem::{middleware::SizeLimit, Body, Endpoint, EndpointExt, Middleware};
+use poem_ext::response;
+use poem_openapi::{
+    param::Path,
+    payload::{Attachment, AttachmentType, Json},
+    types::multipart::{JsonField, Upload},
+    ApiRequest, ApiResponse, Multipart, OpenApi,
+};
 use sandkasten_client::schemas::programs::{
-    BuildRequest, BuildResult, BuildRunRequest, BuildRunResult, EnvVar, File, LimitExceeded,
-    MainFile, RunRequest, RunResult,
+    BuildRequest, BuildResult, BuildRunRequest, BuildRunResult, CheckResult, CompileError, EnvVar,
+    File, FormatRequest, FormatResult, LimitExceeded, LimitsOpt, MainFile, OptionValue,
+    ResourceUsage, RunRequest, RunResult, TestRequest, TestResult,
 };
-use tokio::sync::Semaphore;
 use uuid::Uuid;
 
-use super::Tags;
+use super::{auth::ApiAuth, queue::Overloaded, rate_limit::RateLimited, Tags};
 use crate::{
+    auth::Caller,
     config::Config,
     environments::Environments,
-    metrics::MetricsData,
+    metrics::{Metrics, MetricsData},
     program::{
+        archive::extract_archive,
         build::{build_program, BuildProgramError},
+        cache::CacheLock,
+        check::{check_program, CheckProgramError},
+        check_path, check_paths,
+        files::{archive_program_files, ArchiveFilesError},
+        format::{format_program, FormatProgramError},
+        normalize_path,
+        packages::SelectPackagesError,
         run::{run_program, RunProgramError},
+        tenant::StorageUsage,
+        test::{test_program, TestProgramError},
     },
+    queue::{Demand, JobQueue},
+    rate_limit::RateLimiter,
+    selftest::SelfTests,
 };
 
+#[derive(Clone)]
 pub struct ProgramsApi {
     pub config: Arc<Config>,
     pub environments: Arc<Environments>,
+    pub selftests: Arc<SelfTests>,
+    pub storage_usage: Arc<StorageUsage>,
     pub program_lock: Arc<KeyRwLock<Uuid>>,
     pub job_lock: Arc<KeyRwLock<Uuid>>,
-    pub request_semaphore: Arc<Semaphore>,
+    pub cache_lock: Arc<CacheLock>,
+    pub job_queue: Arc<JobQueue>,
+    pub rate_limiter: Arc<RateLimiter>,
 }
 
 #[OpenApi(tag = "Tags::Programs")]
 impl ProgramsApi {
     /// Build and immediately run a program.
-    #[oai(path = "/run", method = "post", transform = "shield")]
+    #[oai(path = "/run", method = "post")]
     async fn run(
         &self,
         metrics: MetricsData<'_>,
+        auth: ApiAuth,
         data: Json<BuildRunRequest>,
     ) -> BuildRun::Response {
-        let environment = data.0.build.environment.clone();
+        match self.build_and_run(metrics.0, &auth.0, data.0, || ()).await {
+            Ok(result) => BuildRun::ok(result),
+            Err(response) => response,
+        }
+    }
+
+    /// Upload and compile a program.
+    ///
+    /// The source files can either be specified in a json request or uploaded
+    /// as a tar, tar.gz or zip archive using a multipart request. Multipart
+    /// requests must specify their `Content-Length`.
+    #[oai(path = "/programs", method = "post", transform = "limit_archive_size")]
+    async fn build_program(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+        data: BuildProgramRequest,
+    ) -> Build::Response {
+        // archives are only extracted if the caller has not exhausted its
+        // budgets yet
+        if let Err(response) = self.check_budget(metrics.0, &auth.0) {
+            return Ok(response.into());
+        }
+
+        let mut data = match data {
+            BuildProgramRequest::Json(data) => data,
+            BuildProgramRequest::Archive(data) => match self.extract_build_request(data).await {
+                Ok(data) => Json(data),
+                Err(response) => return response,
+            },
+        };
+
+        let environment = data.0.environment.clone();
         metrics
             .0
             .requests
-            .build_run
+            .build
             .with_label_values(&[&environment])
             .inc();
 
-        if !check_mainfile(&data.0.build.main_file)
-            || !check_files(&data.0.build.files)
-            || !check_files(&data.0.run.files)
-        {
-            return BuildRun::invalid_file_names();
+        if !check_mainfile(&data.0.main_file) || !check_files(&data.0.files) {
+            return Build::invalid_file_names();
+        }
+        if !check_env_vars(&data.0.env_vars) {
+            return Build::invalid_env_vars();
+        }
+        if !auth.0.can_use_environment(&environment) {
+            return Build::environment_not_found();
         }
-        if !check_env_vars(&data.0.build.env_vars) || !check_env_vars(&data.0.run.env_vars) {
-            return BuildRun::invalid_env_vars();
+        if let Some(message) = self.selftests.failure(&environment) {
+            return Build::environment_unhealthy(message);
         }
+        let limits = match auth.0.check_compile_limits(&mut data.0.compile_limits) {
+            Ok(limits) => limits,
+            Err(lim) => return Build::compile_limits_exceeded(lim),
+        };
 
-        let _guard = self.request_semaphore.acquire().await?;
+        let reservation = match self
+            .job_queue
+            .acquire(metrics.0, Demand::compile(auth.0.priority, &limits))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => return Ok(Overloaded::from(err).into()),
+        };
 
-        let (
-            BuildResult {
-                program_id,
-                ttl,
-                cached,
-                compile_result,
-            },
-            read_guard,
-        ) = match build_program(
+        match build_program(
             Arc::clone(&self.config),
             Arc::clone(&self.environments),
-            data.0.build,
+            data.0,
+            auth.0.tenant(),
+            &self.storage_usage,
             Arc::clone(&self.program_lock),
             Arc::clone(&self.job_lock),
+            Arc::clone(&self.cache_lock),
+            reservation.cores(),
         )
         .await
         {
-            Ok(result) => result,
-            Err(BuildProgramError::EnvironmentNotFound(_)) => {
-                return BuildRun::environment_not_found()
+            Ok((result, _)) => {
+                if result.cached {
+                    metrics
+                        .0
+                        .cache_hits
+                        .build
+                        .with_label_values(&[&environment])
+                        .inc();
+                } else if let Some(compile_result) = &result.compile_result {
+                    self.record_usage(metrics.0, &auth.0, &compile_result.resource_usage);
+                }
+                Build::ok(result)
             }
+            Err(BuildProgramError::EnvironmentNotFound(_)) => Build::environment_not_found(),
             Err(BuildProgramError::CompilationFailed(result)) => {
-                return BuildRun::compile_error(result)
+                if !result.cached {
+                    self.record_usage(metrics.0, &auth.0, &result.result.resource_usage);
+                }
+                Build::compile_error(result)
             }
-            Err(BuildProgramError::ConflictingFilenames) => return BuildRun::invalid_file_names(),
-            Err(BuildProgramError::LimitsExceeded(lim)) => {
-                return BuildRun::compile_limits_exceeded(lim)
+            Err(
+                BuildProgramError::InvalidFileName(_) | BuildProgramError::ConflictingFilenames,
+            ) => Build::invalid_file_names(),
+            Err(BuildProgramError::InvalidOptions(err)) => Build::invalid_options(err.to_string()),
+            Err(BuildProgramError::InvalidPackages(SelectPackagesError::UnknownPackage(name))) => {
+                Build::unknown_package(name)
             }
-            Err(err) => return Err(err.into()),
-        };
+            Err(BuildProgramError::LimitsExceeded(lim)) => Build::compile_limits_exceeded(lim),
+            Err(BuildProgramError::StorageQuotaExceeded(quota)) => {
+                Build::storage_quota_exceeded(quota)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
 
-        if cached {
-            metrics
-                .0
-                .cache_hits
-                .build_run
-                .with_label_values(&[&environment])
-                .inc();
+    /// Check a program without building or storing it.
+    ///
+    /// Runs the check script of the environment (e.g. a syntax check or a
+    /// linter) on the source files and returns its result.
+    #[oai(path = "/check", method = "post")]
+    async fn check_program(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+        mut data: Json<BuildRequest>,
+    ) -> Check::Response {
+        if let Err(response) = self.check_budget(metrics.0, &auth.0) {
+            return Ok(response.into());
         }
 
-        match run_program(
+        metrics
+            .0
+            .requests
+            .check
+            .with_label_values(&[&data.0.environment])
+            .inc();
+
+        if !check_mainfile(&data.0.main_file) || !check_files(&data.0.files) {
+            return Check::invalid_file_names();
+        }
+        if !check_env_vars(&data.0.env_vars) {
+            return Check::invalid_env_vars();
+        }
+        if !auth.0.can_use_environment(&data.0.environment) {
+            return Check::environment_not_found();
+        }
+        let limits = match auth.0.check_compile_limits(&mut data.0.compile_limits) {
+            Ok(limits) => limits,
+            Err(lim) => return Check::compile_limits_exceeded(lim),
+        };
+
+        let reservation = match self
+            .job_queue
+            .acquire(metrics.0, Demand::compile(auth.0.priority, &limits))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => return Ok(Overloaded::from(err).into()),
+        };
+
+        match check_program(
             Arc::clone(&self.config),
-            program_id,
-            data.0.run,
-            &read_guard,
+            Arc::clone(&self.environments),
+            data.0,
             Arc::clone(&self.job_lock),
+            reservation.cores(),
         )
         .await
         {
-            Ok(run_result) => BuildRun::ok(BuildRunResult {
-                program_id,
-                ttl,
-                cached,
-                build: compile_result,
-                run: run_result,
-            }),
-            Err(RunProgramError::LimitsExceeded(lim)) => BuildRun::run_limits_exceeded(lim),
+            Ok(result) => {
+                self.record_usage(metrics.0, &auth.0, &result.result.resource_usage);
+                Check::ok(result)
+            }
+            Err(CheckProgramError::EnvironmentNotFound(_)) => Check::environment_not_found(),
+            Err(CheckProgramError::CheckNotSupported) => Check::check_not_supported(),
+            Err(
+                CheckProgramError::InvalidFileName(_) | CheckProgramError::ConflictingFilenames,
+            ) => Check::invalid_file_names(),
+            Err(CheckProgramError::InvalidOptions(err)) => Check::invalid_options(err.to_string()),
+            Err(CheckProgramError::InvalidPackages(SelectPackagesError::UnknownPackage(name))) => {
+                Check::unknown_package(name)
+            }
+            Err(CheckProgramError::LimitsExceeded(lim)) => Check::compile_limits_exceeded(lim),
             Err(err) => Err(err.into()),
         }
     }
 
-    /// Upload and compile a program.
-    #[oai(path = "/programs", method = "post", transform = "shield")]
-    async fn build_program(
+    /// Format source files using the formatter of an environment.
+    #[oai(path = "/format", method = "post")]
+    async fn format_program(
         &self,
         metrics: MetricsData<'_>,
-        data: Json<BuildRequest>,
-    ) -> Build::Response {
-        let environment = data.0.environment.clone();
+        auth: ApiAuth,
+        mut data: Json<FormatRequest>,
+    ) -> Format::Response {
+        if let Err(response) = self.check_budget(metrics.0, &auth.0) {
+            return Ok(response.into());
+        }
+
         metrics
             .0
             .requests
-            .build
-            .with_label_values(&[&environment])
+            .format
+            .with_label_values(&[&data.0.environment])
             .inc();
 
         if !check_mainfile(&data.0.main_file) || !check_files(&data.0.files) {
-            return Build::invalid_file_names();
+            return Format::invalid_file_names();
         }
-        if !check_env_vars(&data.0.env_vars) {
-            return Build::invalid_env_vars();
+        if !auth.0.can_use_environment(&data.0.environment) {
+            return Format::environment_not_found();
         }
+        let limits = match auth.0.check_compile_limits(&mut data.0.limits) {
+            Ok(limits) => limits,
+            Err(lim) => return Format::limits_exceeded(lim),
+        };
 
-        let _guard = self.request_semaphore.acquire().await?;
+        let reservation = match self
+            .job_queue
+            .acquire(metrics.0, Demand::compile(auth.0.priority, &limits))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => return Ok(Overloaded::from(err).into()),
+        };
 
-        match build_program(
+        match format_program(
             Arc::clone(&self.config),
             Arc::clone(&self.environments),
             data.0,
-            Arc::clone(&self.program_lock),
             Arc::clone(&self.job_lock),
+            reservation.cores(),
         )
         .await
         {
-            Ok((result, _)) => {
-                if result.cached {
-                    metrics
-                        .0
-                        .cache_hits
-                        .build
-                        .with_label_values(&[&environment])
-                        .inc();
-                }
-                Build::ok(result)
+            Ok((result, resource_usage)) => {
+                self.record_usage(metrics.0, &auth.0, &resource_usage);
+                Format::ok(result)
             }
-            Err(BuildProgramError::EnvironmentNotFound(_)) => Build::environment_not_found(),
-            Err(BuildProgramError::CompilationFailed(result)) => Build::compile_error(result),
-            Err(BuildProgramError::ConflictingFilenames) => Build::invalid_file_names(),
-            Err(BuildProgramError::LimitsExceeded(lim)) => Build::compile_limits_exceeded(lim),
+            Err(FormatProgramError::EnvironmentNotFound(_)) => Format::environment_not_found(),
+            Err(FormatProgramError::FormatNotSupported) => Format::format_not_supported(),
+            Err(FormatProgramError::FormatFailed(result)) => {
+                self.record_usage(metrics.0, &auth.0, &result.resource_usage);
+                Format::format_failed(result)
+            }
+            Err(
+                FormatProgramError::InvalidFileName(_) | FormatProgramError::ConflictingFilenames,
+            ) => Format::invalid_file_names(),
+            Err(FormatProgramError::LimitsExceeded(lim)) => Format::limits_exceeded(lim),
             Err(err) => Err(err.into()),
         }
     }
 
     /// Run a program that has previously been built.
-    #[oai(
-        path = "/programs/:program_id/run",
-        method = "post",
-        transform = "shield"
-    )]
+    #[oai(path = "/programs/:program_id/run", method = "post")]
     async fn run_program(
         &self,
         metrics: MetricsData<'_>,
+        auth: ApiAuth,
         program_id: Path<Uuid>,
-        data: Json<RunRequest>,
+        mut data: Json<RunRequest>,
     ) -> Run::Response {
+        if let Err(response) = self.check_budget(metrics.0, &auth.0) {
+            return Ok(response.into());
+        }
+
         metrics.0.requests.run.inc();
 
         if !check_files(&data.0.files) {
@@ -191,56 +337,527 @@ impl ProgramsApi {
         if !check_env_vars(&data.0.env_vars) {
             return Run::invalid_env_vars();
         }
+        let limits = match auth.0.check_run_limits(&mut data.0.run_limits) {
+            Ok(limits) => limits,
+            Err(lim) => return Run::run_limits_exceeded(lim),
+        };
 
-        let _guard = self.request_semaphore.acquire().await?;
+        let reservation = match self
+            .job_queue
+            .acquire(metrics.0, Demand::run(auth.0.priority, &limits))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => return Ok(Overloaded::from(err).into()),
+        };
 
         match run_program(
             Arc::clone(&self.config),
             program_id.0,
             data.0,
+            auth.0.tenant(),
             &self.program_lock.read(program_id.0).await,
             Arc::clone(&self.job_lock),
+            reservation.cores(),
         )
         .await
         {
-            Ok(result) => Run::ok(result),
+            Ok(result) => {
+                self.record_usage(metrics.0, &auth.0, &result.resource_usage);
+                Run::ok(result)
+            }
             Err(RunProgramError::ProgramNotFound) => Run::program_not_found(),
+            Err(RunProgramError::InvalidFileName(_) | RunProgramError::ConflictingFilenames) => {
+                Run::invalid_file_names()
+            }
+            Err(RunProgramError::InvalidOptions(err)) => Run::invalid_options(err.to_string()),
             Err(RunProgramError::LimitsExceeded(lim)) => Run::run_limits_exceeded(lim),
             Err(err) => Err(err.into()),
         }
     }
+
+    /// Run the tests of a program that has previously been built.
+    ///
+    /// The test script of the environment receives the test files from the
+    /// request and writes a JUnit XML or TAP report, which is parsed into a
+    /// list of test cases.
+    #[oai(path = "/programs/:program_id/test", method = "post")]
+    async fn test_program(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+        program_id: Path<Uuid>,
+        mut data: Json<TestRequest>,
+    ) -> Test::Response {
+        if let Err(response) = self.check_budget(metrics.0, &auth.0) {
+            return Ok(response.into());
+        }
+
+        metrics.0.requests.test.inc();
+
+        if !check_files(&data.0.files) {
+            return Test::invalid_file_names();
+        }
+        if !check_env_vars(&data.0.env_vars) {
+            return Test::invalid_env_vars();
+        }
+        let limits = match auth.0.check_run_limits(&mut data.0.run_limits) {
+            Ok(limits) => limits,
+            Err(lim) => return Test::run_limits_exceeded(lim),
+        };
+
+        let reservation = match self
+            .job_queue
+            .acquire(metrics.0, Demand::run(auth.0.priority, &limits))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => return Ok(Overloaded::from(err).into()),
+        };
+
+        match test_program(
+            Arc::clone(&self.config),
+            program_id.0,
+            data.0,
+            auth.0.tenant(),
+            &self.program_lock.read(program_id.0).await,
+            Arc::clone(&self.job_lock),
+            reservation.cores(),
+        )
+        .await
+        {
+            Ok(result) => {
+                self.record_usage(metrics.0, &auth.0, &result.result.resource_usage);
+                Test::ok(result)
+            }
+            Err(TestProgramError::ProgramNotFound) => Test::program_not_found(),
+            Err(TestProgramError::TestsNotSupported) => Test::tests_not_supported(),
+            Err(TestProgramError::InvalidFileName(_) | TestProgramError::ConflictingFilenames) => {
+                Test::invalid_file_names()
+            }
+            Err(TestProgramError::InvalidOptions(err)) => Test::invalid_options(err.to_string()),
+            Err(TestProgramError::LimitsExceeded(lim)) => Test::run_limits_exceeded(lim),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Download the files of a program that has previously been built.
+    ///
+    /// Returns a tar archive of everything the compile step has written into
+    /// the `/program` directory (or of the source files if the environment
+    /// does not have a compile step).
+    #[oai(path = "/programs/:program_id/files", method = "get")]
+    async fn download_files(
+        &self,
+        metrics: MetricsData<'_>,
+        auth: ApiAuth,
+        program_id: Path<Uuid>,
+    ) -> DownloadFiles::Response {
+        metrics.0.requests.files.inc();
+
+        match archive_program_files(
+            &self.config,
+            program_id.0,
+            auth.0.tenant(),
+            self.program_lock.read(program_id.0).await,
+        )
+        .await
+        {
+            Ok(archive) => Ok(FilesArchive::Ok(
+                Attachment::new(Body::from_async_read(archive))
+                    .attachment_type(AttachmentType::Attachment)
+                    .filename(format!("{}.tar", program_id.0)),
+            )
+            .into()),
+            Err(ArchiveFilesError::ProgramNotFound) => DownloadFiles::program_not_found(),
+            Err(ArchiveFilesError::TooLarge(max_size)) => DownloadFiles::files_too_large(max_size),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl ProgramsApi {
+    /// Reject the request if the caller has exhausted one of its resource
+    /// budgets.
+    pub(super) fn check_budget(
+        &self,
+        metrics: &Metrics,
+        caller: &Caller,
+    ) -> Result<(), RateLimited> {
+        self.rate_limiter
+            .check_budget(&self.config, &caller.client, Instant::now())
+            .map_err(|err| RateLimited::new(metrics, err))
+    }
+
+    /// Add the resources used by one of the caller's programs to its budgets.
+    fn record_usage(&self, metrics: &Metrics, caller: &Caller, usage: &ResourceUsage) {
+        let cpu_time = usage.time as f64 / 1000.0;
+        metrics.rate_limits.cpu_time.inc_by(cpu_time);
+        metrics
+            .rate_limits
+            .memory_time
+            .inc_by(usage.memory as f64 / 1024.0 * cpu_time);
+        self.rate_limiter
+            .record(&self.config, &caller.client, usage, Instant::now());
+    }
+
+    /// Convert a [`BuildArchiveRequest`] into a [`BuildRequest`] by extracting
+    /// the uploaded archive.
+    async fn extract_build_request(
+        &self,
+        data: BuildArchiveRequest,
+    ) -> Result<BuildRequest, Build::Response> {
+        let Some(env) = self.environments.get(&data.environment) else {
+            return Err(Build::environment_not_found());
+        };
+
+        if data.archive.size() as u64 > self.config.max_archive_size {
+            return Err(Build::invalid_archive(format!(
+                "archive is too large (max {} bytes)",
+                self.config.max_archive_size
+            )));
+        }
+        let archive = data
+            .archive
+            .into_vec()
+            .await
+            .map_err(|err| Err(err.into()))?;
+
+        let config = Arc::clone(&self.config);
+        let mut files = tokio::task::spawn_blocking(move || extract_archive(&archive, &config))
+            .await
+            .map_err(|err| Err(err.into()))?
+            .map_err(|err| Build::invalid_archive(err.to_string()))?;
+
+        let main_file_name = match data.main_file {
+            Some(name) => normalize_path(&name)
+                .ok_or_else(|| Build::invalid_archive(format!("invalid main file {name:?}")))?,
+            None => env.default_main_file_name.clone(),
+        };
+        let Some(main_file) = files.iter().position(|f| f.name == main_file_name) else {
+            return Err(Build::invalid_archive(format!(
+                "main file {main_file_name:?} not found in archive"
+            )));
+        };
+        let main_file = files.remove(main_file);
+
+        Ok(BuildRequest {
+            environment: data.environment,
+            main_file: MainFile {
+                name: Some(main_file.name),
+                content: main_file.content,
+            },
+            files,
+            env_vars: data.env_vars.map(|x| x.0).unwrap_or_default(),
+            compile_limits: data.compile_limits.map(|x| x.0).unwrap_or_default(),
+            options: data.options.map(|x| x.0).unwrap_or_default(),
+            packages: data.packages.map(|x| x.0).unwrap_or_default(),
+        })
+    }
+}
+
+impl ProgramsApi {
+    /// Build and immediately run a program. On failure, the error response of
+    /// the `/run` endpoint is returned. `started` is called as soon as the
+    /// request is no longer waiting for other jobs to finish.
+    pub(super) async fn build_and_run(
+        &self,
+        metrics: &Metrics,
+        caller: &Caller,
+        mut data: BuildRunRequest,
+        started: impl FnOnce(),
+    ) -> Result<BuildRunResult, BuildRun::Response> {
+        if let Err(response) = self.check_budget(metrics, caller) {
+            return Err(Ok(response.into()));
+        }
+
+        let environment = data.build.environment.clone();
+        metrics
+            .requests
+            .build_run
+            .with_label_values(&[&environment])
+            .inc();
+
+        if !check_mainfile(&data.build.main_file)
+            || !check_files(&data.build.files)
+            || !check_files(&data.run.files)
+        {
+            return Err(BuildRun::invalid_file_names());
+        }
+        if !check_env_vars(&data.build.env_vars) || !check_env_vars(&data.run.env_vars) {
+            return Err(BuildRun::invalid_env_vars());
+        }
+        if !caller.can_use_environment(&data.build.environment) {
+            return Err(BuildRun::environment_not_found());
+        }
+        if let Some(message) = self.selftests.failure(&data.build.environment) {
+            return Err(BuildRun::environment_unhealthy(message));
+        }
+        let compile_limits = match caller.check_compile_limits(&mut data.build.compile_limits) {
+            Ok(limits) => limits,
+            Err(lim) => return Err(BuildRun::compile_limits_exceeded(lim)),
+        };
+        let run_limits = match caller.check_run_limits(&mut data.run.run_limits) {
+            Ok(limits) => limits,
+            Err(lim) => return Err(BuildRun::run_limits_exceeded(lim)),
+        };
+
+        let reservation = match self
+            .job_queue
+            .acquire(metrics, Demand::compile(caller.priority, &compile_limits))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => return Err(Ok(Overloaded::from(err).into())),
+        };
+        started();
+
+        let (
+            BuildResult {
+                program_id,
+                ttl,
+                cached,
+                compile_result,
+                diagnostics,
+            },
+            read_guard,
+        ) = match build_program(
+            Arc::clone(&self.config),
+            Arc::clone(&self.environments),
+            data.build,
+            caller.tenant(),
+            &self.storage_usage,
+            Arc::clone(&self.program_lock),
+            Arc::clone(&self.job_lock),
+            Arc::clone(&self.cache_lock),
+            reservation.cores(),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(BuildProgramError::EnvironmentNotFound(_)) => {
+                return Err(BuildRun::environment_not_found())
+            }
+            Err(BuildProgramError::CompilationFailed(result)) => {
+                if !result.cached {
+                    self.record_usage(metrics, caller, &result.result.resource_usage);
+                }
+                return Err(BuildRun::compile_error(result));
+            }
+            Err(
+                BuildProgramError::InvalidFileName(_) | BuildProgramError::ConflictingFilenames,
+            ) => return Err(BuildRun::invalid_file_names()),
+            Err(BuildProgramError::InvalidOptions(err)) => {
+                return Err(BuildRun::invalid_options(err.to_string()))
+            }
+            Err(BuildProgramError::InvalidPackages(SelectPackagesError::UnknownPackage(name))) => {
+                return Err(BuildRun::unknown_package(name))
+            }
+            Err(BuildProgramError::LimitsExceeded(lim)) => {
+                return Err(BuildRun::compile_limits_exceeded(lim))
+            }
+            Err(BuildProgramError::StorageQuotaExceeded(quota)) => {
+                return Err(BuildRun::storage_quota_exceeded(quota))
+            }
+            Err(err) => return Err(Err(err.into())),
+        };
+
+        if cached {
+            metrics
+                .cache_hits
+                .build_run
+                .with_label_values(&[&environment])
+                .inc();
+        } else if let Some(compile_result) = &compile_result {
+            self.record_usage(metrics, caller, &compile_result.resource_usage);
+        }
+
+        drop(reservation);
+        let reservation = match self
+            .job_queue
+            .acquire(metrics, Demand::run(caller.priority, &run_limits))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => return Err(Ok(Overloaded::from(err).into())),
+        };
+
+        match run_program(
+            Arc::clone(&self.config),
+            program_id,
+            data.run,
+            caller.tenant(),
+            &read_guard,
+            Arc::clone(&self.job_lock),
+            reservation.cores(),
+        )
+        .await
+        {
+            Ok(run_result) => {
+                self.record_usage(metrics, caller, &run_result.resource_usage);
+                Ok(BuildRunResult {
+                    program_id,
+                    ttl,
+                    cached,
+                    build: compile_result,
+                    diagnostics,
+                    run: run_result,
+                })
+            }
+            Err(RunProgramError::InvalidFileName(_) | RunProgramError::ConflictingFilenames) => {
+                Err(BuildRun::invalid_file_names())
+            }
+            Err(RunProgramError::InvalidOptions(err)) => {
+                Err(BuildRun::invalid_options(err.to_string()))
+            }
+            Err(RunProgramError::LimitsExceeded(lim)) => Err(BuildRun::run_limits_exceeded(lim)),
+            Err(err) => Err(Err(err.into())),
+        }
+    }
+}
+
+/// The maximum size of a multipart request (in bytes) in addition to the
+/// maximum size of the uploaded archive.
+const MAX_MULTIPART_OVERHEAD: u64 = 1024 * 1024;
+
+/// Reject multipart requests that are larger than allowed for source archives
+/// before their body is received.
+fn limit_archive_size(ep: impl Endpoint + 'static) -> impl Endpoint {
+    ep.around(|ep, req| async move {
+        if !req
+            .content_type()
+            .is_some_and(|content_type| content_type.starts_with("multipart/"))
+        {
+            return ep.call(req).await;
+        }
+        let config = req.data::<Arc<Config>>().unwrap();
+        let max_size = config.max_archive_size + MAX_MULTIPART_OVERHEAD;
+        SizeLimit::new(max_size as usize)
+            .transform(ep)
+            .call(req)
+            .await
+    })
+}
+
+/// The request data for building a program.
+#[derive(Debug, ApiRequest)]
+enum BuildProgramRequest {
+    /// Source files are specified directly in the request.
+    Json(Json<BuildRequest>),
+    /// Source files are uploaded as an archive.
+    Archive(BuildArchiveRequest),
+}
+
+/// The request data for building a program from a source archive.
+#[derive(Debug, Multipart)]
+struct BuildArchiveRequest {
+    /// The environment to use for building and running the program.
+    environment: String,
+    /// The path of the main source file in the archive. If omitted, the
+    /// default main file name of the selected environment is used.
+    main_file: Option<String>,
+    /// A tar, tar.gz or zip archive that contains the source files.
+    archive: Upload,
+    /// A json list of environment variables to set during the build step.
+    env_vars: Option<JsonField<Vec<EnvVar>>>,
+    /// Limits to set on the compilation process (json).
+    compile_limits: Option<JsonField<LimitsOpt>>,
+    /// Values for the build options of the selected environment (json).
+    options: Option<JsonField<BTreeMap<String, OptionValue>>>,
+    /// A json list of optional packages of the selected environment.
+    packages: Option<JsonField<Vec<String>>>,
 }
 
-response!(BuildRun = {
+response!(pub(super) BuildRun = {
     /// Code has been executed successfully.
     Ok(200) => BuildRunResult,
     /// Environment does not exist.
     EnvironmentNotFound(404, error),
+    /// The self-test of the environment has failed.
+    EnvironmentUnhealthy(503, error) => String,
     /// Code could not be compiled.
-    CompileError(400, error) => RunResult,
+    CompileError(400, error) => CompileError,
     /// File names are not unique.
     InvalidFileNames(400, error),
     /// Environment variable names are not valid.
     InvalidEnvVars(400, error),
+    /// The specified options are not valid.
+    InvalidOptions(400, error) => String,
+    /// A selected package does not exist.
+    UnknownPackage(400, error) => String,
     /// The specified compile limits are too high.
     CompileLimitsExceeded(400, error) => Vec<LimitExceeded>,
     /// The specified run limits are too high.
     RunLimitsExceeded(400, error) => Vec<LimitExceeded>,
+    /// The program would exceed the storage quota of the api key.
+    StorageQuotaExceeded(403, error) => u64,
+    ..RateLimited,
+    ..Overloaded,
 });
 
 response!(Build = {
     /// Program has been built successfully.
     Ok(201) => BuildResult,
+    /// The uploaded archive is invalid.
+    InvalidArchive(400, error) => String,
     /// Environment does not exist.
     EnvironmentNotFound(404, error),
+    /// The self-test of the environment has failed.
+    EnvironmentUnhealthy(503, error) => String,
     /// Code could not be compiled.
-    CompileError(400, error) => RunResult,
+    CompileError(400, error) => CompileError,
     /// File names are not unique.
     InvalidFileNames(400, error),
     /// Environment variable names are not valid.
     InvalidEnvVars(400, error),
+    /// The specified options are not valid.
+    InvalidOptions(400, error) => String,
+    /// A selected package does not exist.
+    UnknownPackage(400, error) => String,
     /// The specified compile limits are too high.
     CompileLimitsExceeded(400, error) => Vec<LimitExceeded>,
+    /// The program would exceed the storage quota of the api key.
+    StorageQuotaExceeded(403, error) => u64,
+    ..RateLimited,
+    ..Overloaded,
+});
+
+response!(Check = {
+    /// The check script has been executed.
+    Ok(200) => CheckResult,
+    /// Environment does not exist.
+    EnvironmentNotFound(404, error),
+    /// The environment does not support checking programs.
+    CheckNotSupported(400, error),
+    /// File names are not unique.
+    InvalidFileNames(400, error),
+    /// Environment variable names are not valid.
+    InvalidEnvVars(400, error),
+    /// The specified options are not valid.
+    InvalidOptions(400, error) => String,
+    /// A selected package does not exist.
+    UnknownPackage(400, error) => String,
+    /// The specified compile limits are too high.
+    CompileLimitsExceeded(400, error) => Vec<LimitExceeded>,
+    ..RateLimited,
+    ..Overloaded,
+});
+
+response!(Format = {
+    /// The source files have been formatted.
+    Ok(200) => FormatResult,
+    /// Environment does not exist.
+    EnvironmentNotFound(404, error),
+    /// The environment does not support formatting source files.
+    FormatNotSupported(400, error),
+    /// The formatter failed (e.g. because of a syntax error).
+    FormatFailed(400, error) => RunResult,
+    /// File names are not unique.
+    InvalidFileNames(400, error),
+    /// The specified limits are too high.
+    LimitsExceeded(400, error) => Vec<LimitExceeded>,
+    ..RateLimited,
+    ..Overloaded,
 });
 
 response!(Run = {
@@ -250,28 +867,60 @@ response!(Run = {
     InvalidFileNames(400, error),
     /// Environment variable names are not valid.
     InvalidEnvVars(400, error),
+    /// The specified options are not valid.
+    InvalidOptions(400, error) => String,
     /// Program does not exist.
     ProgramNotFound(404, error),
     /// The specified run limits are too high.
     RunLimitsExceeded(400, error) => Vec<LimitExceeded>,
+    ..RateLimited,
+    ..Overloaded,
+});
+
+response!(Test = {
+    /// The test script has been executed.
+    Ok(200) => TestResult,
+    /// File names are not unique.
+    InvalidFileNames(400, error),
+    /// Environment variable names are not valid.
+    InvalidEnvVars(400, error),
+    /// The specified options are not valid.
+    InvalidOptions(400, error) => String,
+    /// Program does not exist.
+    ProgramNotFound(404, error),
+    /// The environment of the program does not support running tests.
+    TestsNotSupported(400, error),
+    /// The specified run limits are too high.
+    RunLimitsExceeded(400, error) => Vec<LimitExceeded>,
+    ..RateLimited,
+    ..Overloaded,
+});
+
+response!(DownloadFiles = {
+    /// Program does not exist.
+    ProgramNotFound(404, error),
+    /// The files of the program exceed the maximum archive size.
+    FilesTooLarge(413, error) => u64,
+    ..FilesArchive,
 });
 
-fn check_filename(name: &str) -> bool {
-    name.chars().any(|c| c != '.')
+#[derive(Debug, ApiResponse)]
+enum FilesArchive {
+    /// Tar archive of the program's files.
+    #[oai(status = 200)]
+    Ok(Attachment<Body>),
 }
 
 fn check_files(files: &[File]) -> bool {
-    files.iter().all(|f| check_filename(&f.name))
-        && files.iter().map(|f| &f.name).collect::<HashSet<_>>().len() == files.len()
+    files.iter().all(|f| check_path(&f.name)) && check_paths(files.iter().map(|f| f.name.as_str()))
 }
 
 fn check_mainfile(main_file: &MainFile) -> bool {
-    main_file
-        .name
-        .as_ref()
-        .map_or(true, |name| check_filename(name))
+    main_file.name.iter().all(|name| check_path(name))
 }
 
 fn check_env_vars(env_vars: &[EnvVar]) -> bool {
-    env_vars.iter().all(|e| e.name != "_")
+    env_vars
+        .iter()
+        .all(|e| e.name != "_" && !e.name.starts_with("SANDKASTEN_"))
 }
@@ -0,0 +1,35 @@
+use poem_ext::static_string;
+use poem_openapi::{payload::Json, ApiResponse, Object};
+use sandkasten_client::schemas::Overload;
+
+use crate::queue::QueueError;
+
+static_string!(pub ServiceUnavailableError, "service_unavailable");
+
+#[derive(Debug, Object)]
+pub struct ServiceUnavailable {
+    pub error: ServiceUnavailableError,
+    pub reason: Overload,
+}
+
+#[derive(Debug, ApiResponse)]
+pub enum Overloaded {
+    /// The server is overloaded, i.e. too many requests are waiting for free
+    /// job slots or the request has waited too long, or the server is in drain
+    /// mode.
+    #[oai(status = 503)]
+    ServiceUnavailable(Json<ServiceUnavailable>),
+}
+
+impl From<QueueError> for Overloaded {
+    fn from(err: QueueError) -> Self {
+        Self::ServiceUnavailable(Json(ServiceUnavailable {
+            error: ServiceUnavailableError,
+            reason: match err {
+                QueueError::Full => Overload::QueueFull,
+                QueueError::Timeout => Overload::QueueTimeout,
+                QueueError::Draining => Overload::Draining,
+            },
+        }))
+    }
+}
@@ -0,0 +1,42 @@
+use poem_ext::static_string;
+use poem_openapi::{payload::Json, ApiResponse, Object};
+use sandkasten_client::schemas::{RateLimit, RateLimitExceeded};
+
+use crate::metrics::Metrics;
+
+static_string!(pub TooManyRequestsError, "too_many_requests");
+
+#[derive(Debug, Object)]
+pub struct TooManyRequests {
+    pub error: TooManyRequestsError,
+    pub reason: RateLimitExceeded,
+}
+
+#[derive(Debug, ApiResponse)]
+pub enum RateLimited {
+    /// The client has exceeded its rate limit or one of its resource budgets.
+    #[oai(status = 429)]
+    TooManyRequests(Json<TooManyRequests>, #[oai(header = "Retry-After")] u64),
+}
+
+impl RateLimited {
+    pub fn new(metrics: &Metrics, reason: RateLimitExceeded) -> Self {
+        let limit = match reason.limit {
+            RateLimit::Requests => "requests",
+            RateLimit::CpuTime => "cpu_time",
+            RateLimit::MemoryTime => "memory_time",
+        };
+        metrics
+            .rate_limits
+            .rejected
+            .with_label_values(&[limit])
+            .inc();
+        Self::TooManyRequests(
+            Json(TooManyRequests {
+                error: TooManyRequestsError,
+                reason: reason.clone(),
+            }),
+            reason.retry_after,
+        )
+    }
+}
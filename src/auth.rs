@@ -0,0 +1,135 @@
+use sandkasten_client::schemas::programs::{LimitExceeded, Limits, LimitsOpt};
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, program::tenant::Tenant};
+
+/// The effective permissions of the client that sent a request.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    /// The identifier of the client that is used for rate limiting, i.e. the
+    /// name of the api key or the ip address of the client if authentication
+    /// is disabled.
+    pub client: String,
+    /// The name of the api key that has been used to authenticate the request
+    /// or `None` if authentication is disabled.
+    pub name: Option<String>,
+    /// The maximum allowed limits for compile steps.
+    pub compile_limits: Limits,
+    /// The maximum allowed limits for run steps.
+    pub run_limits: Limits,
+    /// The environments the client is allowed to use or `None` if all
+    /// environments can be used.
+    pub environments: Option<Vec<String>>,
+    /// The maximum total size of the client's stored programs (in bytes) or
+    /// `None` if the storage is not limited.
+    pub storage_quota: Option<u64>,
+    /// The priority of the client's requests (see
+    /// [`Config::priority_classes`]).
+    pub priority: u32,
+    /// The highest priority the client can select or `None` if all priority
+    /// classes can be selected.
+    pub max_priority: Option<u32>,
+    /// Whether the client can use admin endpoints. This requires an api key
+    /// with admin permissions, i.e. admin endpoints are not available if
+    /// authentication is disabled.
+    pub admin: bool,
+}
+
+impl Caller {
+    /// Authenticate a client using its api key. Return `None` if the api key is
+    /// missing or invalid. If no api keys are configured, every client is
+    /// allowed to use the global limits and all environments, but no admin
+    /// endpoints.
+    pub fn authenticate(config: &Config, api_key: Option<&str>, address: &str) -> Option<Self> {
+        if config.api_keys.is_empty() {
+            return Some(Self {
+                client: address.into(),
+                name: None,
+                compile_limits: config.compile_limits.clone(),
+                run_limits: config.run_limits.clone(),
+                environments: None,
+                storage_quota: None,
+                priority: priority(config, &config.default_priority),
+                max_priority: None,
+                admin: false,
+            });
+        }
+
+        let hash = format!("{:x}", Sha256::digest(api_key?));
+        let key = config
+            .api_keys
+            .iter()
+            .find(|key| key.hash.eq_ignore_ascii_case(&hash))?;
+        let class = key.priority.as_deref().unwrap_or(&config.default_priority);
+        Some(Self {
+            client: key.name.clone(),
+            name: Some(key.name.clone()),
+            compile_limits: config.compile_limits.restrict(&key.compile_limits),
+            run_limits: config.run_limits.restrict(&key.run_limits),
+            environments: key.environments.clone(),
+            storage_quota: key.storage_quota,
+            priority: priority(config, class),
+            max_priority: Some(priority(config, class)),
+            admin: key.admin,
+        })
+    }
+
+    /// Select the priority class of the request. Return `false` if the class
+    /// does not exist or if its priority is higher than the maximum priority of
+    /// the client.
+    pub fn select_priority(&mut self, config: &Config, class: &str) -> bool {
+        let Some(&priority) = config.priority_classes.get(class) else {
+            return false;
+        };
+        if self.max_priority.is_some_and(|max| priority > max) {
+            return false;
+        }
+        self.priority = priority;
+        true
+    }
+
+    /// Return the tenant that owns the programs built by the client.
+    pub fn tenant(&self) -> Tenant<'_> {
+        Tenant {
+            name: self.name.as_deref(),
+            storage_quota: self.storage_quota,
+        }
+    }
+
+    /// Check whether the client is allowed to use an environment.
+    pub fn can_use_environment(&self, environment: &str) -> bool {
+        match &self.environments {
+            Some(environments) => environments.iter().any(|env| env == environment),
+            None => true,
+        }
+    }
+
+    /// Check the requested compile limits against the maximum limits of the
+    /// client and replace omitted limits with these maximum limits. Return the
+    /// resulting limits.
+    pub fn check_compile_limits(
+        &self,
+        limits: &mut LimitsOpt,
+    ) -> Result<Limits, Vec<LimitExceeded>> {
+        let checked = limits.check(&self.compile_limits)?;
+        *limits = checked.clone().into();
+        Ok(checked)
+    }
+
+    /// Check the requested run limits against the maximum limits of the client
+    /// and replace omitted limits with these maximum limits. Return the
+    /// resulting limits.
+    pub fn check_run_limits(&self, limits: &mut LimitsOpt) -> Result<Limits, Vec<LimitExceeded>> {
+        let checked = limits.check(&self.run_limits)?;
+        *limits = checked.clone().into();
+        Ok(checked)
+    }
+}
+
+fn priority(config: &Config, class: &str) -> u32 {
+    config
+        .priority_classes
+        .get(class)
+        .copied()
+        .unwrap_or_default()
+}
@@ -1,8 +1,8 @@
-use std::{env, path::PathBuf};
+use std::{collections::HashMap, env, path::PathBuf};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use config::{Environment, File};
-use sandkasten_client::schemas::programs::Limits;
+use sandkasten_client::schemas::programs::{Limits, LimitsOpt};
 use serde::{Deserialize, Deserializer};
 use tracing::info;
 
@@ -17,6 +17,10 @@ pub fn load() -> Result<Config, anyhow::Error> {
         .try_deserialize()
         .context("Failed to parse config")?;
 
+    if !conf.webhook_allowed_urls.is_empty() && conf.webhook_secret.is_empty() {
+        bail!("`webhook_secret` must be set if `webhook_allowed_urls` is not empty");
+    }
+
     Ok(Config {
         nsjail_path: conf.nsjail_path.canonicalize().with_context(|| {
             format!(
@@ -48,31 +52,156 @@ pub struct Config {
     pub programs_dir: PathBuf,
     /// The directory where files for jobs are stored.
     pub jobs_dir: PathBuf,
+    /// The minimum amount of free space (in MB) in `programs_dir` and
+    /// `jobs_dir` for the instance to be considered ready.
+    pub min_free_space: u64,
+
+    /// The directory where the persistent compile caches of environments are
+    /// stored. Each api key has its own compile caches, but if authentication
+    /// is disabled (i.e. [`Config::api_keys`] is empty), all clients share the
+    /// same compile caches and can therefore influence each other's builds.
+    pub compile_cache_dir: PathBuf,
+    /// The maximum size of a single compile cache shard (in bytes). Shards that
+    /// exceed this size are cleared after the compile step.
+    pub compile_cache_max_size: u64,
+    /// The number of independent shards of each environment's compile cache,
+    /// i.e. the number of compile steps of the same environment that can use
+    /// the cache at the same time.
+    pub compile_cache_shards: usize,
 
     /// The time to live for programs in seconds.
     pub program_ttl: u64,
     /// The number of seconds to wait between deleting old programs.
     pub prune_programs_interval: u64,
 
+    /// The time to live for finished asynchronous jobs in seconds.
+    pub job_ttl: u64,
+
+    /// A list of URL prefixes that callback URLs of asynchronous jobs must
+    /// match. The scheme, host and port of a callback URL must be equal to
+    /// those of the prefix and its path must start with the path of the
+    /// prefix. If specified as an environment variable, separate the URLs
+    /// using whitespace.
+    #[serde(deserialize_with = "urls")]
+    pub webhook_allowed_urls: Vec<String>,
+    /// The secret used to sign webhook requests. The hex encoded HMAC-SHA256
+    /// of the request body is sent in the `X-Sandkasten-Signature` header.
+    /// Required if `webhook_allowed_urls` is not empty.
+    #[serde(default)]
+    pub webhook_secret: String,
+    /// The maximum number of attempts to deliver a webhook.
+    pub webhook_attempts: u32,
+    /// The number of seconds to wait before retrying a failed webhook
+    /// delivery. The delay is doubled after each failed attempt.
+    pub webhook_retry_delay: u64,
+    /// The timeout for a single webhook request in seconds.
+    pub webhook_timeout: u64,
+
+    /// The maximum total size of a program's files (in bytes) that can be
+    /// downloaded as an archive.
+    pub max_files_archive_size: u64,
+
+    /// The maximum size of an uploaded source archive (in bytes).
+    pub max_archive_size: u64,
+    /// The maximum number of files in an uploaded source archive.
+    pub max_archive_files: usize,
+    /// The maximum total size of the files in an uploaded source archive after
+    /// extraction (in bytes).
+    pub max_archive_extracted_size: u64,
+
     /// The maximum number of jobs that can run at the same time.
     pub max_concurrent_jobs: usize,
+    /// The maximum number of compile steps (builds, checks and formatting)
+    /// that can run at the same time. Defaults to `max_concurrent_jobs`.
+    #[serde(default)]
+    pub max_concurrent_compile_jobs: Option<usize>,
+    /// The maximum number of run steps (runs and tests) that can run at the
+    /// same time. Defaults to `max_concurrent_jobs`.
+    #[serde(default)]
+    pub max_concurrent_run_jobs: Option<usize>,
+    /// The number of cpus that can be used by jobs running at the same time,
+    /// i.e. jobs are only started if the sum of their `cpus` limits does not
+    /// exceed this value. If omitted, the number of cpus of the host is used.
+    #[serde(default)]
+    pub host_cpus: Option<u64>,
+    /// The amount of memory (in MB) that can be used by jobs running at the
+    /// same time, i.e. jobs are only started if the sum of their `memory`
+    /// limits does not exceed this value. If omitted, the total memory of the
+    /// host is used.
+    #[serde(default)]
+    pub host_memory: Option<u64>,
+    /// The cpu cores that jobs are pinned to. If set, every job receives
+    /// exclusive cores from this list (as many as its `cpus` limit) and waits
+    /// until enough cores are free. Sandboxed programs are not allowed to
+    /// change their cpu affinity. This makes timing measurements more stable
+    /// and overrides `host_cpus`.
+    #[serde(default)]
+    pub cpuset: Option<Vec<usize>>,
+    /// The maximum number of requests that can wait for free job slots.
+    /// Further requests are rejected immediately.
+    pub max_queue_length: usize,
+    /// The maximum number of seconds a request can wait for free job slots.
+    /// If set to 0, requests are rejected immediately if there are no free job
+    /// slots.
+    pub max_queue_wait: u64,
+    /// The priority classes that can be selected using the `X-Priority`
+    /// header, mapped to their priority. Waiting requests with a higher
+    /// priority are started first.
+    pub priority_classes: HashMap<String, u32>,
+    /// The priority class of requests that don't select one.
+    pub default_priority: String,
+    /// The number of seconds after which a waiting request is treated as if its
+    /// priority was one higher than it actually is, so that requests with a low
+    /// priority are not starved. Set to 0 to disable aging.
+    pub priority_aging: u64,
+
+    /// The maximum number of seconds to wait for running jobs to finish when
+    /// shutting down.
+    pub shutdown_timeout: u64,
+
+    /// The maximum number of requests a single client can send in a burst.
+    /// Clients are identified by the name of their api key or, if
+    /// authentication is disabled, by their ip address. Set to `0` to disable
+    /// rate limiting.
+    pub rate_limit_burst: u32,
+    /// The number of requests per second a single client can send on average.
+    pub rate_limit_per_second: f64,
+    /// The length of the rolling window for the resource budgets in seconds.
+    pub budget_window: u64,
+    /// The maximum total cpu time (in seconds) that the programs of a single
+    /// client can use within the budget window. Set to `0` to disable.
+    pub cpu_budget: u64,
+    /// The maximum total memory-time (in megabyte-seconds, i.e. memory usage
+    /// multiplied by cpu time) that the programs of a single client can use
+    /// within the budget window. Set to `0` to disable.
+    pub memory_budget: u64,
 
     /// The maximum allowed limits for compile steps.
     pub compile_limits: Limits,
     /// The maximum allowed limits for run steps.
     pub run_limits: Limits,
 
+    /// The api keys that can be used to authenticate requests. If no api keys
+    /// are configured, authentication is disabled and every client may use the
+    /// global limits and all environments, but no admin endpoints.
+    pub api_keys: Vec<ApiKey>,
+
     /// The number of times the program is run when measuring the base resource
     /// usage of an environment.
     pub base_resource_usage_runs: usize,
-    /// The number of permits to acquire from the internal request semaphore
-    /// when measuring the base resource usage of an environment. If
-    /// set to the value of `max_concurrent_jobs`, no other jobs can run at the
-    /// same time.
+    /// The number of job slots (of the run pool) to reserve when measuring the
+    /// base resource usage of an environment. If set to the value of
+    /// `max_concurrent_jobs`, no other jobs can run at the same time.
     pub base_resource_usage_permits: u32,
     /// The time to live for base resource usage cache entries in seconds.
     pub base_resource_usage_cache_ttl: u64,
 
+    /// Whether to build and run the test program of every environment at
+    /// startup. Environments whose self-test fails cannot be used to build
+    /// programs. If enabled, the instance is only considered ready after at
+    /// least one environment has passed its self-test.
+    pub selftest_on_startup: bool,
+
     /// Whether to use cgroup to set resource limits where possible. It is
     /// strongly recommended to set this to true in production environments!
     pub use_cgroup: bool,
@@ -89,6 +218,58 @@ pub struct Config {
     pub environments_path: Vec<PathBuf>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ApiKey {
+    /// A unique name of the api key (e.g. the name of the client).
+    pub name: String,
+    /// The hex encoded SHA-256 hash of the api key.
+    pub hash: String,
+    /// The maximum allowed limits for compile steps. Limits that are omitted
+    /// default to the global `compile_limits`. Network access is only allowed
+    /// if `network` is enabled both here and in the global limits.
+    #[serde(default)]
+    pub compile_limits: LimitsOpt,
+    /// The maximum allowed limits for run steps. Limits that are omitted
+    /// default to the global `run_limits`.
+    #[serde(default)]
+    pub run_limits: LimitsOpt,
+    /// The environments that can be used with this api key. If omitted, all
+    /// environments can be used.
+    #[serde(default)]
+    pub environments: Option<Vec<String>>,
+    /// The maximum total size of the programs stored for this api key (in
+    /// bytes). If omitted, the storage is not limited.
+    #[serde(default)]
+    pub storage_quota: Option<u64>,
+    /// The highest priority class that can be selected with this api key, which
+    /// is also used for requests that don't select one. If omitted, the global
+    /// `default_priority` is used.
+    #[serde(default)]
+    pub priority: Option<String>,
+    /// Whether this api key can use admin endpoints (e.g. to enable drain
+    /// mode). Admin endpoints are only available to api keys with this
+    /// permission, even if authentication is disabled.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+fn urls<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Urls {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match Urls::deserialize(deserializer)? {
+        Urls::String(x) => x.split_whitespace().map(Into::into).collect(),
+        Urls::List(x) => x,
+    })
+}
+
 fn path<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
 where
     D: Deserializer<'de>,
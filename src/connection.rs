@@ -0,0 +1,219 @@
+use std::{
+    collections::HashMap,
+    future::pending,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use poem::{
+    http::{uri::Scheme, StatusCode},
+    listener::Acceptor,
+    web::{LocalAddr, RemoteAddr},
+    Endpoint, Error, Middleware, Request, Result,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    time::sleep,
+};
+use tracing::info;
+
+/// The interval in which connections with unread data are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The status code that is logged for requests that have been cancelled
+/// because the client has closed the connection.
+const CLIENT_CLOSED_REQUEST: u16 = 499;
+
+/// The open client connections, indexed by the address of the client.
+///
+/// hyper does not read from an HTTP/1 connection while a request is being
+/// handled, so it does not notice if the client closes the connection in the
+/// meantime and keeps running the handler. Instead, a second handle to each
+/// connection is kept here, which is used to detect that the connection has
+/// been closed (see [`CancelOnDisconnect`]).
+#[derive(Debug, Default)]
+pub struct Connections(Mutex<HashMap<SocketAddr, Arc<TcpStream>>>);
+
+impl Connections {
+    /// Wait until the client with the given address has closed its connection.
+    /// Never completes if the connection is unknown.
+    pub async fn closed(&self, addr: &SocketAddr) {
+        let Some(stream) = self.0.lock().unwrap().get(addr).cloned() else {
+            return pending().await;
+        };
+        loop {
+            match stream.peek(&mut [0]).await {
+                Ok(0) | Err(_) => return,
+                // data that has not been read by the server yet (e.g. the body of
+                // the current request or a pipelined request) hides whether the
+                // connection has been closed, so check again later
+                Ok(_) => sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+/// Acceptor that registers all accepted connections in [`Connections`].
+pub struct ConnectionAcceptor<A> {
+    inner: A,
+    connections: Arc<Connections>,
+}
+
+impl<A> ConnectionAcceptor<A> {
+    pub fn new(inner: A, connections: Arc<Connections>) -> Self {
+        Self { inner, connections }
+    }
+}
+
+impl<A: Acceptor<Io = TcpStream>> Acceptor for ConnectionAcceptor<A> {
+    type Io = Connection;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.inner.local_addr()
+    }
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, LocalAddr, RemoteAddr, Scheme)> {
+        let (stream, local_addr, remote_addr, scheme) = self.inner.accept().await?;
+        let Some(&addr) = remote_addr.as_socket_addr() else {
+            let connection = Connection {
+                stream,
+                _registration: None,
+            };
+            return Ok((connection, local_addr, remote_addr, scheme));
+        };
+
+        // the duplicated handle is registered separately with the runtime, so
+        // it can be used to wait for the connection to be closed without
+        // interfering with the handle used by the server
+        let stream = stream.into_std()?;
+        let watcher = Arc::new(TcpStream::from_std(stream.try_clone()?)?);
+        let stream = TcpStream::from_std(stream)?;
+        self.connections
+            .0
+            .lock()
+            .unwrap()
+            .insert(addr, Arc::clone(&watcher));
+
+        let connection = Connection {
+            stream,
+            _registration: Some(Registration {
+                connections: Arc::clone(&self.connections),
+                addr,
+                watcher,
+            }),
+        };
+        Ok((connection, local_addr, remote_addr, scheme))
+    }
+}
+
+/// A client connection that is removed from [`Connections`] when it is
+/// dropped.
+pub struct Connection {
+    stream: TcpStream,
+    _registration: Option<Registration>,
+}
+
+struct Registration {
+    connections: Arc<Connections>,
+    addr: SocketAddr,
+    watcher: Arc<TcpStream>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut connections = self.connections.0.lock().unwrap();
+        // the address might already have been reused by a new connection
+        if connections
+            .get(&self.addr)
+            .is_some_and(|watcher| Arc::ptr_eq(watcher, &self.watcher))
+        {
+            connections.remove(&self.addr);
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+}
+
+/// Middleware that cancels requests as soon as their client has closed the
+/// connection. The request handler is dropped, which kills all sandboxed
+/// processes it has started and releases its resources.
+pub struct CancelOnDisconnect(pub Arc<Connections>);
+
+impl<E: Endpoint> Middleware<E> for CancelOnDisconnect {
+    type Output = CancelOnDisconnectEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CancelOnDisconnectEndpoint {
+            inner: ep,
+            connections: Arc::clone(&self.0),
+        }
+    }
+}
+
+pub struct CancelOnDisconnectEndpoint<E> {
+    inner: E,
+    connections: Arc<Connections>,
+}
+
+impl<E: Endpoint> Endpoint for CancelOnDisconnectEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let Some(&addr) = req.remote_addr().as_socket_addr() else {
+            return self.inner.call(req).await;
+        };
+        let path = req.uri().path().to_owned();
+        tokio::select! {
+            response = self.inner.call(req) => response,
+            _ = self.connections.closed(&addr) => {
+                info!("Client {addr} has disconnected, cancelling request to {path}");
+                Err(Error::from_status(
+                    StatusCode::from_u16(CLIENT_CLOSED_REQUEST).unwrap(),
+                ))
+            }
+        }
+    }
+}
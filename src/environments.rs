@@ -1,15 +1,21 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
 };
 
 use anyhow::Context;
-use sandkasten_client::schemas::programs;
-use serde::Deserialize;
+use sandkasten_client::schemas::{environments::EnvironmentOption, programs};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tracing::{debug, warn};
 
-use crate::VERSION;
+use crate::{
+    program::{
+        diagnostics::DiagnosticsFormat,
+        options::{check_option_name, resolve_options},
+    },
+    VERSION,
+};
 
 pub type Environments = HashMap<String, Environment>;
 
@@ -20,13 +26,46 @@ pub struct Environment {
     pub meta: Value,
     pub default_main_file_name: String,
     pub compile_script: Option<String>,
+    pub check_script: Option<String>,
+    pub format_script: Option<String>,
     pub run_script: String,
+    pub test_script: Option<String>,
     pub closure: PathBuf,
+    pub compile_cache: Option<CompileCache>,
+    pub diagnostics: Option<DiagnosticsFormat>,
+    #[serde(default)]
+    pub packages: BTreeMap<String, Package>,
+    #[serde(default)]
+    pub build_options: BTreeMap<String, EnvironmentOption>,
+    #[serde(default)]
+    pub run_options: BTreeMap<String, EnvironmentOption>,
     pub example: Option<String>,
     pub test: Test,
     pub sandkasten_version: String,
 }
 
+/// An optional package that can be selected in build requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Package {
+    /// The version of the package.
+    pub version: Option<String>,
+    /// A short description of the package.
+    pub description: Option<String>,
+    /// The closure file of the package.
+    pub closure: PathBuf,
+    /// Environment variables that make the package available to the compile
+    /// and run scripts (e.g. `PYTHONPATH`).
+    #[serde(default)]
+    pub env_vars: BTreeMap<String, String>,
+}
+
+/// A persistent cache directory that is mounted into the compile sandbox.
+#[derive(Debug, Deserialize)]
+pub struct CompileCache {
+    /// The path where the cache is mounted inside the sandbox.
+    pub path: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Test {
     pub main_file: programs::MainFile,
@@ -76,6 +115,13 @@ fn load_file(out: &mut Environments, path: &Path) -> Result<(), anyhow::Error> {
     let environment: Environment = serde_json::from_str(&content)
         .with_context(|| format!("Failed to parse {}", path.display()))?;
 
+    for options in [&environment.build_options, &environment.run_options] {
+        if let Some(name) = options.keys().find(|name| !check_option_name(name)) {
+            anyhow::bail!("Invalid option name {name:?}");
+        }
+        resolve_options(options, &BTreeMap::new()).context("Invalid option defaults")?;
+    }
+
     if environment.sandkasten_version != VERSION {
         warn!(
             "Package {name} was built for a different version of Sandkasten ({})",
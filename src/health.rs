@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    sys::statvfs::statvfs,
+    unistd::{access, AccessFlags},
+};
+use sandkasten_client::schemas::health::HealthCheck;
+use uuid::Uuid;
+
+use crate::{config::Config, environments::Environments, queue::JobQueue, selftest::SelfTests};
+
+/// The cgroup v2 hierarchy nsjail creates its cgroups in (see
+/// `--cgroupv2_mount`).
+const CGROUP_V2_MOUNT: &str = "/sys/fs/cgroup";
+
+/// Check whether this instance is able to execute code. This performs blocking
+/// file system operations.
+pub fn check_readiness(
+    config: &Config,
+    environments: &Environments,
+    selftests: &SelfTests,
+    job_queue: &JobQueue,
+) -> Vec<HealthCheck> {
+    let mut checks = vec![
+        check("nsjail", executable(&config.nsjail_path)),
+        check("time", executable(&config.time_path)),
+        check(
+            "programs_dir",
+            writable_dir(&config.programs_dir, config.min_free_space),
+        ),
+        check(
+            "jobs_dir",
+            writable_dir(&config.jobs_dir, config.min_free_space),
+        ),
+    ];
+    if config.use_cgroup {
+        checks.push(check("cgroup", cgroup_v2()));
+    }
+    checks.push(check(
+        "environments",
+        usable_environments(config, environments, selftests),
+    ));
+    checks.push(check(
+        "drain",
+        if job_queue.is_draining() {
+            Err("drain mode is enabled".into())
+        } else {
+            Ok(())
+        },
+    ));
+    checks
+}
+
+fn check(name: &str, result: Result<(), String>) -> HealthCheck {
+    HealthCheck {
+        name: name.into(),
+        healthy: result.is_ok(),
+        message: result.err(),
+    }
+}
+
+/// Check whether at least one environment has passed its self-test. If
+/// environments are not tested at startup, environments that have not been
+/// tested yet are considered usable as well.
+fn usable_environments(
+    config: &Config,
+    environments: &Environments,
+    selftests: &SelfTests,
+) -> Result<(), String> {
+    if environments.is_empty() {
+        return Err("no environments have been loaded".into());
+    }
+    if !environments.keys().any(|id| match selftests.get(id) {
+        Some(result) => result.passed,
+        None => !config.selftest_on_startup,
+    }) {
+        return Err("no environment has passed its self-test".into());
+    }
+    Ok(())
+}
+
+/// Check whether a path is an executable file.
+fn executable(path: &Path) -> Result<(), String> {
+    let metadata = fs::metadata(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Err(format!("{} is not an executable file", path.display()));
+    }
+    Ok(())
+}
+
+/// Check whether files can be created in a directory and whether the file
+/// system has at least `min_free_space` MB of free space.
+fn writable_dir(path: &Path, min_free_space: u64) -> Result<(), String> {
+    let file = path.join(format!(".health-{}", Uuid::new_v4()));
+    fs::write(&file, []).map_err(|err| format!("{} is not writable: {err}", path.display()))?;
+    fs::remove_file(&file).map_err(|err| format!("failed to remove {}: {err}", file.display()))?;
+
+    let stat = statvfs(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    let free = stat.blocks_available() as u64 * stat.fragment_size() as u64 / (1024 * 1024);
+    if free < min_free_space {
+        return Err(format!(
+            "only {free} MB of free space left in {} (min {min_free_space} MB)",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Check whether nsjail can create cgroups with memory and pids controllers.
+fn cgroup_v2() -> Result<(), String> {
+    let mount = PathBuf::from(CGROUP_V2_MOUNT);
+    let controllers = fs::read_to_string(mount.join("cgroup.controllers"))
+        .map_err(|err| format!("cgroup v2 is not available: {err}"))?;
+    for controller in ["memory", "pids"] {
+        if !controllers.split_whitespace().any(|c| c == controller) {
+            return Err(format!("cgroup controller {controller} is not available"));
+        }
+    }
+    access(&mount, AccessFlags::W_OK)
+        .map_err(|err| format!("{} is not writable: {err}", mount.display()))?;
+    Ok(())
+}
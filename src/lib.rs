@@ -2,10 +2,17 @@
 #![warn(clippy::dbg_macro, clippy::use_debug, clippy::todo)]
 
 pub mod api;
+pub mod auth;
 pub mod config;
+pub mod connection;
 pub mod environments;
+pub mod health;
 pub mod metrics;
 pub mod program;
+pub mod queue;
+pub mod rate_limit;
 pub mod sandbox;
+pub mod selftest;
+pub mod webhook;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -1,25 +1,47 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::dbg_macro, clippy::use_debug, clippy::todo)]
 
-use std::{path::Path, sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
 
 use anyhow::{ensure, Context};
 use key_rwlock::KeyRwLock;
-use poem::{listener::TcpListener, middleware::Tracing, EndpointExt, Route, Server};
+use poem::{
+    listener::{Listener, TcpListener},
+    middleware::Tracing,
+    EndpointExt, Route, Server,
+};
 use poem_ext::panic_handler::PanicHandler;
 use poem_openapi::OpenApiService;
 use sandkasten::{
-    api::get_api,
+    api::{abort_jobs, get_api, Jobs},
     config::{self, Config},
+    connection::{CancelOnDisconnect, ConnectionAcceptor, Connections},
     environments,
     metrics::{self, Metrics},
-    program::prune::prune_programs,
+    program::{
+        cache::CacheLock, manifest::migrate_programs, now, prune::prune_programs,
+        tenant::StorageUsage,
+    },
+    queue::JobQueue,
+    rate_limit::RateLimiter,
+    selftest::{self, run_selftest, SelfTests},
     VERSION,
 };
-use tokio::fs;
+use tokio::{
+    fs,
+    signal::unix::{signal, SignalKind},
+};
 use tracing::{error, info, trace};
 use uuid::Uuid;
 
+/// The maximum time to wait for aborted jobs to stop when shutting down.
+const ABORT_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
@@ -32,26 +54,42 @@ async fn main() -> anyhow::Result<()> {
     ensure!(config.base_resource_usage_runs >= 1);
     ensure!(config.base_resource_usage_permits >= 1);
     ensure!(config.base_resource_usage_permits <= config.max_concurrent_jobs as _);
+    ensure!(config.compile_cache_shards >= 1);
+    ensure!(config.rate_limit_burst == 0 || config.rate_limit_per_second > 0.0);
+    ensure!(config.budget_window >= 1);
+    ensure!(config.max_concurrent_compile_jobs != Some(0));
+    ensure!(config.max_concurrent_run_jobs != Some(0));
+    if let Some(cpuset) = &config.cpuset {
+        ensure!(!cpuset.is_empty(), "`cpuset` must not be empty");
+        ensure!(
+            cpuset.iter().collect::<HashSet<_>>().len() == cpuset.len(),
+            "`cpuset` must not contain duplicate cores"
+        );
+    }
+    for class in std::iter::once(&config.default_priority).chain(
+        config
+            .api_keys
+            .iter()
+            .filter_map(|key| key.priority.as_ref()),
+    ) {
+        ensure!(
+            config.priority_classes.contains_key(class),
+            "Unknown priority class {class:?}"
+        );
+    }
 
-    info!("Creating directories for jobs and programs");
+    info!("Creating directories for jobs, programs and compile caches");
     create_dir_if_not_exists(&config.programs_dir).await?;
     create_dir_if_not_exists(&config.jobs_dir).await?;
+    create_dir_if_not_exists(&config.compile_cache_dir).await?;
 
     info!("Pruning jobs directory");
-    for dir in std::fs::read_dir(&config.jobs_dir).context("Failed to read jobs directory")? {
-        let path = dir.context("Failed to read jobs directory entry")?.path();
-        if path.is_dir() {
-            std::fs::remove_dir_all(&path)
-                .with_context(|| format!("Failed to remove directory {}", path.display()))?;
-        } else {
-            std::fs::remove_file(&path)
-                .with_context(|| format!("Failed to remove file {}", path.display()))?;
-        }
-    }
+    clear_jobs_dir(&config)?;
 
     let config = Arc::new(Config {
         programs_dir: config.programs_dir.canonicalize().unwrap(),
         jobs_dir: config.jobs_dir.canonicalize().unwrap(),
+        compile_cache_dir: config.compile_cache_dir.canonicalize().unwrap(),
         ..config
     });
 
@@ -61,22 +99,60 @@ async fn main() -> anyhow::Result<()> {
     );
     info!("Loaded {} environments", environments.len());
 
+    info!("Migrating programs");
+    migrate_programs(&config, &environments)
+        .await
+        .context("Failed to migrate programs")?;
+
+    info!("Computing storage usage");
+    let storage_usage = Arc::new(
+        StorageUsage::load(&config)
+            .await
+            .context("Failed to compute storage usage")?,
+    );
+
     let program_lock = Arc::new(KeyRwLock::new());
     let job_lock = Arc::new(KeyRwLock::new());
+    let cache_lock = Arc::new(KeyRwLock::new());
+    let rate_limiter = Arc::new(RateLimiter::default());
+    let job_queue = Arc::new(JobQueue::new(&config));
+    let selftests = Arc::new(SelfTests::new(&environments));
+    let jobs = Arc::new(Jobs::default());
 
     let metrics = Arc::new(Metrics::new().context("Failed to initialize Prometheus metrics")?);
 
     tokio::spawn(prune_old_programs_loop(
         Arc::clone(&config),
         Arc::clone(&program_lock),
+        Arc::clone(&storage_usage),
     ));
 
+    if config.selftest_on_startup {
+        tokio::spawn(selftest_environments(
+            Arc::clone(&config),
+            Arc::clone(&environments),
+            Arc::clone(&selftests),
+            Arc::clone(&storage_usage),
+            Arc::clone(&program_lock),
+            Arc::clone(&job_lock),
+            Arc::clone(&cache_lock),
+            Arc::clone(&job_queue),
+            Arc::clone(&metrics),
+        ));
+    }
+
     let api_service = OpenApiService::new(
         get_api(
             Arc::clone(&config),
             Arc::clone(&environments),
             program_lock,
             job_lock,
+            cache_lock,
+            Arc::clone(&rate_limiter),
+            Arc::clone(&job_queue),
+            selftests,
+            storage_usage,
+            Arc::clone(&jobs),
         ),
         "Sandkasten",
         VERSION,
@@ -92,32 +168,175 @@ async fn main() -> anyhow::Result<()> {
     if config.enable_metrics {
         route = route.nest("/metrics", poem::get(metrics::endpoint));
     }
+    let connections = Arc::new(Connections::default());
     let app = route
         .nest("/", api_service)
         .data(metrics)
+        .data(Arc::clone(&config))
+        .data(rate_limiter)
+        .with(CancelOnDisconnect(Arc::clone(&connections)))
         .with(Tracing)
         .with(PanicHandler::middleware());
 
     info!("Listening on {}:{}", config.host, config.port);
-    Server::new(TcpListener::bind((config.host.as_str(), config.port)))
-        .run(app)
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+    let deadline = Arc::new(OnceLock::new());
+    let mut sigterm =
+        signal(SignalKind::terminate()).context("Failed to register SIGTERM handler")?;
+    let acceptor = TcpListener::bind((config.host.as_str(), config.port))
+        .into_acceptor()
+        .await
+        .context("Failed to bind to address")?;
+    Server::new_with_acceptor(ConnectionAcceptor::new(acceptor, connections))
+        .run_with_graceful_shutdown(
+            app,
+            {
+                let job_queue = Arc::clone(&job_queue);
+                let deadline = Arc::clone(&deadline);
+                async move {
+                    tokio::select! {
+                        _ = sigterm.recv() => {}
+                        _ = tokio::signal::ctrl_c() => {}
+                    }
+                    info!("Shutting down, waiting for running jobs to finish");
+                    deadline.get_or_init(|| Instant::now() + shutdown_timeout);
+                    job_queue.set_draining(true);
+                }
+            },
+            Some(shutdown_timeout),
+        )
         .await
         .context("Failed to start server")?;
 
+    // asynchronous jobs are not bound to a request, so they might still be
+    // running even though all connections have been closed
+    let deadline = deadline
+        .get()
+        .copied()
+        .unwrap_or_else(|| Instant::now() + shutdown_timeout);
+    if tokio::time::timeout_at(deadline.into(), job_queue.wait_idle())
+        .await
+        .is_err()
+    {
+        let (running_jobs, waiting_requests) = job_queue.status();
+        error!(
+            "Shutdown timeout exceeded, killing {running_jobs} running jobs and \
+             {waiting_requests} waiting requests"
+        );
+        abort_jobs(&jobs);
+
+        // aborted jobs release their resources as soon as their tasks have been
+        // dropped, while the remaining requests are only dropped when the
+        // runtime shuts down, so their directories must not be removed yet
+        if tokio::time::timeout(ABORT_TIMEOUT, job_queue.wait_idle())
+            .await
+            .is_err()
+        {
+            error!("Jobs are still running, skipping pruning of the jobs directory");
+            return Ok(());
+        }
+    }
+
+    info!("Pruning jobs directory");
+    clear_jobs_dir(&config)?;
+
+    Ok(())
+}
+
+/// Delete all files in the jobs directory.
+fn clear_jobs_dir(config: &Config) -> anyhow::Result<()> {
+    for dir in std::fs::read_dir(&config.jobs_dir).context("Failed to read jobs directory")? {
+        let path = dir.context("Failed to read jobs directory entry")?.path();
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+                .with_context(|| format!("Failed to remove directory {}", path.display()))?;
+        } else {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove file {}", path.display()))?;
+        }
+    }
     Ok(())
 }
 
 /// Periodically delete all programs that are not in use anymore.
-async fn prune_old_programs_loop(config: Arc<Config>, program_lock: Arc<KeyRwLock<Uuid>>) {
+async fn prune_old_programs_loop(
+    config: Arc<Config>,
+    program_lock: Arc<KeyRwLock<Uuid>>,
+    storage_usage: Arc<StorageUsage>,
+) {
     let mut interval = tokio::time::interval(Duration::from_secs(config.prune_programs_interval));
     loop {
         interval.tick().await;
-        if let Err(err) = prune_programs(&config, Arc::clone(&program_lock)).await {
+        if let Err(err) = prune_programs(
+            &config,
+            Arc::clone(&program_lock),
+            Arc::clone(&storage_usage),
+        )
+        .await
+        {
             error!("Pruning old programs failed: {err:#}");
         }
     }
 }
 
+/// Run the self-tests of all environments one after another.
+#[allow(clippy::too_many_arguments)]
+async fn selftest_environments(
+    config: Arc<Config>,
+    environments: Arc<environments::Environments>,
+    selftests: Arc<SelfTests>,
+    storage_usage: Arc<StorageUsage>,
+    program_lock: Arc<KeyRwLock<Uuid>>,
+    job_lock: Arc<KeyRwLock<Uuid>>,
+    cache_lock: Arc<CacheLock>,
+    job_queue: Arc<JobQueue>,
+    metrics: Arc<Metrics>,
+) {
+    info!("Running self-tests of {} environments", environments.len());
+    let priority = config.priority_classes[&config.default_priority];
+    let mut ids = environments.keys().collect::<Vec<_>>();
+    ids.sort_unstable();
+    let mut passed = 0;
+    for id in ids {
+        let _guard = match selftests.lock(id, now()).await {
+            Some(Ok(guard)) => guard,
+            // the environment has been tested on demand in the meantime
+            Some(Err(result)) => {
+                passed += result.passed as usize;
+                continue;
+            }
+            None => continue,
+        };
+        let reservation = match job_queue
+            .acquire(&metrics, selftest::demand(&config, priority))
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(err) => {
+                error!("Failed to run self-test of environment {id}: {err}");
+                continue;
+            }
+        };
+        let result = run_selftest(
+            Arc::clone(&config),
+            Arc::clone(&environments),
+            &storage_usage,
+            Arc::clone(&program_lock),
+            Arc::clone(&job_lock),
+            Arc::clone(&cache_lock),
+            id,
+            reservation.cores(),
+        )
+        .await;
+        passed += result.passed as usize;
+        selftests.set(id, result);
+    }
+    info!(
+        "{passed} of {} environments passed their self-test",
+        environments.len()
+    );
+}
+
 /// Create a directory if it does not exist yet. Return an error if the file
 /// exists, but is not a directory.
 async fn create_dir_if_not_exists(path: &Path) -> Result<(), anyhow::Error> {
@@ -1,7 +1,10 @@
 use std::sync::Arc;
 
 use poem::web::Data;
-use prometheus::{IntCounter, IntCounterVec, Opts, Registry, TextEncoder};
+use prometheus::{
+    Counter, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
 
 pub type MetricsData<'a> = Data<&'a Arc<Metrics>>;
 
@@ -9,6 +12,8 @@ pub struct Metrics {
     registry: Registry,
     pub requests: Requests,
     pub cache_hits: CacheHits,
+    pub rate_limits: RateLimits,
+    pub queue: Queue,
 }
 
 pub struct Requests {
@@ -18,6 +23,32 @@ pub struct Requests {
     pub build_run: IntCounterVec,
     pub build: IntCounterVec,
     pub run: IntCounter,
+    pub files: IntCounter,
+    pub test: IntCounter,
+    pub create_job: IntCounter,
+    pub get_job: IntCounter,
+    pub cancel_job: IntCounter,
+    pub check: IntCounterVec,
+    pub format: IntCounterVec,
+}
+
+pub struct RateLimits {
+    pub rejected: IntCounterVec,
+    pub cpu_time: Counter,
+    pub memory_time: Counter,
+}
+
+pub struct Queue {
+    pub length: IntGauge,
+    pub wait_time: Histogram,
+    pub rejected: IntCounterVec,
+    pub reserved: Reserved,
+}
+
+pub struct Reserved {
+    pub jobs: IntGauge,
+    pub cpus: IntGauge,
+    pub memory: IntGauge,
 }
 
 pub struct CacheHits {
@@ -33,6 +64,8 @@ impl Metrics {
         Ok(Self {
             requests: Requests::new(&registry)?,
             cache_hits: CacheHits::new(&registry)?,
+            rate_limits: RateLimits::new(&registry)?,
+            queue: Queue::new(&registry)?,
             registry,
         })
     }
@@ -59,12 +92,32 @@ impl Requests {
             &["environment"],
         )?;
         let run = IntCounter::new("run_requests", "Number of run requests")?;
+        let files = IntCounter::new("files_requests", "Number of files requests")?;
+        let test = IntCounter::new("test_requests", "Number of test requests")?;
+        let create_job = IntCounter::new("create_job_requests", "Number of create job requests")?;
+        let get_job = IntCounter::new("get_job_requests", "Number of get job requests")?;
+        let cancel_job = IntCounter::new("cancel_job_requests", "Number of cancel job requests")?;
+        let check = IntCounterVec::new(
+            Opts::new("check_requests", "Number of check requests"),
+            &["environment"],
+        )?;
+        let format = IntCounterVec::new(
+            Opts::new("format_requests", "Number of format requests"),
+            &["environment"],
+        )?;
         registry.register(Box::new(config.clone()))?;
         registry.register(Box::new(environments.clone()))?;
         registry.register(Box::new(resource_usage.clone()))?;
         registry.register(Box::new(build_run.clone()))?;
         registry.register(Box::new(build.clone()))?;
         registry.register(Box::new(run.clone()))?;
+        registry.register(Box::new(files.clone()))?;
+        registry.register(Box::new(test.clone()))?;
+        registry.register(Box::new(create_job.clone()))?;
+        registry.register(Box::new(get_job.clone()))?;
+        registry.register(Box::new(cancel_job.clone()))?;
+        registry.register(Box::new(check.clone()))?;
+        registry.register(Box::new(format.clone()))?;
 
         Ok(Self {
             config,
@@ -73,6 +126,13 @@ impl Requests {
             build_run,
             build,
             run,
+            files,
+            test,
+            create_job,
+            get_job,
+            cancel_job,
+            check,
+            format,
         })
     }
 }
@@ -106,6 +166,84 @@ impl CacheHits {
     }
 }
 
+impl RateLimits {
+    fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let rejected = IntCounterVec::new(
+            Opts::new(
+                "rate_limited_requests",
+                "Number of requests rejected by rate limits or resource budgets",
+            ),
+            &["limit"],
+        )?;
+        let cpu_time = Counter::new(
+            "cpu_time_seconds",
+            "Total cpu time used by programs in seconds",
+        )?;
+        let memory_time = Counter::new(
+            "memory_time_megabyte_seconds",
+            "Total memory-time used by programs in megabyte-seconds",
+        )?;
+        registry.register(Box::new(rejected.clone()))?;
+        registry.register(Box::new(cpu_time.clone()))?;
+        registry.register(Box::new(memory_time.clone()))?;
+
+        Ok(Self {
+            rejected,
+            cpu_time,
+            memory_time,
+        })
+    }
+}
+
+impl Queue {
+    fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let length = IntGauge::new(
+            "queue_length",
+            "Number of requests waiting for free job slots",
+        )?;
+        let wait_time = Histogram::with_opts(
+            HistogramOpts::new(
+                "queue_wait_seconds",
+                "Time requests have waited for free job slots in seconds",
+            )
+            .buckets(vec![0.0, 0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0]),
+        )?;
+        let rejected = IntCounterVec::new(
+            Opts::new(
+                "queue_rejected_requests",
+                "Number of requests rejected by the job queue",
+            ),
+            &["reason"],
+        )?;
+        registry.register(Box::new(length.clone()))?;
+        registry.register(Box::new(wait_time.clone()))?;
+        registry.register(Box::new(rejected.clone()))?;
+
+        Ok(Self {
+            length,
+            wait_time,
+            rejected,
+            reserved: Reserved::new(registry)?,
+        })
+    }
+}
+
+impl Reserved {
+    fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let jobs = IntGauge::new("reserved_jobs", "Number of running jobs")?;
+        let cpus = IntGauge::new("reserved_cpus", "Number of cpus reserved by running jobs")?;
+        let memory = IntGauge::new(
+            "reserved_memory_megabytes",
+            "Amount of memory reserved by running jobs in megabytes",
+        )?;
+        registry.register(Box::new(jobs.clone()))?;
+        registry.register(Box::new(cpus.clone()))?;
+        registry.register(Box::new(memory.clone()))?;
+
+        Ok(Self { jobs, cpus, memory })
+    }
+}
+
 #[poem::handler]
 pub fn endpoint(metrics: Data<&Arc<Metrics>>) -> anyhow::Result<String> {
     let encoder = TextEncoder::new();
@@ -0,0 +1,121 @@
+use std::io::{Cursor, Read};
+
+use flate2::read::GzDecoder;
+use sandkasten_client::schemas::programs::File;
+use thiserror::Error;
+
+use super::{check_path, check_paths, normalize_path};
+use crate::config::Config;
+
+/// Extract the source files from a tar, tar.gz or zip archive. Directories
+/// are implied by the paths of the files they contain, links are rejected.
+pub fn extract_archive(data: &[u8], config: &Config) -> Result<Vec<File>, ExtractArchiveError> {
+    let mut extractor = Extractor {
+        config,
+        files: Vec::new(),
+        size: 0,
+    };
+
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        extractor.extract_zip(data)?;
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        extractor.extract_tar(GzDecoder::new(data))?;
+    } else {
+        extractor.extract_tar(data)?;
+    }
+
+    if !check_paths(extractor.files.iter().map(|f| f.name.as_str())) {
+        return Err(ExtractArchiveError::ConflictingFilenames);
+    }
+
+    Ok(extractor.files)
+}
+
+struct Extractor<'a> {
+    config: &'a Config,
+    files: Vec<File>,
+    size: u64,
+}
+
+impl Extractor<'_> {
+    fn extract_tar(&mut self, reader: impl Read) -> Result<(), ExtractArchiveError> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+            match entry.header().entry_type() {
+                tar::EntryType::Directory => continue,
+                tar::EntryType::Regular | tar::EntryType::Continuous => {}
+                // pax/gnu extension headers are handled by the tar crate
+                tar::EntryType::XGlobalHeader => continue,
+                _ => return Err(ExtractArchiveError::UnsupportedEntry(path)),
+            }
+            self.add_file(&path, entry)?;
+        }
+        Ok(())
+    }
+
+    fn extract_zip(&mut self, data: &[u8]) -> Result<(), ExtractArchiveError> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i)?;
+            let path = entry.name().to_owned();
+            if entry.is_dir() {
+                continue;
+            }
+            if !entry.is_file() {
+                return Err(ExtractArchiveError::UnsupportedEntry(path));
+            }
+            self.add_file(&path, entry)?;
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: &str, reader: impl Read) -> Result<(), ExtractArchiveError> {
+        let name = normalize_path(path)
+            .filter(|name| check_path(name))
+            .ok_or_else(|| ExtractArchiveError::InvalidPath(path.into()))?;
+
+        if self.files.len() >= self.config.max_archive_files {
+            return Err(ExtractArchiveError::TooManyFiles(
+                self.config.max_archive_files,
+            ));
+        }
+
+        // never trust the sizes stored in the archive
+        let remaining = self.config.max_archive_extracted_size - self.size;
+        let mut content = Vec::new();
+        reader.take(remaining + 1).read_to_end(&mut content)?;
+        if content.len() as u64 > remaining {
+            return Err(ExtractArchiveError::TooLarge(
+                self.config.max_archive_extracted_size,
+            ));
+        }
+        self.size += content.len() as u64;
+
+        let content = String::from_utf8(content)
+            .map_err(|_| ExtractArchiveError::InvalidContent(name.clone()))?;
+        self.files.push(File { name, content });
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExtractArchiveError {
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("zip error: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+    #[error("invalid path {0:?}")]
+    InvalidPath(String),
+    #[error("unsupported entry {0:?}, only regular files and directories are allowed")]
+    UnsupportedEntry(String),
+    #[error("file {0:?} is not valid utf-8")]
+    InvalidContent(String),
+    #[error("conflicting filenames")]
+    ConflictingFilenames,
+    #[error("archive contains too many files (max {0})")]
+    TooManyFiles(usize),
+    #[error("extracted files are too large (max {0} bytes)")]
+    TooLarge(u64),
+}
@@ -1,14 +1,8 @@
-use std::{
-    ffi::OsStr,
-    os::unix::prelude::OsStrExt,
-    path::Path,
-    sync::Arc,
-    time::{self, UNIX_EPOCH},
-};
+use std::{collections::BTreeMap, ffi::OsStr, path::Path, sync::Arc};
 
 use key_rwlock::KeyRwLock;
 use sandkasten_client::schemas::programs::{
-    BuildRequest, BuildResult, LimitExceeded, Limits, RunResult,
+    BuildRequest, BuildResult, CompileError, Diagnostic, LimitExceeded, Limits, RunResult,
 };
 use sha2::{Digest, Sha256};
 use thiserror::Error;
@@ -16,31 +10,59 @@ use tokio::{fs, sync::OwnedRwLockReadGuard};
 use tracing::error;
 use uuid::Uuid;
 
-use super::{mounts_from_closure, with_tempdir};
+use super::{
+    cache::{CacheLock, CacheShard},
+    check_path, check_paths,
+    diagnostics::{parse_diagnostics, render_output},
+    directory_size,
+    manifest::{Manifest, ManifestError, MANIFEST_VERSION},
+    mounts_from_closures, now,
+    options::{option_env_vars, resolve_options, InvalidOptionError},
+    packages::{package_env_vars, select_packages, SelectPackagesError},
+    tenant::{StorageUsage, Tenant},
+    with_tempdir, write_file, RemoveDirOnDrop,
+};
 use crate::{
     config::Config,
-    environments::{Environment, Environments},
+    environments::{Environment, Environments, Package},
     sandbox::{Mount, MountType, RunConfig, RunError},
 };
 
 /// Build and store the uploaded program into a directory in the local fs.
 /// Return a unique identifier for the program.
+#[allow(clippy::too_many_arguments)]
 pub async fn build_program(
     config: Arc<Config>,
     environments: Arc<Environments>,
     data: BuildRequest,
+    tenant: Tenant<'_>,
+    storage_usage: &StorageUsage,
     program_lock: Arc<KeyRwLock<Uuid>>,
     job_lock: Arc<KeyRwLock<Uuid>>,
+    cache_lock: Arc<CacheLock>,
+    cores: Option<&[usize]>,
 ) -> Result<(BuildResult, OwnedRwLockReadGuard<()>), BuildProgramError> {
+    let environment_id = data.environment.clone();
     let env = environments
         .get(&data.environment)
         .ok_or(BuildProgramError::EnvironmentNotFound(
             data.environment.clone(),
         ))?;
 
+    let options = resolve_options(&env.build_options, &data.options)?;
+    let packages = select_packages(env, &data.packages)?;
+
+    // check if limits have been exceeded and use default values from config for
+    // empty fields
+    let compile_limits = data
+        .compile_limits
+        .check(&config.compile_limits)
+        .map_err(BuildProgramError::LimitsExceeded)?;
+
     // compute the program id by hashing the request data
     let hash = Sha256::new()
         .chain_update(postcard::to_stdvec(&(
+            tenant.name,
             &env.name,
             &env.version,
             &env.compile_script,
@@ -50,6 +72,11 @@ pub async fn build_program(
             &data.main_file,
             &data.files,
             &data.env_vars,
+            &options,
+            &packages
+                .iter()
+                .map(|(name, package)| (name, &package.closure))
+                .collect::<Vec<_>>(),
         ))?)
         .finalize();
     let id = Uuid::from_u128(
@@ -61,44 +88,140 @@ pub async fn build_program(
 
     // check if the program has already been built before
     let _guard = program_lock.read(id).await;
-    if let Some(cached) = get_cached_program(id, &path, &config, env).await? {
+    if let Some(cached) = get_cached_program(id, &path, &config, env, &compile_limits).await? {
         return Ok((cached, _guard));
     }
     drop(_guard);
 
     // acquire the write lock and start building the program
     let _guard = program_lock.write(id).await;
-    if let Some(cached) = get_cached_program(id, &path, &config, env).await? {
+    if let Some(cached) = get_cached_program(id, &path, &config, env, &compile_limits).await? {
         return Ok((cached, _guard.downgrade()));
     }
 
-    match store_in_directory(&config, data, env, &path, &job_lock).await {
-        Ok(result) => {
-            if let Some(result) = &result {
-                let serialized = postcard::to_stdvec(result)?;
-                fs::write(path.join("compile_result"), serialized).await?;
+    // the size of the program is only known after it has been built, so the
+    // size of the source files is reserved first, which prevents concurrent
+    // builds of the same tenant from exceeding its quota together
+    let source_size = data.main_file.content.len() as u64
+        + data
+            .files
+            .iter()
+            .map(|file| file.content.len() as u64)
+            .sum::<u64>();
+    let mut reservation = storage_usage
+        .reserve(tenant, source_size)
+        .map_err(BuildProgramError::StorageQuotaExceeded)?;
+
+    // programs with an unsupported manifest version and cached compile errors
+    // that might not occur with the requested limits are rebuilt from scratch
+    if let Some(manifest) = Manifest::load_supported(&path).await? {
+        storage_usage.release(manifest.tenant.as_deref(), manifest.size);
+    }
+    if fs::try_exists(&path).await? {
+        fs::remove_dir_all(&path).await?;
+    }
+
+    // make sure no partially built program is left behind if the build is
+    // cancelled
+    let cleanup = RemoveDirOnDrop(Some(&path));
+    match store_in_directory(StoreInDirectory {
+        config: &config,
+        build_request: data,
+        environment_id: &environment_id,
+        environment: env,
+        options: &options,
+        packages: &packages,
+        program_directory: &path,
+        compile_limits,
+        tenant: tenant.name,
+        job_lock: &job_lock,
+        cache_lock: &cache_lock,
+        cores,
+    })
+    .await
+    {
+        Ok(Stored {
+            main_file,
+            files,
+            compile_result,
+        }) => {
+            let now = now();
+            let mut manifest = Manifest {
+                version: MANIFEST_VERSION,
+                environment: environment_id.clone(),
+                environment_version: env.version.clone(),
+                run_script: env.run_script.clone(),
+                closure: env.closure.clone(),
+                test_script: env.test_script.clone(),
+                run_options: env.run_options.clone(),
+                packages: packages.clone(),
+                main_file,
+                files,
+                hash: Some(format!("{hash:x}")),
+                compile_result,
+                created_at: now,
+                last_run: now,
+                tenant: tenant.name.map(Into::into),
+                size: 0,
+            };
+            if !manifest.compilation_failed() {
+                let files = path.join("files");
+                manifest.size = tokio::task::spawn_blocking(move || directory_size(&files))
+                    .await
+                    .unwrap()?;
+            } else {
+                // only the manifest is kept for failed builds
+                manifest.size = serde_json::to_vec(&manifest)
+                    .map_err(ManifestError::from)?
+                    .len() as u64;
             }
-            fs::write(path.join("ok"), []).await?;
-            fs::write(
-                path.join("last_run"),
-                time::SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-                    .to_string(),
-            )
-            .await?;
+            if manifest.compilation_failed()
+                && !is_deterministic_failure(manifest.compile_result.as_ref().unwrap())
+            {
+                // the compile step might succeed with higher limits, so the
+                // error is not cached and the program directory is removed by
+                // the cleanup guard
+                let mut result = manifest.compile_result.unwrap();
+                return Err(BuildProgramError::CompilationFailed(CompileError {
+                    cached: false,
+                    diagnostics: diagnostics(env, Some(&mut result)),
+                    result,
+                }));
+            }
+            // if the quota is exceeded, the program directory is removed by the
+            // cleanup guard
+            reservation
+                .resize(manifest.size)
+                .map_err(BuildProgramError::StorageQuotaExceeded)?;
+            manifest.save(&path).await?;
+            cleanup.disarm();
+            reservation.commit();
+
+            if manifest.compilation_failed() {
+                // keep the manifest to cache the compile error, but the build
+                // outputs are not needed anymore
+                fs::remove_dir_all(path.join("files")).await?;
+                let mut result = manifest.compile_result.unwrap();
+                return Err(BuildProgramError::CompilationFailed(CompileError {
+                    cached: false,
+                    diagnostics: diagnostics(env, Some(&mut result)),
+                    result,
+                }));
+            }
+
             Ok((
                 BuildResult {
                     program_id: id,
                     ttl: config.program_ttl,
                     cached: false,
-                    compile_result: result,
+                    diagnostics: diagnostics(env, manifest.compile_result.as_mut()),
+                    compile_result: manifest.compile_result,
                 },
                 _guard.downgrade(),
             ))
         }
         Err(err) => {
+            cleanup.disarm();
             if fs::try_exists(&path).await? {
                 if let Err(err) = fs::remove_dir_all(&path).await {
                     error!("Failed to remove program directory {path:?}: {err:#}");
@@ -109,119 +232,179 @@ pub async fn build_program(
     }
 }
 
-/// Try to get a program that has been built previoulsy by id.
+/// Try to get a program that has been built previoulsy by id. Cached compile
+/// errors are only returned if the requested compile limits are not higher than
+/// the limits of the failed compile step.
 async fn get_cached_program(
     program_id: Uuid,
     path: &Path,
     config: &Config,
-    env: &Environment,
+    environment: &Environment,
+    compile_limits: &Limits,
 ) -> Result<Option<BuildResult>, BuildProgramError> {
-    if !fs::try_exists(path.join("ok")).await? {
+    let Some(mut manifest) = Manifest::load_supported(path).await? else {
         return Ok(None);
+    };
+
+    if manifest.compilation_failed() {
+        let mut result = manifest.compile_result.unwrap();
+        // the program hash does not include the compile limits, and a compile
+        // step that has failed because of a limit (e.g. the size of the tmpfs
+        // or the number of processes) might succeed with higher limits
+        if !compile_limits.within(&result.limits) {
+            return Ok(None);
+        }
+        return Err(BuildProgramError::CompilationFailed(CompileError {
+            cached: true,
+            diagnostics: diagnostics(environment, Some(&mut result)),
+            result,
+        }));
     }
 
-    let compile_result = if env.compile_script.is_some() {
-        let serialized = fs::read(path.join("compile_result")).await?;
-        Some(postcard::from_bytes(&serialized)?)
-    } else {
-        None
-    };
     Ok(Some(BuildResult {
         program_id,
         ttl: config.program_ttl,
         cached: true,
-        compile_result,
+        diagnostics: diagnostics(environment, manifest.compile_result.as_mut()),
+        compile_result: manifest.compile_result,
     }))
 }
 
+/// Check whether a failed compile step would fail again with the same inputs,
+/// i.e. it has not been cancelled or killed by a signal and has not reached its
+/// time or memory limit.
+fn is_deterministic_failure(result: &RunResult) -> bool {
+    result.termination_reason.is_none()
+        && result.status < 128
+        && result.resource_usage.time < result.limits.time * 1000
+        && result.resource_usage.memory < result.limits.memory * 1024
+}
+
+/// Extract the diagnostics from the results of a compile step if the
+/// environment declares the output format of its compiler, and make its output
+/// human-readable.
+fn diagnostics(
+    environment: &Environment,
+    compile_result: Option<&mut RunResult>,
+) -> Vec<Diagnostic> {
+    match (environment.diagnostics, compile_result) {
+        (Some(format), Some(result)) => {
+            let diagnostics = parse_diagnostics(format, result);
+            render_output(format, result);
+            diagnostics
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The metadata of a program that has been stored by [`store_in_directory`].
+struct Stored {
+    main_file: String,
+    files: Vec<String>,
+    compile_result: Option<RunResult>,
+}
+
 /// Build a program from a given [`BuildRequest`] and store the result at the
 /// given `path`.
 async fn store_in_directory(
-    config: &Config,
-    build_request: BuildRequest,
-    environment: &Environment,
-    program_directory: &Path,
-    job_lock: &KeyRwLock<Uuid>,
-) -> Result<Option<RunResult>, BuildProgramError> {
-    // check if limits have been exceeded and use default values from config for
-    // empty fields
-    let compile_limits = build_request
-        .compile_limits
-        .check(&config.compile_limits)
-        .map_err(BuildProgramError::LimitsExceeded)?;
-
-    // write metadata that is used later for running the program
+    StoreInDirectory {
+        config,
+        build_request,
+        environment_id,
+        environment,
+        options,
+        packages,
+        program_directory,
+        compile_limits,
+        tenant,
+        job_lock,
+        cache_lock,
+        cores,
+    }: StoreInDirectory<'_>,
+) -> Result<Stored, BuildProgramError> {
     fs::create_dir_all(program_directory.join("files")).await?;
-    fs::write(
-        program_directory.join("run_script"),
-        &environment.run_script,
-    )
-    .await?;
-    fs::write(
-        program_directory.join("closure"),
-        &environment.closure.as_os_str().as_bytes(),
-    )
-    .await?;
 
     let main_file_name = build_request
         .main_file
         .name
         .as_ref()
         .unwrap_or(&environment.default_main_file_name);
-    if build_request
-        .files
-        .iter()
-        .any(|f| f.name == *main_file_name)
-    {
+    let names = std::iter::once(main_file_name.as_str())
+        .chain(build_request.files.iter().map(|f| f.name.as_str()));
+    if let Some(name) = names.clone().find(|name| !check_path(name)) {
+        return Err(BuildProgramError::InvalidFileName(name.into()));
+    }
+    if !check_paths(names) {
         return Err(BuildProgramError::ConflictingFilenames);
     }
-    fs::write(program_directory.join("main_file"), main_file_name).await?;
+    let files = build_request
+        .files
+        .iter()
+        .map(|f| f.name.clone())
+        .collect::<Vec<_>>();
 
-    if let Some(compile_script) = &environment.compile_script {
+    let compile_result = if let Some(compile_script) = &environment.compile_script {
         // if the environment has a compile script, run it and write the output
         // to the program directory
-        Ok(Some(
+        Some(
             compile_program(CompileProgram {
                 config,
+                tenant,
                 job_lock,
+                cache_lock,
                 build_request: &build_request,
+                environment_id,
                 environment,
+                options,
+                packages,
                 compile_script,
                 program_directory,
                 compile_limits,
                 main_file_name,
+                cores,
             })
             .await?,
-        ))
+        )
     } else {
         // copy files to program dir
-        fs::write(
-            program_directory.join("files").join(main_file_name),
+        let files_directory = program_directory.join("files");
+        write_file(
+            &files_directory,
+            main_file_name,
             build_request.main_file.content,
         )
         .await?;
         for file in build_request.files {
-            fs::write(
-                program_directory.join("files").join(file.name),
-                file.content,
-            )
-            .await?;
+            write_file(&files_directory, &file.name, file.content).await?;
         }
-        Ok(None)
-    }
+        None
+    };
+
+    Ok(Stored {
+        main_file: main_file_name.clone(),
+        files,
+        compile_result,
+    })
 }
 
-// Run the compile script of an environment to build a program
+// Run the compile script of an environment to build a program. A non-zero exit
+// code of the compile script is not treated as an error here.
 async fn compile_program(
     CompileProgram {
         config,
+        tenant,
         job_lock,
+        cache_lock,
         build_request,
+        environment_id,
         environment,
+        options,
+        packages,
         compile_script,
         program_directory,
         compile_limits,
         main_file_name,
+        cores,
     }: CompileProgram<'_>,
 ) -> Result<RunResult, BuildProgramError> {
     let job_id = Uuid::new_v4();
@@ -231,25 +414,45 @@ async fn compile_program(
     let args = std::iter::once(main_file_name)
         .chain(build_request.files.iter().map(|f| f.name.as_str()))
         .collect::<Vec<_>>();
+    let option_env_vars = option_env_vars(options);
+    let package_env_vars = package_env_vars(packages);
     let envvars = build_request
         .env_vars
         .iter()
         .map(|e| (e.name.as_str(), e.value.as_str()))
+        .chain(option_env_vars.iter().map(|(k, v)| (k.as_str(), *v)))
+        .chain(
+            package_env_vars
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        )
         .collect::<Vec<_>>();
 
-    with_tempdir(config.jobs_dir.join(job_id.to_string()), |tmpdir| async {
+    // acquire a shard of the environment's compile cache so that concurrent
+    // compile steps never write to the same cache directory
+    let cache = match &environment.compile_cache {
+        Some(compile_cache) => Some((
+            CacheShard::acquire(config, cache_lock, tenant, environment_id).await?,
+            compile_cache,
+        )),
+        None => None,
+    };
+
+    let result = with_tempdir(config.jobs_dir.join(job_id.to_string()), |tmpdir| async {
         let tmpdir = { tmpdir }; // move tmpdir into async block
 
         // create working directory for compile script and copy files from build request
         // into it
-        fs::create_dir_all(tmpdir.join("box")).await?;
-        fs::write(
-            tmpdir.join("box").join(main_file_name),
+        let box_directory = tmpdir.join("box");
+        fs::create_dir_all(&box_directory).await?;
+        write_file(
+            &box_directory,
+            main_file_name,
             &build_request.main_file.content,
         )
         .await?;
         for file in &build_request.files {
-            fs::write(tmpdir.join("box").join(&file.name), &file.content).await?;
+            write_file(&box_directory, &file.name, &file.content).await?;
         }
 
         let mut mounts = vec![
@@ -272,7 +475,22 @@ async fn compile_program(
                 },
             },
         ];
-        mounts.extend(mounts_from_closure(&environment.closure).await?);
+        if let Some((shard, compile_cache)) = &cache {
+            mounts.push(Mount {
+                dest: OsStr::new(&compile_cache.path).into(),
+                typ: MountType::ReadWrite {
+                    src: shard.path().as_os_str().into(),
+                },
+            });
+        }
+        mounts.extend(
+            mounts_from_closures(
+                &std::iter::once(environment.closure.as_path())
+                    .chain(packages.values().map(|p| p.closure.as_path()))
+                    .collect::<Vec<_>>(),
+            )
+            .await?,
+        );
 
         // run the compile script
         RunConfig {
@@ -287,30 +505,50 @@ async fn compile_program(
             stdin: None,
             mounts: &mounts,
             limits: compile_limits,
+            cores,
         }
         .run()
         .await
-        .map_err(Into::into)
-    })
-    .await?
-    .and_then(|result| {
-        if result.status == 0 {
-            Ok(result)
-        } else {
-            Err(BuildProgramError::CompilationFailed(result))
-        }
     })
+    .await?;
+
+    if let Some((shard, _)) = cache {
+        shard.release(result.as_ref().ok()).await;
+    }
+
+    Ok(result?)
+}
+
+struct StoreInDirectory<'a> {
+    config: &'a Config,
+    build_request: BuildRequest,
+    environment_id: &'a str,
+    environment: &'a Environment,
+    options: &'a BTreeMap<String, String>,
+    packages: &'a BTreeMap<String, Package>,
+    program_directory: &'a Path,
+    compile_limits: Limits,
+    tenant: Option<&'a str>,
+    job_lock: &'a KeyRwLock<Uuid>,
+    cache_lock: &'a CacheLock,
+    cores: Option<&'a [usize]>,
 }
 
 struct CompileProgram<'a> {
     config: &'a Config,
+    tenant: Option<&'a str>,
     job_lock: &'a KeyRwLock<Uuid>,
+    cache_lock: &'a CacheLock,
     build_request: &'a BuildRequest,
+    environment_id: &'a str,
     environment: &'a Environment,
+    options: &'a BTreeMap<String, String>,
+    packages: &'a BTreeMap<String, Package>,
     compile_script: &'a str,
     program_directory: &'a Path,
     compile_limits: Limits,
     main_file_name: &'a str,
+    cores: Option<&'a [usize]>,
 }
 
 #[derive(Debug, Error)]
@@ -321,12 +559,22 @@ pub enum BuildProgramError {
     IOError(#[from] std::io::Error),
     #[error("run error: {0}")]
     RunError(#[from] RunError),
-    #[error("compilation failed (exit code {})", .0.status)]
-    CompilationFailed(RunResult),
+    #[error("compilation failed (exit code {})", .0.result.status)]
+    CompilationFailed(CompileError),
     #[error("postcard error: {0}")]
     PostcardError(#[from] postcard::Error),
+    #[error("manifest error: {0}")]
+    ManifestError(#[from] ManifestError),
+    #[error("invalid file name {0:?}")]
+    InvalidFileName(String),
     #[error("conflicting filenames")]
     ConflictingFilenames,
+    #[error("invalid options: {0}")]
+    InvalidOptions(#[from] InvalidOptionError),
+    #[error("invalid packages: {0}")]
+    InvalidPackages(#[from] SelectPackagesError),
     #[error("limits exceeded: {0:?}")]
     LimitsExceeded(Vec<LimitExceeded>),
+    #[error("storage quota exceeded (max {0} bytes)")]
+    StorageQuotaExceeded(u64),
 }
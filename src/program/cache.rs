@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+use key_rwlock::KeyRwLock;
+use sandkasten_client::schemas::programs::RunResult;
+use sha2::{Digest, Sha256};
+use tokio::{fs, runtime::Handle, sync::OwnedRwLockWriteGuard};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use super::directory_size;
+use crate::config::Config;
+
+/// Lock for the shards of the compile caches, identified by tenant name,
+/// environment id and shard index.
+pub type CacheLock = KeyRwLock<(Option<String>, String, usize)>;
+
+/// A shard of an environment's compile cache that is exclusively held by a
+/// single compile step. Each tenant has its own compile caches, so that
+/// programs of one tenant can never read or poison the cache entries of
+/// another tenant. If authentication is disabled, all clients share the
+/// compile caches of the default tenant.
+///
+/// The shard is cleared if it is dropped without being released (e.g. because
+/// the compile step has been cancelled), as the cache might have been left in
+/// an inconsistent state.
+pub struct CacheShard {
+    path: PathBuf,
+    max_size: u64,
+    /// `None` once the shard has been released.
+    guard: Option<OwnedRwLockWriteGuard<()>>,
+}
+
+impl CacheShard {
+    /// Acquire a shard of the compile cache of an environment for a tenant.
+    /// Prefer shards that are currently not in use and only wait for a busy
+    /// shard if all of them are in use.
+    pub async fn acquire(
+        config: &Config,
+        cache_lock: &CacheLock,
+        tenant: Option<&str>,
+        environment: &str,
+    ) -> Result<Self, std::io::Error> {
+        let shards = config.compile_cache_shards;
+        let key = |shard| (tenant.map(Into::into), environment.into(), shard);
+        let mut acquired = None;
+        for shard in 0..shards {
+            if let Ok(guard) = cache_lock.try_write(key(shard)).await {
+                acquired = Some((shard, guard));
+                break;
+            }
+        }
+        let (shard, guard) = match acquired {
+            Some(acquired) => acquired,
+            None => {
+                let shard = (Uuid::new_v4().as_u128() % shards as u128) as usize;
+                (shard, cache_lock.write(key(shard)).await)
+            }
+        };
+
+        // tenant names are hashed, as they may contain arbitrary characters
+        let tenant = match tenant {
+            Some(name) => format!("{:x}", Sha256::digest(name)),
+            None => "default".into(),
+        };
+        let path = config
+            .compile_cache_dir
+            .join(environment)
+            .join(tenant)
+            .join(shard.to_string());
+        fs::create_dir_all(&path).await?;
+
+        Ok(Self {
+            path,
+            max_size: config.compile_cache_max_size,
+            guard: Some(guard),
+        })
+    }
+
+    /// The directory of the shard on the host.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Release the shard after the compile step has finished with the given
+    /// result. The shard is cleared if it has grown beyond the configured size
+    /// limit or if the compile step did not finish properly (e.g. because it
+    /// has been killed after exceeding its time limit) and might have left the
+    /// cache in an inconsistent state.
+    pub async fn release(mut self, result: Option<&RunResult>) {
+        let _guard = self.guard.take();
+        let clear = result.is_none_or(|result| result.status >= 128);
+        let size = tokio::task::spawn_blocking({
+            let path = self.path.clone();
+            move || directory_size(&path)
+        })
+        .await
+        .unwrap();
+        let clear = match size {
+            Ok(size) => clear || size > self.max_size,
+            Err(err) => {
+                error!("Failed to compute size of {}: {err:#}", self.path.display());
+                true
+            }
+        };
+        if !clear {
+            return;
+        }
+
+        debug!("Clearing compile cache shard {}", self.path.display());
+        if let Err(err) = fs::remove_dir_all(&self.path).await {
+            error!(
+                "Failed to clear compile cache shard {}: {err:#}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+impl Drop for CacheShard {
+    fn drop(&mut self) {
+        let Some(guard) = self.guard.take() else {
+            return;
+        };
+        // the shard is cleared in the background, but it cannot be acquired by
+        // another compile step until it has been cleared
+        let path = std::mem::take(&mut self.path);
+        let clear = move || {
+            debug!("Clearing compile cache shard {}", path.display());
+            if let Err(err) = std::fs::remove_dir_all(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    error!(
+                        "Failed to clear compile cache shard {}: {err:#}",
+                        path.display()
+                    );
+                }
+            }
+            drop(guard);
+        };
+        match Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(clear)),
+            Err(_) => clear(),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{ffi::OsStr, sync::Arc};
+
+use key_rwlock::KeyRwLock;
+use sandkasten_client::schemas::programs::{BuildRequest, CheckResult, LimitExceeded};
+use thiserror::Error;
+use tokio::fs;
+use uuid::Uuid;
+
+use super::{
+    check_path, check_paths,
+    diagnostics::{parse_diagnostics, render_output},
+    mounts_from_closures,
+    options::{option_env_vars, resolve_options, InvalidOptionError},
+    packages::{package_env_vars, select_packages, SelectPackagesError},
+    with_tempdir, write_file,
+};
+use crate::{
+    config::Config,
+    environments::Environments,
+    sandbox::{Mount, MountType, RunConfig, RunError},
+};
+
+/// Run the check script of an environment (e.g. a syntax check or a linter) on
+/// the source files of a build request. In contrast to a build, nothing is
+/// stored in the programs directory and no caches are used or updated.
+pub async fn check_program(
+    config: Arc<Config>,
+    environments: Arc<Environments>,
+    data: BuildRequest,
+    job_lock: Arc<KeyRwLock<Uuid>>,
+    cores: Option<&[usize]>,
+) -> Result<CheckResult, CheckProgramError> {
+    let env = environments
+        .get(&data.environment)
+        .ok_or_else(|| CheckProgramError::EnvironmentNotFound(data.environment.clone()))?;
+    let check_script = env
+        .check_script
+        .as_deref()
+        .ok_or(CheckProgramError::CheckNotSupported)?;
+
+    // check if limits have been exceeded and use default values from config for
+    // empty fields
+    let compile_limits = data
+        .compile_limits
+        .check(&config.compile_limits)
+        .map_err(CheckProgramError::LimitsExceeded)?;
+
+    let options = resolve_options(&env.build_options, &data.options)?;
+    let packages = select_packages(env, &data.packages)?;
+
+    let main_file_name = data
+        .main_file
+        .name
+        .as_ref()
+        .unwrap_or(&env.default_main_file_name);
+    let names =
+        std::iter::once(main_file_name.as_str()).chain(data.files.iter().map(|f| f.name.as_str()));
+    if let Some(name) = names.clone().find(|name| !check_path(name)) {
+        return Err(CheckProgramError::InvalidFileName(name.into()));
+    }
+    if !check_paths(names.clone()) {
+        return Err(CheckProgramError::ConflictingFilenames);
+    }
+
+    // collect command line arguments and environment variables from the request
+    let args = names.collect::<Vec<_>>();
+    let option_env_vars = option_env_vars(&options);
+    let package_env_vars = package_env_vars(&packages);
+    let envvars = data
+        .env_vars
+        .iter()
+        .map(|e| (e.name.as_str(), e.value.as_str()))
+        .chain(option_env_vars.iter().map(|(k, v)| (k.as_str(), *v)))
+        .chain(
+            package_env_vars
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        )
+        .collect::<Vec<_>>();
+
+    let job_id = Uuid::new_v4();
+    let _guard = job_lock.write(job_id).await;
+    let mut result = with_tempdir(config.jobs_dir.join(job_id.to_string()), |tmpdir| async {
+        let tmpdir = { tmpdir }; // move tmpdir into async block
+
+        // create working directory for the check script and copy files from the
+        // request into it
+        let box_directory = tmpdir.join("box");
+        fs::create_dir_all(&box_directory).await?;
+        write_file(&box_directory, main_file_name, &data.main_file.content).await?;
+        for file in &data.files {
+            write_file(&box_directory, &file.name, &file.content).await?;
+        }
+
+        // the working directory is discarded afterwards, so tools that write
+        // next to the source files (e.g. `__pycache__`) are allowed to do so
+        let mut mounts = vec![
+            Mount {
+                dest: OsStr::new("/box").into(),
+                typ: MountType::ReadWrite {
+                    src: box_directory.into_os_string().into(),
+                },
+            },
+            Mount {
+                dest: OsStr::new("/tmp").into(),
+                typ: MountType::Temp {
+                    size: compile_limits.tmpfs,
+                },
+            },
+        ];
+        mounts.extend(
+            mounts_from_closures(
+                &std::iter::once(env.closure.as_path())
+                    .chain(packages.values().map(|p| p.closure.as_path()))
+                    .collect::<Vec<_>>(),
+            )
+            .await?,
+        );
+
+        // run the check script
+        RunConfig {
+            nsjail: &config.nsjail_path,
+            time: &config.time_path,
+            use_cgroup: config.use_cgroup,
+            tmpdir: &tmpdir,
+            program: check_script,
+            args: &args,
+            envvars: &envvars,
+            cwd: "/box",
+            stdin: None,
+            mounts: &mounts,
+            limits: compile_limits,
+            cores,
+        }
+        .run()
+        .await
+    })
+    .await??;
+
+    let diagnostics = match env.diagnostics {
+        Some(format) => {
+            let diagnostics = parse_diagnostics(format, &result);
+            render_output(format, &mut result);
+            diagnostics
+        }
+        None => Vec::new(),
+    };
+    Ok(CheckResult {
+        diagnostics,
+        result,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum CheckProgramError {
+    #[error("could not find environment {0}")]
+    EnvironmentNotFound(String),
+    #[error("environment does not have a check script")]
+    CheckNotSupported,
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("run error: {0}")]
+    RunError(#[from] RunError),
+    #[error("invalid file name {0:?}")]
+    InvalidFileName(String),
+    #[error("conflicting filenames")]
+    ConflictingFilenames,
+    #[error("invalid options: {0}")]
+    InvalidOptions(#[from] InvalidOptionError),
+    #[error("invalid packages: {0}")]
+    InvalidPackages(#[from] SelectPackagesError),
+    #[error("limits exceeded: {0:?}")]
+    LimitsExceeded(Vec<LimitExceeded>),
+}
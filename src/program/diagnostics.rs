@@ -0,0 +1,225 @@
+use sandkasten_client::schemas::programs::{Diagnostic, RunResult, Severity};
+use serde::Deserialize;
+
+/// The output format of a compiler that is used to extract [`Diagnostic`]s
+/// from the output of a compile step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticsFormat {
+    /// `file:line:column: severity: message [-Wflag]` as reported by gcc and
+    /// clang.
+    Gcc,
+    /// The json output of rustc (`--error-format=json`). The messages are
+    /// rendered back into their human-readable form after they have been
+    /// parsed (see [`render_output`]).
+    RustcJson,
+    /// `file:line: severity: message` followed by the source line and a caret
+    /// that marks the column, as reported by javac.
+    Javac,
+    /// `file(line,column): severity TS1234: message` as reported by tsc.
+    Tsc,
+    /// `file:line[:column]: message` with an optional severity prefix.
+    Generic,
+}
+
+/// Extract the diagnostics from the stderr and stdout output of a compile step.
+pub fn parse_diagnostics(format: DiagnosticsFormat, result: &RunResult) -> Vec<Diagnostic> {
+    [&result.stderr, &result.stdout]
+        .into_iter()
+        .flat_map(|output| parse_output(format, output))
+        .collect()
+}
+
+/// Extract the diagnostics from the output of a compiler.
+pub fn parse_output(format: DiagnosticsFormat, output: &str) -> Vec<Diagnostic> {
+    match format {
+        DiagnosticsFormat::Gcc => output.lines().filter_map(parse_gcc_line).collect(),
+        DiagnosticsFormat::RustcJson => output.lines().filter_map(parse_rustc_line).collect(),
+        DiagnosticsFormat::Javac => parse_javac(output),
+        DiagnosticsFormat::Tsc => output.lines().filter_map(parse_tsc_line).collect(),
+        DiagnosticsFormat::Generic => output.lines().filter_map(parse_generic_line).collect(),
+    }
+}
+
+/// Replace machine-readable compiler output in the stderr output of a compile
+/// step with the human-readable messages it contains, so that stderr can be
+/// shown to users as is. Must be called after [`parse_diagnostics`].
+pub fn render_output(format: DiagnosticsFormat, result: &mut RunResult) {
+    if format == DiagnosticsFormat::RustcJson {
+        result.stderr = render_rustc_output(&result.stderr);
+    }
+}
+
+fn parse_gcc_line(line: &str) -> Option<Diagnostic> {
+    let (file, line, column, rest) = split_location(line)?;
+    let (severity, message) = split_severity(rest)?;
+    // warnings end with the flag that enabled them, e.g. `[-Wunused-variable]`
+    let (message, code) = match message.strip_suffix(']').and_then(|m| m.rsplit_once(" [-")) {
+        Some((message, flag)) => (message, Some(format!("-{flag}"))),
+        None => (message, None),
+    };
+    Some(Diagnostic {
+        file: Some(normalize_file(file)),
+        line: Some(line),
+        column,
+        severity,
+        message: message.into(),
+        code,
+    })
+}
+
+fn parse_rustc_line(line: &str) -> Option<Diagnostic> {
+    #[derive(Deserialize)]
+    struct Message {
+        message: String,
+        code: Option<Code>,
+        level: String,
+        spans: Vec<Span>,
+    }
+    #[derive(Deserialize)]
+    struct Code {
+        code: String,
+    }
+    #[derive(Deserialize)]
+    struct Span {
+        file_name: String,
+        line_start: u32,
+        column_start: u32,
+        is_primary: bool,
+    }
+
+    let message = serde_json::from_str::<Message>(line).ok()?;
+    // messages without a location are summaries like "aborting due to 2
+    // previous errors"
+    let span = message.spans.iter().find(|span| span.is_primary)?;
+    Some(Diagnostic {
+        file: Some(normalize_file(&span.file_name)),
+        line: Some(span.line_start),
+        column: Some(span.column_start),
+        severity: parse_severity(&message.level)?,
+        message: message.message,
+        code: message.code.map(|code| code.code),
+    })
+}
+
+fn render_rustc_output(output: &str) -> String {
+    #[derive(Deserialize)]
+    struct Message {
+        rendered: Option<String>,
+    }
+
+    let mut out = String::new();
+    for line in output.lines() {
+        match serde_json::from_str::<Message>(line) {
+            // json messages without a rendered form (e.g. artifact
+            // notifications) are not meant for users
+            Ok(message) => out.extend(message.rendered),
+            // e.g. panics of the compiler or output of the linker
+            Err(_) => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+fn parse_javac(output: &str) -> Vec<Diagnostic> {
+    let mut out = Vec::new();
+    let mut lines = output.lines();
+    while let Some(line) = lines.next() {
+        let Some((file, line, None, rest)) = split_location(line) else {
+            continue;
+        };
+        let Some((severity, message)) = split_severity(rest) else {
+            continue;
+        };
+        // the source line is followed by a line that marks the column
+        let mut column = None;
+        if let Some(caret) = lines.clone().nth(1) {
+            if caret.trim() == "^" {
+                column = caret.find('^').map(|i| i as u32 + 1);
+                lines.nth(1);
+            }
+        }
+        out.push(Diagnostic {
+            file: Some(normalize_file(file)),
+            line: Some(line),
+            column,
+            severity,
+            message: message.into(),
+            code: None,
+        });
+    }
+    out
+}
+
+fn parse_tsc_line(line: &str) -> Option<Diagnostic> {
+    let (file, rest) = line.split_once('(')?;
+    let (location, rest) = rest.split_once("): ")?;
+    let (line, column) = location.split_once(',')?;
+    let (severity, rest) = rest.split_once(' ')?;
+    let (code, message) = rest.split_once(": ")?;
+    Some(Diagnostic {
+        file: Some(normalize_file(file)),
+        line: Some(line.parse().ok()?),
+        column: Some(column.parse().ok()?),
+        severity: parse_severity(severity)?,
+        message: message.into(),
+        code: code.starts_with("TS").then(|| code.into()),
+    })
+}
+
+fn parse_generic_line(line: &str) -> Option<Diagnostic> {
+    let (file, line, column, rest) = split_location(line)?;
+    let (severity, message) = split_severity(rest).unwrap_or((Severity::Error, rest.trim()));
+    Some(Diagnostic {
+        file: Some(normalize_file(file)),
+        line: Some(line),
+        column,
+        severity,
+        message: message.into(),
+        code: None,
+    })
+}
+
+/// Split a line of the form `file:line[:column]:rest`.
+fn split_location(line: &str) -> Option<(&str, u32, Option<u32>, &str)> {
+    let (file, rest) = line.split_once(':')?;
+    if file.is_empty() || file.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let (line, rest) = rest.split_once(':')?;
+    let line = line.parse().ok()?;
+    match rest.split_once(':') {
+        Some((column, after)) => match column.parse() {
+            Ok(column) => Some((file, line, Some(column), after)),
+            Err(_) => Some((file, line, None, rest)),
+        },
+        None => Some((file, line, None, rest)),
+    }
+}
+
+/// Split a message of the form ` severity: message`.
+fn split_severity(rest: &str) -> Option<(Severity, &str)> {
+    let (severity, message) = rest.trim_start().split_once(": ")?;
+    Some((parse_severity(severity)?, message.trim()))
+}
+
+fn parse_severity(severity: &str) -> Option<Severity> {
+    Some(match severity {
+        "error" | "fatal error" => Severity::Error,
+        "warning" => Severity::Warning,
+        "note" | "message" => Severity::Note,
+        "help" => Severity::Help,
+        _ => return None,
+    })
+}
+
+/// Make paths relative to the working directory of the build step.
+fn normalize_file(file: &str) -> String {
+    file.strip_prefix("/box/")
+        .or_else(|| file.strip_prefix("./"))
+        .unwrap_or(file)
+        .into()
+}
@@ -0,0 +1,76 @@
+use thiserror::Error;
+use tokio::{io::AsyncRead, sync::OwnedRwLockReadGuard};
+use tokio_util::io::SyncIoBridge;
+use tracing::error;
+use uuid::Uuid;
+
+use super::{
+    directory_size,
+    manifest::{Manifest, ManifestError},
+    tenant::Tenant,
+};
+use crate::config::Config;
+
+/// The size of the buffer between the thread that creates the archive and the
+/// response body.
+const ARCHIVE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Create a tar archive of the files a program's compile step has written into
+/// `/program`. The archive is created in the background and streamed through
+/// the returned reader. The program lock is released once the archive is
+/// complete.
+pub async fn archive_program_files(
+    config: &Config,
+    program_id: Uuid,
+    tenant: Tenant<'_>,
+    program_guard: OwnedRwLockReadGuard<()>,
+) -> Result<impl AsyncRead, ArchiveFilesError> {
+    let path = config.programs_dir.join(program_id.to_string());
+    if Manifest::load_supported(&path)
+        .await?
+        .filter(|manifest| tenant.owns(manifest) && !manifest.compilation_failed())
+        .is_none()
+    {
+        return Err(ArchiveFilesError::ProgramNotFound);
+    }
+
+    let files = path.join("files");
+    let size = tokio::task::spawn_blocking({
+        let files = files.clone();
+        move || directory_size(&files)
+    })
+    .await
+    .unwrap()?;
+    if size > config.max_files_archive_size {
+        return Err(ArchiveFilesError::TooLarge(config.max_files_archive_size));
+    }
+
+    let (reader, writer) = tokio::io::duplex(ARCHIVE_BUFFER_SIZE);
+    let writer = SyncIoBridge::new(writer);
+    tokio::task::spawn_blocking(move || {
+        let _program_guard = program_guard;
+        let mut builder = tar::Builder::new(writer);
+        builder.follow_symlinks(false);
+        if let Err(err) = builder
+            .append_dir_all(".", &files)
+            .and_then(|_| builder.into_inner())
+        {
+            // the client sees a truncated archive in this case
+            error!("Failed to archive files of program {program_id}: {err:#}");
+        }
+    });
+
+    Ok(reader)
+}
+
+#[derive(Debug, Error)]
+pub enum ArchiveFilesError {
+    #[error("program does not exist")]
+    ProgramNotFound,
+    #[error("files are too large to be archived (max {0} bytes)")]
+    TooLarge(u64),
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("manifest error: {0}")]
+    ManifestError(#[from] ManifestError),
+}
@@ -0,0 +1,153 @@
+use std::{ffi::OsStr, sync::Arc};
+
+use key_rwlock::KeyRwLock;
+use sandkasten_client::schemas::programs::{
+    File, FormatRequest, FormatResult, LimitExceeded, ResourceUsage, RunResult,
+};
+use thiserror::Error;
+use tokio::fs;
+use uuid::Uuid;
+
+use super::{
+    check_path, check_paths, mounts_from_closures, read_sandbox_file, with_tempdir, write_file,
+};
+use crate::{
+    config::Config,
+    environments::Environments,
+    sandbox::{Mount, MountType, RunConfig, RunError},
+};
+
+/// Run the format script of an environment on the source files of a request
+/// and return the reformatted files together with the resources used by the
+/// format script. The format script receives the paths of all files as
+/// arguments and is expected to reformat them in place.
+pub async fn format_program(
+    config: Arc<Config>,
+    environments: Arc<Environments>,
+    data: FormatRequest,
+    job_lock: Arc<KeyRwLock<Uuid>>,
+    cores: Option<&[usize]>,
+) -> Result<(FormatResult, ResourceUsage), FormatProgramError> {
+    let env = environments
+        .get(&data.environment)
+        .ok_or_else(|| FormatProgramError::EnvironmentNotFound(data.environment.clone()))?;
+    let format_script = env
+        .format_script
+        .as_deref()
+        .ok_or(FormatProgramError::FormatNotSupported)?;
+
+    // check if limits have been exceeded and use default values from config for
+    // empty fields
+    let limits = data
+        .limits
+        .check(&config.compile_limits)
+        .map_err(FormatProgramError::LimitsExceeded)?;
+
+    let main_file_name = data
+        .main_file
+        .name
+        .as_ref()
+        .unwrap_or(&env.default_main_file_name);
+    let names =
+        std::iter::once(main_file_name.as_str()).chain(data.files.iter().map(|f| f.name.as_str()));
+    if let Some(name) = names.clone().find(|name| !check_path(name)) {
+        return Err(FormatProgramError::InvalidFileName(name.into()));
+    }
+    if !check_paths(names.clone()) {
+        return Err(FormatProgramError::ConflictingFilenames);
+    }
+    let args = names.collect::<Vec<_>>();
+
+    let job_id = Uuid::new_v4();
+    let _guard = job_lock.write(job_id).await;
+    with_tempdir(config.jobs_dir.join(job_id.to_string()), |tmpdir| async {
+        let tmpdir = { tmpdir }; // move tmpdir into async block
+
+        // create working directory for the format script and copy files from
+        // the request into it
+        let box_directory = tmpdir.join("box");
+        fs::create_dir_all(&box_directory).await?;
+        write_file(&box_directory, main_file_name, &data.main_file.content).await?;
+        for file in &data.files {
+            write_file(&box_directory, &file.name, &file.content).await?;
+        }
+
+        let mut mounts = vec![
+            Mount {
+                dest: OsStr::new("/box").into(),
+                typ: MountType::ReadWrite {
+                    src: box_directory.clone().into_os_string().into(),
+                },
+            },
+            Mount {
+                dest: OsStr::new("/tmp").into(),
+                typ: MountType::Temp { size: limits.tmpfs },
+            },
+        ];
+        mounts.extend(mounts_from_closures(&[env.closure.as_path()]).await?);
+
+        // run the format script
+        let max_size = limits.filesize * 1024 * 1024;
+        let result = RunConfig {
+            nsjail: &config.nsjail_path,
+            time: &config.time_path,
+            use_cgroup: config.use_cgroup,
+            tmpdir: &tmpdir,
+            program: format_script,
+            args: &args,
+            envvars: &[],
+            cwd: "/box",
+            stdin: None,
+            mounts: &mounts,
+            limits,
+            cores,
+        }
+        .run()
+        .await?;
+        if result.status != 0 {
+            return Err(FormatProgramError::FormatFailed(result));
+        }
+
+        // read the reformatted files, which must not have been replaced by
+        // symlinks or anything else than regular utf-8 files
+        let read = |name: &str| {
+            let box_directory = &box_directory;
+            let result = &result;
+            let name = name.to_owned();
+            async move {
+                let content = read_sandbox_file(box_directory, &name, max_size)
+                    .await?
+                    .and_then(|content| String::from_utf8(content).ok())
+                    .ok_or_else(|| FormatProgramError::FormatFailed(result.clone()))?;
+                Ok::<_, FormatProgramError>(File { name, content })
+            }
+        };
+        let main_file = read(main_file_name).await?;
+        let mut files = Vec::with_capacity(data.files.len());
+        for file in &data.files {
+            files.push(read(&file.name).await?);
+        }
+        Ok((FormatResult { main_file, files }, result.resource_usage))
+    })
+    .await?
+}
+
+#[derive(Debug, Error)]
+pub enum FormatProgramError {
+    #[error("could not find environment {0}")]
+    EnvironmentNotFound(String),
+    #[error("environment does not have a format script")]
+    FormatNotSupported,
+    #[error("format script failed")]
+    FormatFailed(RunResult),
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("run error: {0}")]
+    RunError(#[from] RunError),
+    #[error("invalid file name {0:?}")]
+    InvalidFileName(String),
+    #[error("conflicting filenames")]
+    ConflictingFilenames,
+    #[error("limits exceeded: {0:?}")]
+    LimitsExceeded(Vec<LimitExceeded>),
+}
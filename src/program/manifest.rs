@@ -0,0 +1,273 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use sandkasten_client::schemas::{environments::EnvironmentOption, programs::RunResult};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::fs;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    environments::{Environments, Package},
+};
+
+/// The version of the manifest format written by this version of Sandkasten.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// The name of the manifest file in a program directory.
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Files of the legacy program directory layout that have been replaced by the
+/// manifest.
+const LEGACY_FILES: &[&str] = &[
+    "run_script",
+    "closure",
+    "main_file",
+    "compile_result",
+    "ok",
+    "last_run",
+];
+
+/// Metadata of a program that is stored next to its `files` directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The version of the manifest format.
+    pub version: u32,
+    /// The id of the environment the program has been built in.
+    pub environment: String,
+    /// The version of the environment the program has been built in.
+    pub environment_version: String,
+    /// The run script of the environment.
+    pub run_script: String,
+    /// The closure file of the environment.
+    pub closure: PathBuf,
+    /// The test script of the environment.
+    #[serde(default)]
+    pub test_script: Option<String>,
+    /// The run options declared by the environment.
+    #[serde(default)]
+    pub run_options: BTreeMap<String, EnvironmentOption>,
+    /// The optional packages that have been selected in the build request.
+    #[serde(default)]
+    pub packages: BTreeMap<String, Package>,
+    /// The name of the main file.
+    pub main_file: String,
+    /// The names of the additional source files.
+    pub files: Vec<String>,
+    /// The hex encoded hash of the build request. Not available for programs
+    /// that have been migrated from the legacy layout.
+    pub hash: Option<String>,
+    /// The results of the compile step. Failed compilations are cached as well,
+    /// in which case the program cannot be run.
+    pub compile_result: Option<RunResult>,
+    /// Unix timestamp of when the program has been built.
+    pub created_at: u64,
+    /// Unix timestamp of the program's last run.
+    pub last_run: u64,
+    /// The name of the tenant that has built the program or `None` if
+    /// authentication has been disabled.
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// The total size of the program's files (in bytes).
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl Manifest {
+    /// Load the manifest from a program directory. Return `None` if the program
+    /// directory does not contain a manifest.
+    pub async fn load(program_directory: &Path) -> Result<Option<Self>, ManifestError> {
+        let content = match fs::read(program_directory.join(MANIFEST_FILE)).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let Version { version } = serde_json::from_slice(&content)?;
+        if version != MANIFEST_VERSION {
+            return Err(ManifestError::UnsupportedVersion(version));
+        }
+
+        Ok(Some(serde_json::from_slice(&content)?))
+    }
+
+    /// Load the manifest from a program directory like [`Manifest::load`], but
+    /// treat programs that have been written with an unsupported manifest
+    /// version as missing.
+    pub async fn load_supported(program_directory: &Path) -> Result<Option<Self>, ManifestError> {
+        match Self::load(program_directory).await {
+            Err(ManifestError::UnsupportedVersion(version)) => {
+                debug!(
+                    "Ignoring program at {} with unsupported manifest version {version}",
+                    program_directory.display()
+                );
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// Whether the compile step of the program has failed.
+    pub fn compilation_failed(&self) -> bool {
+        self.compile_result
+            .as_ref()
+            .is_some_and(|result| result.status != 0)
+    }
+
+    /// Atomically write the manifest to a program directory.
+    pub async fn save(&self, program_directory: &Path) -> Result<(), ManifestError> {
+        // write to a temporary file first so that concurrent readers never see a
+        // partially written manifest
+        let tmp = program_directory.join(format!("{MANIFEST_FILE}.{}.tmp", Uuid::new_v4()));
+        fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp, program_directory.join(MANIFEST_FILE)).await?;
+        Ok(())
+    }
+}
+
+/// Convert programs that are still stored in the legacy layout (loose
+/// `run_script`, `closure`, `main_file`, `compile_result`, `ok` and `last_run`
+/// files) to the manifest format. Programs that cannot be migrated are deleted.
+pub async fn migrate_programs(
+    config: &Config,
+    environments: &Environments,
+) -> Result<(), std::io::Error> {
+    let mut migrated = 0;
+    let mut it = fs::read_dir(&config.programs_dir).await?;
+    while let Some(dir) = it.next_entry().await? {
+        let path = dir.path();
+        if !dir.file_type().await?.is_dir() {
+            continue;
+        }
+
+        match Manifest::load(&path).await {
+            Ok(Some(_)) => continue,
+            Ok(None) => {}
+            Err(ManifestError::UnsupportedVersion(version)) => {
+                warn!(
+                    "Program at {} uses unsupported manifest version {version}",
+                    path.display()
+                );
+                continue;
+            }
+            Err(err) => {
+                warn!("Failed to load manifest of {}: {err:#}", path.display());
+                continue;
+            }
+        }
+
+        // incomplete programs are removed by the next prune
+        if !fs::try_exists(path.join("ok")).await? {
+            continue;
+        }
+
+        match migrate_legacy_program(&path, environments).await {
+            Ok(()) => migrated += 1,
+            Err(err) => {
+                warn!(
+                    "Failed to migrate program at {}, deleting it: {err:#}",
+                    path.display()
+                );
+                fs::remove_dir_all(&path).await?;
+            }
+        }
+    }
+
+    if migrated > 0 {
+        info!("Migrated {migrated} program(s) from the legacy layout");
+    }
+    Ok(())
+}
+
+/// Migrate a single program from the legacy layout to the manifest format.
+async fn migrate_legacy_program(
+    program_directory: &Path,
+    environments: &Environments,
+) -> Result<(), ManifestError> {
+    let run_script = fs::read_to_string(program_directory.join("run_script")).await?;
+    let closure = PathBuf::from(fs::read_to_string(program_directory.join("closure")).await?);
+    let main_file = fs::read_to_string(program_directory.join("main_file")).await?;
+    let last_run = fs::read_to_string(program_directory.join("last_run"))
+        .await?
+        .parse()
+        .unwrap_or_default();
+    let compile_result = match fs::read(program_directory.join("compile_result")).await {
+        Ok(serialized) => {
+            // postcard is not self-describing, so the result is decoded with the
+            // fields it had in the legacy layout
+            let (status, stdout, stderr, resource_usage, limits) =
+                postcard::from_bytes(&serialized)?;
+            Some(RunResult {
+                status,
+                stdout,
+                stderr,
+                resource_usage,
+                limits,
+                termination_reason: None,
+            })
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    // the legacy layout did not store the environment, so find the one that
+    // matches the stored run script and closure
+    let (environment, env) = environments
+        .iter()
+        .find(|(_, env)| env.run_script == run_script && env.closure == closure)
+        .ok_or(ManifestError::UnknownEnvironment)?;
+
+    Manifest {
+        version: MANIFEST_VERSION,
+        environment: environment.clone(),
+        environment_version: env.version.clone(),
+        run_script,
+        closure,
+        test_script: env.test_script.clone(),
+        run_options: env.run_options.clone(),
+        packages: BTreeMap::new(),
+        main_file,
+        files: Vec::new(),
+        hash: None,
+        compile_result,
+        created_at: last_run,
+        last_run,
+        tenant: None,
+        size: 0,
+    }
+    .save(program_directory)
+    .await?;
+
+    for file in LEGACY_FILES {
+        if let Err(err) = fs::remove_file(program_directory.join(file)).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+    }
+
+    debug!("Migrated program at {}", program_directory.display());
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("json error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("postcard error: {0}")]
+    PostcardError(#[from] postcard::Error),
+    #[error("unsupported manifest version {0}")]
+    UnsupportedVersion(u32),
+    #[error("no matching environment found")]
+    UnknownEnvironment,
+}
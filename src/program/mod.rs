@@ -1,22 +1,58 @@
-use std::{ffi::OsString, future::Future, path::Path};
+use std::{
+    collections::{BTreeSet, HashSet},
+    ffi::OsString,
+    future::Future,
+    path::{Component, Path},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use tokio::fs;
+use nix::libc;
+use tokio::{fs, io::AsyncReadExt};
 use tracing::error;
 
 use crate::sandbox::{Mount, MountType};
 
+pub mod archive;
 pub mod build;
+pub mod cache;
+pub mod check;
+pub mod diagnostics;
+pub mod files;
+pub mod format;
+pub mod manifest;
+pub mod options;
+pub mod packages;
 pub mod prune;
 pub mod run;
+pub mod tenant;
+pub mod test;
+pub mod test_report;
 
-/// Create the [`Mount`]s for the given closure file.
-async fn mounts_from_closure(closure: &Path) -> Result<Vec<Mount>, std::io::Error> {
-    Ok(fs::read_to_string(closure)
-        .await?
-        .trim()
-        .lines()
+/// The maximum number of components of a file path.
+pub const MAX_PATH_DEPTH: usize = 8;
+/// The maximum length of a single component of a file path.
+pub const MAX_PATH_COMPONENT_LENGTH: usize = 64;
+/// The maximum length of a file path.
+pub const MAX_PATH_LENGTH: usize = 256;
+
+/// Create the [`Mount`]s for the given closure files. Store paths that are part
+/// of multiple closures are only mounted once.
+async fn mounts_from_closures(closures: &[&Path]) -> Result<Vec<Mount<'static>>, std::io::Error> {
+    let mut paths = BTreeSet::new();
+    for closure in closures {
+        paths.extend(
+            fs::read_to_string(closure)
+                .await?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned),
+        );
+    }
+    Ok(paths
+        .into_iter()
         .map(|line| Mount {
-            dest: OsString::from(line).into(),
+            dest: OsString::from(&line).into(),
             typ: MountType::ReadOnly {
                 src: OsString::from(line).into(),
             },
@@ -24,7 +60,149 @@ async fn mounts_from_closure(closure: &Path) -> Result<Vec<Mount>, std::io::Erro
         .collect())
 }
 
-/// Create a tempdir, run an async closure and delete the tempdir.
+/// Normalize a relative file path by removing empty and `.` components.
+/// Return `None` if the path is absolute, empty or tries to escape its
+/// directory.
+pub fn normalize_path(path: &str) -> Option<String> {
+    if path.starts_with('/') {
+        return None;
+    }
+    let components = path
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(|c| (c.chars().any(|c| c != '.') && !c.contains('\0')).then_some(c))
+        .collect::<Option<Vec<_>>>()?;
+    (!components.is_empty()).then(|| components.join("/"))
+}
+
+/// Check whether a path is a valid name for a source or run file, i.e. whether
+/// it is already normalized, stays within the depth and length limits and only
+/// contains alphanumeric characters, `.`, `_`, `-` and `/`.
+pub fn check_path(path: &str) -> bool {
+    path.len() <= MAX_PATH_LENGTH
+        && path.split('/').count() <= MAX_PATH_DEPTH
+        && path.split('/').all(|component| {
+            component.len() <= MAX_PATH_COMPONENT_LENGTH
+                && component
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        })
+        && normalize_path(path).as_deref() == Some(path)
+}
+
+/// Check whether a list of normalized paths can be written into the same
+/// directory, i.e. whether they are unique and no path is used both as a file
+/// and as a directory.
+pub fn check_paths<'a>(paths: impl IntoIterator<Item = &'a str>) -> bool {
+    let mut files = HashSet::new();
+    let mut directories = HashSet::new();
+    for path in paths {
+        if !files.insert(path) || directories.contains(path) {
+            return false;
+        }
+        let mut parent = path;
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            if files.contains(dir) {
+                return false;
+            }
+            directories.insert(dir);
+            parent = dir;
+        }
+    }
+    true
+}
+
+/// Write a file into a directory, creating missing parent directories. The
+/// name must be a normalized relative path (see [`normalize_path`]).
+async fn write_file(
+    directory: &Path,
+    name: &str,
+    content: impl AsRef<[u8]>,
+) -> Result<(), std::io::Error> {
+    if normalize_path(name).as_deref() != Some(name) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid file name {name:?}"),
+        ));
+    }
+    let path = directory.join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(path, content).await
+}
+
+/// Read a file that has been written by a sandboxed process. Neither the file
+/// itself nor any of its parent directories below `directory` may be a
+/// symlink, so the sandbox cannot trick the host into reading other files.
+/// Return `None` if the file does not exist, is not a regular file or is larger
+/// than `max_size` bytes.
+pub async fn read_sandbox_file(
+    directory: &Path,
+    name: &str,
+    max_size: u64,
+) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let mut path = directory.to_path_buf();
+    for component in Path::new(name).components() {
+        let Component::Normal(component) = component else {
+            return Ok(None);
+        };
+        path.push(component);
+        match fs::symlink_metadata(&path).await {
+            Ok(metadata) if !metadata.is_symlink() => {}
+            Ok(_) => return Ok(None),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        }
+    }
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(&path)
+        .await;
+    let file = match file {
+        Ok(file) => file,
+        Err(err)
+            if err.kind() == std::io::ErrorKind::NotFound
+                || err.raw_os_error() == Some(libc::ELOOP) =>
+        {
+            return Ok(None)
+        }
+        Err(err) => return Err(err),
+    };
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() || metadata.len() > max_size {
+        return Ok(None);
+    }
+
+    // the file might still grow, so never read more than `max_size` bytes
+    let mut content = Vec::with_capacity(metadata.len() as _);
+    file.take(max_size + 1).read_to_end(&mut content).await?;
+    if content.len() as u64 > max_size {
+        return Ok(None);
+    }
+    Ok(Some(content))
+}
+
+/// Return the total size of all regular files in a directory without following
+/// symlinks.
+fn directory_size(path: &Path) -> Result<u64, std::io::Error> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        let metadata = path.symlink_metadata()?;
+        if metadata.is_dir() {
+            size += directory_size(&path)?;
+        } else if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Create a tempdir, run an async closure and delete the tempdir. The tempdir
+/// is also deleted if the returned future is dropped before completion.
 pub async fn with_tempdir<P, A>(
     path: P,
     closure: impl FnOnce(P) -> A,
@@ -34,9 +212,44 @@ where
     A: Future,
 {
     fs::create_dir_all(&path).await?;
+    let cleanup = RemoveDirOnDrop(Some(path.as_ref()));
     let out = closure(path.clone()).await;
+    cleanup.disarm();
     if let Err(err) = fs::remove_dir_all(&path).await {
         error!("Failed to remove tempdir {path:?}: {err:#}");
     }
     Ok(out)
 }
+
+/// Delete a directory when dropped, unless the guard has been disarmed. Used
+/// to clean up after operations that have been cancelled.
+struct RemoveDirOnDrop<'a>(Option<&'a Path>);
+
+impl RemoveDirOnDrop<'_> {
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for RemoveDirOnDrop<'_> {
+    fn drop(&mut self) {
+        let Some(path) = self.0 else {
+            return;
+        };
+        // this has to happen synchronously, because the caller might still
+        // hold a lock on the directory
+        if let Err(err) = std::fs::remove_dir_all(path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                error!("Failed to remove directory {path:?}: {err:#}");
+            }
+        }
+    }
+}
+
+/// Return the current unix timestamp in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use sandkasten_client::schemas::{environments::EnvironmentOption, programs::OptionValue};
+use thiserror::Error;
+
+/// The prefix of the environment variables that contain the values of build
+/// and run options.
+pub const OPTION_ENV_VAR_PREFIX: &str = "SANDKASTEN_OPTION_";
+
+/// Validate the option values of a request against the options declared by an
+/// environment and fill in the default values of options that have not been
+/// set. Return the values as they are passed to the compile/run script.
+pub fn resolve_options(
+    declared: &BTreeMap<String, EnvironmentOption>,
+    values: &BTreeMap<String, OptionValue>,
+) -> Result<BTreeMap<String, String>, InvalidOptionError> {
+    if let Some(name) = values.keys().find(|name| !declared.contains_key(*name)) {
+        return Err(InvalidOptionError::UnknownOption(name.clone()));
+    }
+
+    declared
+        .iter()
+        .map(|(name, option)| {
+            let value = match (option, values.get(name)) {
+                (EnvironmentOption::Bool(_), Some(OptionValue::Bool(value))) => value.to_string(),
+                (EnvironmentOption::Bool(option), None) => option.default.to_string(),
+                (EnvironmentOption::Bool(_), Some(_)) => {
+                    return Err(InvalidOptionError::InvalidType(name.clone()))
+                }
+                (EnvironmentOption::Int(option), value) => {
+                    let value = match value {
+                        Some(OptionValue::Int(value)) => *value,
+                        None => option.default,
+                        Some(_) => return Err(InvalidOptionError::InvalidType(name.clone())),
+                    };
+                    if option.min.is_some_and(|min| value < min)
+                        || option.max.is_some_and(|max| value > max)
+                    {
+                        return Err(InvalidOptionError::OutOfRange(name.clone()));
+                    }
+                    value.to_string()
+                }
+                (EnvironmentOption::Enum(option), value) => {
+                    let value = match value {
+                        Some(OptionValue::String(value)) => value,
+                        None => &option.default,
+                        Some(_) => return Err(InvalidOptionError::InvalidType(name.clone())),
+                    };
+                    if !option.values.contains(value) {
+                        return Err(InvalidOptionError::InvalidValue(name.clone()));
+                    }
+                    value.clone()
+                }
+            };
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
+
+/// Return the environment variables that pass resolved option values to the
+/// compile/run script.
+pub fn option_env_vars(options: &BTreeMap<String, String>) -> Vec<(String, &str)> {
+    options
+        .iter()
+        .map(|(name, value)| {
+            (
+                format!("{OPTION_ENV_VAR_PREFIX}{}", name.to_uppercase()),
+                value.as_str(),
+            )
+        })
+        .collect()
+}
+
+/// Check whether a string is a valid option name.
+pub fn check_option_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[derive(Debug, Error)]
+pub enum InvalidOptionError {
+    #[error("unknown option {0:?}")]
+    UnknownOption(String),
+    #[error("invalid type for option {0:?}")]
+    InvalidType(String),
+    #[error("value of option {0:?} is out of range")]
+    OutOfRange(String),
+    #[error("invalid value for option {0:?}")]
+    InvalidValue(String),
+}
@@ -0,0 +1,44 @@
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::environments::{Environment, Package};
+
+/// Look up the optional packages selected in a build request.
+pub fn select_packages(
+    environment: &Environment,
+    names: &[String],
+) -> Result<BTreeMap<String, Package>, SelectPackagesError> {
+    names
+        .iter()
+        .map(|name| {
+            environment
+                .packages
+                .get(name)
+                .map(|package| (name.clone(), package.clone()))
+                .ok_or_else(|| SelectPackagesError::UnknownPackage(name.clone()))
+        })
+        .collect()
+}
+
+/// Return the environment variables that make the selected packages available
+/// to the compile/run script. Values of variables that are set by multiple
+/// packages are joined using `:` (e.g. for `PYTHONPATH`).
+pub fn package_env_vars(packages: &BTreeMap<String, Package>) -> Vec<(String, String)> {
+    let mut env_vars = BTreeMap::<_, Vec<_>>::new();
+    for package in packages.values() {
+        for (name, value) in &package.env_vars {
+            env_vars.entry(name).or_default().push(value.as_str());
+        }
+    }
+    env_vars
+        .into_iter()
+        .map(|(name, values)| (name.clone(), values.join(":")))
+        .collect()
+}
+
+#[derive(Debug, Error)]
+pub enum SelectPackagesError {
+    #[error("unknown package {0:?}")]
+    UnknownPackage(String),
+}
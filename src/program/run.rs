@@ -1,9 +1,4 @@
-use std::{
-    ffi::OsStr,
-    path::PathBuf,
-    sync::Arc,
-    time::{self, UNIX_EPOCH},
-};
+use std::{ffi::OsStr, sync::Arc};
 
 use key_rwlock::KeyRwLock;
 use sandkasten_client::schemas::programs::{LimitExceeded, RunRequest, RunResult};
@@ -11,7 +6,15 @@ use thiserror::Error;
 use tokio::{fs, sync::OwnedRwLockReadGuard};
 use uuid::Uuid;
 
-use super::{mounts_from_closure, with_tempdir};
+use super::{
+    check_path, check_paths,
+    manifest::{Manifest, ManifestError},
+    mounts_from_closures, now,
+    options::{option_env_vars, resolve_options, InvalidOptionError},
+    packages::package_env_vars,
+    tenant::Tenant,
+    with_tempdir, write_file,
+};
 use crate::{
     config::Config,
     sandbox::{Mount, MountType, RunConfig, RunError},
@@ -22,8 +25,10 @@ pub async fn run_program(
     config: Arc<Config>,
     program_id: Uuid,
     run_request: RunRequest,
+    tenant: Tenant<'_>,
     _program_guard: &OwnedRwLockReadGuard<()>,
     job_lock: Arc<KeyRwLock<Uuid>>,
+    cores: Option<&[usize]>,
 ) -> Result<RunResult, RunProgramError> {
     // check if limits have been exceeded and use default values from config for
     // empty fields
@@ -32,35 +37,46 @@ pub async fn run_program(
         .check(&config.run_limits)
         .map_err(RunProgramError::LimitsExceeded)?;
 
-    // check that the program's directory exists
+    // make sure that all files stay within the working directory
+    let names = run_request.files.iter().map(|f| f.name.as_str());
+    if let Some(name) = names.clone().find(|name| !check_path(name)) {
+        return Err(RunProgramError::InvalidFileName(name.into()));
+    }
+    if !check_paths(names) {
+        return Err(RunProgramError::ConflictingFilenames);
+    }
+
+    // read the program's manifest
     let path = config.programs_dir.join(program_id.to_string());
-    if !fs::try_exists(&path).await? {
+    let Some(mut manifest) = Manifest::load_supported(&path)
+        .await?
+        .filter(|manifest| tenant.owns(manifest) && !manifest.compilation_failed())
+    else {
         return Err(RunProgramError::ProgramNotFound);
-    }
+    };
 
-    // update the program's last run timestamp
-    std::fs::write(
-        path.join("last_run"),
-        time::SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            .to_string(),
-    )?;
+    let options = resolve_options(&manifest.run_options, &run_request.options)?;
 
-    // read environment metadata from program directory
-    let run_script = fs::read_to_string(path.join("run_script")).await?;
-    let main_file = fs::read_to_string(path.join("main_file")).await?;
-    let closure = PathBuf::from(fs::read_to_string(path.join("closure")).await?);
+    // update the program's last run timestamp
+    manifest.last_run = now();
+    manifest.save(&path).await?;
 
     // collect command line arguments and environment variables from run request
-    let args = std::iter::once(main_file.as_str())
+    let args = std::iter::once(manifest.main_file.as_str())
         .chain(run_request.args.iter().map(|f| f.as_str()))
         .collect::<Vec<_>>();
+    let option_env_vars = option_env_vars(&options);
+    let package_env_vars = package_env_vars(&manifest.packages);
     let envvars = run_request
         .env_vars
         .iter()
         .map(|e| (e.name.as_str(), e.value.as_str()))
+        .chain(option_env_vars.iter().map(|(k, v)| (k.as_str(), *v)))
+        .chain(
+            package_env_vars
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        )
         .collect::<Vec<_>>();
 
     let job_id = Uuid::new_v4();
@@ -69,9 +85,10 @@ pub async fn run_program(
         let tmpdir = { tmpdir }; // move tmpdir into async block
 
         // create working directory and copy files from run request into it
-        fs::create_dir_all(tmpdir.join("box")).await?;
+        let box_directory = tmpdir.join("box");
+        fs::create_dir_all(&box_directory).await?;
         for file in &run_request.files {
-            fs::write(tmpdir.join("box").join(&file.name), &file.content).await?;
+            write_file(&box_directory, &file.name, &file.content).await?;
         }
 
         let mut mounts = vec![
@@ -94,7 +111,14 @@ pub async fn run_program(
                 },
             },
         ];
-        mounts.extend(mounts_from_closure(&closure).await?);
+        mounts.extend(
+            mounts_from_closures(
+                &std::iter::once(manifest.closure.as_path())
+                    .chain(manifest.packages.values().map(|p| p.closure.as_path()))
+                    .collect::<Vec<_>>(),
+            )
+            .await?,
+        );
 
         // run the program
         RunConfig {
@@ -102,13 +126,14 @@ pub async fn run_program(
             time: &config.time_path,
             use_cgroup: config.use_cgroup,
             tmpdir: &tmpdir,
-            program: &run_script,
+            program: &manifest.run_script,
             args: &args,
             envvars: &envvars,
             cwd: "/box",
             stdin: run_request.stdin.as_deref(),
             mounts: &mounts,
             limits: run_limits,
+            cores,
         }
         .run()
         .await
@@ -125,6 +150,14 @@ pub enum RunProgramError {
     IOError(#[from] std::io::Error),
     #[error("run error: {0}")]
     RunError(#[from] RunError),
+    #[error("manifest error: {0}")]
+    ManifestError(#[from] ManifestError),
+    #[error("invalid file name {0:?}")]
+    InvalidFileName(String),
+    #[error("conflicting filenames")]
+    ConflictingFilenames,
+    #[error("invalid options: {0}")]
+    InvalidOptions(#[from] InvalidOptionError),
     #[error("limits exceeded: {0:?}")]
     LimitsExceeded(Vec<LimitExceeded>),
 }
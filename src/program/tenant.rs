@@ -0,0 +1,120 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use tokio::fs;
+
+use super::manifest::Manifest;
+use crate::config::Config;
+
+/// The tenant a program belongs to. Program ids are namespaced per tenant, so
+/// the same build request results in different programs for different
+/// tenants, and programs of other tenants cannot be accessed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tenant<'a> {
+    /// The name of the tenant or `None` if authentication is disabled.
+    pub name: Option<&'a str>,
+    /// The maximum total size of the tenant's stored programs (in bytes) or
+    /// `None` if the storage is not limited.
+    pub storage_quota: Option<u64>,
+}
+
+impl Tenant<'_> {
+    /// Check whether a program belongs to this tenant.
+    pub fn owns(&self, manifest: &Manifest) -> bool {
+        manifest.tenant.as_deref() == self.name
+    }
+}
+
+/// The total size of the stored programs of each tenant (in bytes). The usage
+/// is computed once at startup and then kept up to date when programs are
+/// built or pruned, so that storage quotas can be checked without reading the
+/// manifests of all programs.
+#[derive(Debug, Default)]
+pub struct StorageUsage(Mutex<HashMap<Option<String>, u64>>);
+
+impl StorageUsage {
+    /// Compute the storage usage of all tenants from the manifests of the
+    /// programs in [`Config::programs_dir`].
+    pub async fn load(config: &Config) -> Result<Self, std::io::Error> {
+        let mut usage = HashMap::new();
+        let mut it = fs::read_dir(&config.programs_dir).await?;
+        while let Some(dir) = it.next_entry().await? {
+            if let Ok(Some(manifest)) = Manifest::load(&dir.path()).await {
+                *usage.entry(manifest.tenant).or_default() += manifest.size;
+            }
+        }
+        Ok(Self(Mutex::new(usage)))
+    }
+
+    /// Return the total size of all programs that are currently stored (or
+    /// being built) for a tenant.
+    pub fn get(&self, tenant: Option<&str>) -> u64 {
+        let usage = self.0.lock().unwrap();
+        usage
+            .get(&tenant.map(Into::into))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Reserve storage for a program of a tenant. Fail with the tenant's quota
+    /// if the reservation would exceed it. The reservation is released when the
+    /// returned guard is dropped, unless it has been committed.
+    pub fn reserve(&self, tenant: Tenant<'_>, size: u64) -> Result<StorageReservation<'_>, u64> {
+        let mut reservation = StorageReservation {
+            usage: self,
+            tenant: tenant.name.map(Into::into),
+            quota: tenant.storage_quota,
+            size: 0,
+        };
+        reservation.resize(size)?;
+        Ok(reservation)
+    }
+
+    /// Release the storage of a program that has been deleted.
+    pub fn release(&self, tenant: Option<&str>, size: u64) {
+        let mut usage = self.0.lock().unwrap();
+        if let Some(usage) = usage.get_mut(&tenant.map(Into::into)) {
+            *usage = usage.saturating_sub(size);
+        }
+    }
+}
+
+/// Storage that has been reserved for a program (see
+/// [`StorageUsage::reserve`]).
+#[derive(Debug)]
+pub struct StorageReservation<'a> {
+    usage: &'a StorageUsage,
+    tenant: Option<String>,
+    quota: Option<u64>,
+    size: u64,
+}
+
+impl StorageReservation<'_> {
+    /// Change the size of the reservation, e.g. once the actual size of a
+    /// program is known. Fail with the tenant's quota if the new size would
+    /// exceed it.
+    pub fn resize(&mut self, size: u64) -> Result<(), u64> {
+        let mut usage = self.usage.0.lock().unwrap();
+        let usage = usage.entry(self.tenant.clone()).or_default();
+        let new_usage = usage.saturating_sub(self.size).saturating_add(size);
+        if let Some(quota) = self.quota {
+            if size > self.size && new_usage > quota {
+                return Err(quota);
+            }
+        }
+        *usage = new_usage;
+        self.size = size;
+        Ok(())
+    }
+
+    /// Keep the reserved storage allocated after the reservation is dropped.
+    /// It is released when the program is deleted.
+    pub fn commit(mut self) {
+        self.size = 0;
+    }
+}
+
+impl Drop for StorageReservation<'_> {
+    fn drop(&mut self) {
+        self.usage.release(self.tenant.as_deref(), self.size);
+    }
+}
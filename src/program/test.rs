@@ -0,0 +1,206 @@
+use std::{ffi::OsStr, sync::Arc};
+
+use key_rwlock::KeyRwLock;
+use sandkasten_client::schemas::programs::{LimitExceeded, TestRequest, TestResult};
+use thiserror::Error;
+use tokio::{fs, sync::OwnedRwLockReadGuard};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::{
+    check_path, check_paths,
+    manifest::{Manifest, ManifestError},
+    mounts_from_closures, now,
+    options::{option_env_vars, resolve_options, InvalidOptionError},
+    packages::package_env_vars,
+    read_sandbox_file,
+    tenant::Tenant,
+    test_report::parse_test_report,
+    with_tempdir, write_file,
+};
+use crate::{
+    config::Config,
+    sandbox::{Mount, MountType, RunConfig, RunError},
+};
+
+/// The environment variable that contains the path the test script should
+/// write its report to.
+pub const TEST_REPORT_ENV_VAR: &str = "SANDKASTEN_TEST_REPORT";
+
+/// The maximum size of a test report (in bytes). Larger reports are ignored.
+const MAX_TEST_REPORT_SIZE: u64 = 1 << 20;
+
+/// Run the test script of a program's environment on the test files of a
+/// request and return the test cases from the report written by the script.
+pub async fn test_program(
+    config: Arc<Config>,
+    program_id: Uuid,
+    test_request: TestRequest,
+    tenant: Tenant<'_>,
+    _program_guard: &OwnedRwLockReadGuard<()>,
+    job_lock: Arc<KeyRwLock<Uuid>>,
+    cores: Option<&[usize]>,
+) -> Result<TestResult, TestProgramError> {
+    // check if limits have been exceeded and use default values from config for
+    // empty fields
+    let run_limits = test_request
+        .run_limits
+        .check(&config.run_limits)
+        .map_err(TestProgramError::LimitsExceeded)?;
+
+    // make sure that all files stay within the working directory
+    let names = test_request.files.iter().map(|f| f.name.as_str());
+    if let Some(name) = names.clone().find(|name| !check_path(name)) {
+        return Err(TestProgramError::InvalidFileName(name.into()));
+    }
+    if !check_paths(names.clone()) {
+        return Err(TestProgramError::ConflictingFilenames);
+    }
+
+    // read the program's manifest
+    let path = config.programs_dir.join(program_id.to_string());
+    let Some(mut manifest) = Manifest::load_supported(&path)
+        .await?
+        .filter(|manifest| tenant.owns(manifest) && !manifest.compilation_failed())
+    else {
+        return Err(TestProgramError::ProgramNotFound);
+    };
+    let Some(test_script) = manifest.test_script.clone() else {
+        return Err(TestProgramError::TestsNotSupported);
+    };
+
+    let options = resolve_options(&manifest.run_options, &test_request.options)?;
+
+    // update the program's last run timestamp
+    manifest.last_run = now();
+    manifest.save(&path).await?;
+
+    // the test script receives the name of the main file followed by the names
+    // of the test files
+    let args = std::iter::once(manifest.main_file.as_str())
+        .chain(names)
+        .collect::<Vec<_>>();
+    let option_env_vars = option_env_vars(&options);
+    let package_env_vars = package_env_vars(&manifest.packages);
+    let envvars = test_request
+        .env_vars
+        .iter()
+        .map(|e| (e.name.as_str(), e.value.as_str()))
+        .chain(option_env_vars.iter().map(|(k, v)| (k.as_str(), *v)))
+        .chain(
+            package_env_vars
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        )
+        .chain(std::iter::once((TEST_REPORT_ENV_VAR, "/report/report")))
+        .collect::<Vec<_>>();
+
+    let job_id = Uuid::new_v4();
+    let _guard = job_lock.write(job_id).await;
+    with_tempdir(config.jobs_dir.join(job_id.to_string()), |tmpdir| async {
+        let tmpdir = { tmpdir }; // move tmpdir into async block
+
+        // create working directory and copy test files from the request into
+        // it
+        let box_directory = tmpdir.join("box");
+        fs::create_dir_all(&box_directory).await?;
+        for file in &test_request.files {
+            write_file(&box_directory, &file.name, &file.content).await?;
+        }
+        let report_directory = tmpdir.join("report");
+        fs::create_dir_all(&report_directory).await?;
+
+        let mut mounts = vec![
+            Mount {
+                dest: OsStr::new("/program").into(),
+                typ: MountType::ReadOnly {
+                    src: path.join("files").into_os_string().into(),
+                },
+            },
+            Mount {
+                dest: OsStr::new("/box").into(),
+                typ: MountType::ReadOnly {
+                    src: box_directory.into_os_string().into(),
+                },
+            },
+            Mount {
+                dest: OsStr::new("/report").into(),
+                typ: MountType::ReadWrite {
+                    src: report_directory.clone().into_os_string().into(),
+                },
+            },
+            Mount {
+                dest: OsStr::new("/tmp").into(),
+                typ: MountType::Temp {
+                    size: run_limits.tmpfs,
+                },
+            },
+        ];
+        mounts.extend(
+            mounts_from_closures(
+                &std::iter::once(manifest.closure.as_path())
+                    .chain(manifest.packages.values().map(|p| p.closure.as_path()))
+                    .collect::<Vec<_>>(),
+            )
+            .await?,
+        );
+
+        // run the test script
+        let result = RunConfig {
+            nsjail: &config.nsjail_path,
+            time: &config.time_path,
+            use_cgroup: config.use_cgroup,
+            tmpdir: &tmpdir,
+            program: &test_script,
+            args: &args,
+            envvars: &envvars,
+            cwd: "/box",
+            stdin: None,
+            mounts: &mounts,
+            limits: run_limits,
+            cores,
+        }
+        .run()
+        .await?;
+
+        // read and parse the report
+        let tests =
+            match read_sandbox_file(&report_directory, "report", MAX_TEST_REPORT_SIZE).await? {
+                Some(report) => {
+                    let tests = std::str::from_utf8(&report)
+                        .ok()
+                        .and_then(parse_test_report);
+                    if tests.is_none() {
+                        warn!("Failed to parse test report of program {program_id}");
+                    }
+                    tests.unwrap_or_default()
+                }
+                None => Vec::new(),
+            };
+
+        Ok(TestResult { tests, result })
+    })
+    .await?
+}
+
+#[derive(Debug, Error)]
+pub enum TestProgramError {
+    #[error("program does not exist")]
+    ProgramNotFound,
+    #[error("environment of the program does not have a test script")]
+    TestsNotSupported,
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("run error: {0}")]
+    RunError(#[from] RunError),
+    #[error("manifest error: {0}")]
+    ManifestError(#[from] ManifestError),
+    #[error("invalid file name {0:?}")]
+    InvalidFileName(String),
+    #[error("conflicting filenames")]
+    ConflictingFilenames,
+    #[error("invalid options: {0}")]
+    InvalidOptions(#[from] InvalidOptionError),
+    #[error("limits exceeded: {0:?}")]
+    LimitsExceeded(Vec<LimitExceeded>),
+}
@@ -0,0 +1,164 @@
+use sandkasten_client::schemas::programs::{TestCase, TestStatus};
+
+/// Parse a test report in JUnit XML or TAP format. The format is detected
+/// automatically. Return `None` if the report is not valid JUnit XML.
+pub fn parse_test_report(report: &str) -> Option<Vec<TestCase>> {
+    if report.trim_start().starts_with('<') {
+        parse_junit(report)
+    } else {
+        Some(parse_tap(report))
+    }
+}
+
+/// Parse a JUnit XML report (e.g. as written by `pytest --junitxml`).
+pub fn parse_junit(report: &str) -> Option<Vec<TestCase>> {
+    let document = roxmltree::Document::parse(report).ok()?;
+    let tests = document
+        .descendants()
+        .filter(|node| node.has_tag_name("testcase"))
+        .map(|testcase| {
+            let name = testcase.attribute("name").unwrap_or_default();
+            let name = match testcase.attribute("classname") {
+                Some(classname) if !classname.is_empty() => format!("{classname}.{name}"),
+                _ => name.into(),
+            };
+            let duration = testcase
+                .attribute("time")
+                .and_then(|time| time.parse::<f64>().ok())
+                .map(|time| (time * 1000.0).round() as u64);
+
+            // the first child element determines the status of the test case
+            let outcome = testcase.children().find(|child| {
+                ["failure", "error", "skipped"]
+                    .iter()
+                    .any(|tag| child.has_tag_name(*tag))
+            });
+            let (status, message) = match outcome {
+                Some(outcome) => (
+                    match outcome.tag_name().name() {
+                        "failure" => TestStatus::Failed,
+                        "error" => TestStatus::Error,
+                        _ => TestStatus::Skipped,
+                    },
+                    outcome
+                        .attribute("message")
+                        .or_else(|| outcome.text())
+                        .map(|message| message.trim().to_owned())
+                        .filter(|message| !message.is_empty()),
+                ),
+                None => (TestStatus::Passed, None),
+            };
+
+            TestCase {
+                name,
+                status,
+                duration,
+                message,
+            }
+        })
+        .collect();
+    Some(tests)
+}
+
+/// Parse a TAP report (e.g. as written by `node --test --test-reporter=tap`).
+/// Only top level test points are reported; subtests are ignored. Durations and
+/// messages are read from the YAML diagnostics block that follows a test point.
+pub fn parse_tap(report: &str) -> Vec<TestCase> {
+    let mut out = Vec::new();
+    let mut lines = report.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some(mut test) = parse_tap_test_point(line) else {
+            continue;
+        };
+
+        if lines.peek().is_some_and(|next| next.trim() == "---") {
+            lines.next();
+            while let Some(line) = lines.next() {
+                if line.trim() == "..." {
+                    break;
+                }
+                let indent = indentation(line);
+                let Some((key, value)) = line.trim().split_once(':') else {
+                    continue;
+                };
+                let mut value = value.trim().trim_matches(['\'', '"']).to_owned();
+                if value.starts_with(['|', '>']) {
+                    // block scalar: the value consists of the following lines
+                    // that are indented more than the key
+                    let mut block = Vec::new();
+                    while let Some(next) =
+                        lines.next_if(|next| next.trim().is_empty() || indentation(next) > indent)
+                    {
+                        block.push(next.trim());
+                    }
+                    value = block.join("\n").trim().into();
+                }
+                match key {
+                    "duration_ms" => {
+                        test.duration = value.parse::<f64>().ok().map(|d| d.round() as u64)
+                    }
+                    "message" | "error" if test.message.is_none() && !value.is_empty() => {
+                        test.message = Some(value)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        out.push(test);
+    }
+    out
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Parse a line of the form `[not ]ok [number] [- ]description [# directive]`.
+fn parse_tap_test_point(line: &str) -> Option<TestCase> {
+    let (ok, rest) = if let Some(rest) = line.strip_prefix("not ok") {
+        (false, rest)
+    } else {
+        (true, line.strip_prefix("ok")?)
+    };
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+
+    let rest = rest.trim_start();
+    let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix("- ").unwrap_or(rest);
+    let (description, directive) = match rest.strip_prefix("# ") {
+        Some(directive) => ("", Some(directive.trim())),
+        None => match rest.split_once(" # ") {
+            Some((description, directive)) => (description, Some(directive.trim())),
+            None => (rest, None),
+        },
+    };
+
+    // both skipped tests and failing tests that are not implemented yet (TODO)
+    // are reported as skipped
+    let directive = directive.and_then(|directive| {
+        let (keyword, reason) = directive.split_once(' ').unwrap_or((directive, ""));
+        ["SKIP", "TODO"]
+            .iter()
+            .any(|k| keyword.eq_ignore_ascii_case(k))
+            .then(|| reason.trim())
+    });
+    let (status, message) = match directive {
+        Some(reason) => (
+            TestStatus::Skipped,
+            Some(reason).filter(|r| !r.is_empty()).map(Into::into),
+        ),
+        None if ok => (TestStatus::Passed, None),
+        None => (TestStatus::Failed, None),
+    };
+
+    Some(TestCase {
+        name: description.trim().into(),
+        status,
+        duration: None,
+        message,
+    })
+}
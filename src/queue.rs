@@ -0,0 +1,510 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use sandkasten_client::schemas::programs::Limits;
+use thiserror::Error;
+use tokio::sync::{oneshot, Notify};
+use tracing::{info, warn};
+
+use crate::{config::Config, metrics::Metrics};
+
+/// The resources that are reserved by jobs running at the same time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Resources {
+    /// The number of job slots (see [`Config::max_concurrent_jobs`]).
+    pub jobs: u64,
+    /// The number of cpus.
+    pub cpus: u64,
+    /// The amount of memory (in MB).
+    pub memory: u64,
+}
+
+impl Resources {
+    /// Return the resources that are reserved by a single job with the given
+    /// limits.
+    pub fn job(limits: &Limits) -> Self {
+        Self {
+            jobs: 1,
+            cpus: limits.cpus,
+            memory: limits.memory,
+        }
+    }
+
+    /// Return the maximum of each resource.
+    pub fn max(self, other: Self) -> Self {
+        Self {
+            jobs: self.jobs.max(other.jobs),
+            cpus: self.cpus.max(other.cpus),
+            memory: self.memory.max(other.memory),
+        }
+    }
+
+    /// Return the minimum of each resource.
+    pub fn min(self, other: Self) -> Self {
+        Self {
+            jobs: self.jobs.min(other.jobs),
+            cpus: self.cpus.min(other.cpus),
+            memory: self.memory.min(other.memory),
+        }
+    }
+
+    fn fits_into(&self, capacity: &Self) -> bool {
+        self.jobs <= capacity.jobs && self.cpus <= capacity.cpus && self.memory <= capacity.memory
+    }
+
+    fn add(&mut self, other: Self) {
+        self.jobs += other.jobs;
+        self.cpus += other.cpus;
+        self.memory += other.memory;
+    }
+
+    fn sub(&mut self, other: Self) {
+        self.jobs -= other.jobs;
+        self.cpus -= other.cpus;
+        self.memory -= other.memory;
+    }
+}
+
+/// The pools of job slots for the different kinds of steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pool {
+    /// Builds, checks and formatting.
+    Compile,
+    /// Runs and tests.
+    Run,
+}
+
+/// A request for resources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Demand {
+    /// The pool of job slots to use.
+    pub pool: Pool,
+    /// The priority of the request (see [`Config::priority_classes`]).
+    pub priority: u32,
+    /// The resources to reserve.
+    pub resources: Resources,
+}
+
+impl Demand {
+    /// Return the demand of a compile step with the given limits.
+    pub fn compile(priority: u32, limits: &Limits) -> Self {
+        Self {
+            pool: Pool::Compile,
+            priority,
+            resources: Resources::job(limits),
+        }
+    }
+
+    /// Return the demand of a run step with the given limits.
+    pub fn run(priority: u32, limits: &Limits) -> Self {
+        Self {
+            pool: Pool::Run,
+            priority,
+            resources: Resources::job(limits),
+        }
+    }
+}
+
+/// A bounded queue for requests that wait for free resources. A job is only
+/// admitted if the sum of the resources reserved by all running jobs stays
+/// within the capacity of the host and if a slot in its pool is available.
+/// Waiting requests are admitted in the order of their priority, which
+/// increases while they are waiting, so requests with a low priority are not
+/// starved. Requests with the same priority are admitted in the order they have
+/// arrived. If a [`Config::cpuset`] is configured, each admitted job receives
+/// exclusive cpu cores. In drain mode, new requests are rejected.
+#[derive(Debug)]
+pub struct JobQueue {
+    capacity: Resources,
+    pinned: bool,
+    pools: [u64; 2],
+    max_length: usize,
+    max_wait: Duration,
+    aging: Option<Duration>,
+    draining: AtomicBool,
+    idle: Notify,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    used: Resources,
+    pools: [u64; 2],
+    free_cores: Vec<usize>,
+    waiters: Vec<Waiter>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    demand: Demand,
+    since: Instant,
+    admit: oneshot::Sender<Vec<usize>>,
+}
+
+impl JobQueue {
+    pub fn new(config: &Config) -> Self {
+        let jobs = config.max_concurrent_jobs;
+        Self {
+            capacity: capacity(config),
+            pinned: config.cpuset.is_some(),
+            pools: [
+                config.max_concurrent_compile_jobs.unwrap_or(jobs) as _,
+                config.max_concurrent_run_jobs.unwrap_or(jobs) as _,
+            ],
+            max_length: config.max_queue_length,
+            max_wait: Duration::from_secs(config.max_queue_wait),
+            aging: (config.priority_aging != 0).then(|| Duration::from_secs(config.priority_aging)),
+            draining: AtomicBool::new(false),
+            idle: Notify::new(),
+            state: Mutex::new(State {
+                free_cores: config.cpuset.clone().unwrap_or_default(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Return the total resources that can be reserved by running jobs.
+    pub fn capacity(&self) -> Resources {
+        self.capacity
+    }
+
+    /// Enable or disable drain mode.
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::Relaxed);
+    }
+
+    /// Return whether drain mode is enabled.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Fail if drain mode is enabled.
+    pub fn check_draining(&self, metrics: &Metrics) -> Result<(), QueueError> {
+        if self.is_draining() {
+            metrics
+                .queue
+                .rejected
+                .with_label_values(&["draining"])
+                .inc();
+            return Err(QueueError::Draining);
+        }
+        Ok(())
+    }
+
+    /// Return the number of running jobs and the number of waiting requests.
+    pub fn status(&self) -> (u64, u64) {
+        let state = self.state.lock().unwrap();
+        (state.used.jobs, state.waiters.len() as _)
+    }
+
+    /// Wait until no jobs are running and no requests are waiting anymore.
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            if self.status() == (0, 0) {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Wait until the requested resources are available and reserve them.
+    /// Requests for more resources than the host provides are limited to the
+    /// capacity of the host, i.e. they can only run alone. In cpuset mode, every
+    /// job reserves at least one core. Fail immediately if
+    /// the queue is already full and fail if the resources do not become
+    /// available within the maximum wait time.
+    pub async fn acquire<'a>(
+        &'a self,
+        metrics: &'a Metrics,
+        mut demand: Demand,
+    ) -> Result<Reservation<'a>, QueueError> {
+        self.check_draining(metrics)?;
+
+        demand.resources = demand.resources.min(Resources {
+            jobs: self.capacity.jobs.min(self.pools[demand.pool as usize]),
+            ..self.capacity
+        });
+        if self.pinned {
+            demand.resources.cpus = demand.resources.cpus.max(1);
+        }
+        let reservation = |state: &State, cores| {
+            update_metrics(metrics, state);
+            Reservation {
+                queue: self,
+                metrics,
+                demand,
+                cores,
+            }
+        };
+
+        let (admit, mut admitted) = oneshot::channel();
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push(Waiter {
+                id,
+                demand,
+                since: Instant::now(),
+                admit,
+            });
+            self.admit(&mut state);
+            if let Ok(cores) = admitted.try_recv() {
+                metrics.queue.wait_time.observe(0.0);
+                return Ok(reservation(&state, cores));
+            }
+
+            if state.waiters.len() > self.max_length {
+                state.waiters.retain(|waiter| waiter.id != id);
+                metrics
+                    .queue
+                    .rejected
+                    .with_label_values(&["queue_full"])
+                    .inc();
+                return Err(QueueError::Full);
+            }
+            id
+        };
+        metrics.queue.length.inc();
+        let mut waiting = Waiting {
+            queue: self,
+            metrics,
+            id,
+            demand,
+            admitted,
+            done: false,
+        };
+
+        let start = Instant::now();
+        let result = tokio::time::timeout(self.max_wait, &mut waiting.admitted).await;
+        metrics
+            .queue
+            .wait_time
+            .observe(start.elapsed().as_secs_f64());
+        match result {
+            Ok(admitted) => {
+                let cores =
+                    admitted.expect("waiters are only removed by admitting or cancelling them");
+                waiting.done = true;
+                Ok(reservation(&self.state.lock().unwrap(), cores))
+            }
+            Err(_) => {
+                metrics
+                    .queue
+                    .rejected
+                    .with_label_values(&["queue_timeout"])
+                    .inc();
+                Err(QueueError::Timeout)
+            }
+        }
+    }
+
+    /// Admit waiting requests in the order of their effective priority for as
+    /// long as their resources are available. A request that does not fit
+    /// blocks all requests behind it, unless only the slots of its own pool are
+    /// exhausted, in which case it only blocks the requests of the same pool.
+    fn admit(&self, state: &mut State) {
+        let now = Instant::now();
+        let mut order = state
+            .waiters
+            .iter()
+            .map(|waiter| (self.effective_priority(waiter, now), waiter.id))
+            .collect::<Vec<_>>();
+        order.sort_by(|(a, a_id), (b, b_id)| b.total_cmp(a).then(a_id.cmp(b_id)));
+
+        let mut blocked = [false; 2];
+        for (_, id) in order {
+            let pos = state
+                .waiters
+                .iter()
+                .position(|waiter| waiter.id == id)
+                .unwrap();
+            let demand = state.waiters[pos].demand;
+            let pool = demand.pool as usize;
+            if blocked[pool] {
+                continue;
+            }
+
+            let mut used = state.used;
+            used.add(demand.resources);
+            if !used.fits_into(&self.capacity) {
+                break;
+            }
+            if state.pools[pool] + demand.resources.jobs > self.pools[pool] {
+                blocked[pool] = true;
+                continue;
+            }
+
+            let waiter = state.waiters.remove(pos);
+            let cores = if self.pinned {
+                let free = state.free_cores.len();
+                state
+                    .free_cores
+                    .split_off(free - demand.resources.cpus as usize)
+            } else {
+                Vec::new()
+            };
+            match waiter.admit.send(cores) {
+                Ok(()) => {
+                    state.used = used;
+                    state.pools[pool] += demand.resources.jobs;
+                }
+                Err(cores) => state.free_cores.extend(cores),
+            }
+        }
+    }
+
+    /// Return the priority of a waiting request, increased by one for every
+    /// `priority_aging` seconds it has been waiting.
+    fn effective_priority(&self, waiter: &Waiter, now: Instant) -> f64 {
+        let aging = self.aging.map_or(0.0, |aging| {
+            now.saturating_duration_since(waiter.since).as_secs_f64() / aging.as_secs_f64()
+        });
+        waiter.demand.priority as f64 + aging
+    }
+
+    fn release(&self, state: &mut State, demand: Demand, cores: Vec<usize>) {
+        state.used.sub(demand.resources);
+        state.pools[demand.pool as usize] -= demand.resources.jobs;
+        state.free_cores.extend(cores);
+    }
+
+    fn notify_if_idle(&self, state: &State) {
+        if state.used.jobs == 0 && state.waiters.is_empty() {
+            self.idle.notify_waiters();
+        }
+    }
+}
+
+/// Resources that have been reserved for a job. The resources are released
+/// when the reservation is dropped.
+pub struct Reservation<'a> {
+    queue: &'a JobQueue,
+    metrics: &'a Metrics,
+    demand: Demand,
+    cores: Vec<usize>,
+}
+
+impl Reservation<'_> {
+    /// Return the cpu cores the job should be pinned to or `None` if cpuset
+    /// mode is disabled.
+    pub fn cores(&self) -> Option<&[usize]> {
+        self.queue.pinned.then_some(&self.cores)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        self.queue
+            .release(&mut state, self.demand, std::mem::take(&mut self.cores));
+        self.queue.admit(&mut state);
+        self.queue.notify_if_idle(&state);
+        update_metrics(self.metrics, &state);
+    }
+}
+
+/// Remove a request from the queue when it stops waiting, even if it has been
+/// cancelled.
+struct Waiting<'a> {
+    queue: &'a JobQueue,
+    metrics: &'a Metrics,
+    id: u64,
+    demand: Demand,
+    admitted: oneshot::Receiver<Vec<usize>>,
+    done: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.metrics.queue.length.dec();
+        if self.done {
+            return;
+        }
+
+        let mut state = self.queue.state.lock().unwrap();
+        if let Some(pos) = state.waiters.iter().position(|waiter| waiter.id == self.id) {
+            // the request has not been admitted yet, but the requests behind it
+            // might fit now
+            state.waiters.remove(pos);
+        } else if let Ok(cores) = self.admitted.try_recv() {
+            // the request has been admitted after it stopped waiting
+            self.queue.release(&mut state, self.demand, cores);
+        }
+        self.queue.admit(&mut state);
+        self.queue.notify_if_idle(&state);
+        update_metrics(self.metrics, &state);
+    }
+}
+
+fn update_metrics(metrics: &Metrics, state: &State) {
+    let reserved = &metrics.queue.reserved;
+    reserved.jobs.set(state.used.jobs as _);
+    reserved.cpus.set(state.used.cpus as _);
+    reserved.memory.set(state.used.memory as _);
+}
+
+/// Determine the total resources that can be reserved by running jobs. The
+/// number of cpus and the amount of memory are detected from the host unless
+/// they have been configured explicitly. In cpuset mode, the number of cpus is
+/// the number of cores in the cpuset.
+fn capacity(config: &Config) -> Resources {
+    let cpus = config.cpuset.as_ref().map(|cores| cores.len() as _);
+    let cpus = cpus.or(config.host_cpus).unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|cpus| cpus.get() as _)
+            .unwrap_or_else(|err| {
+                warn!("Failed to detect the number of cpus: {err:#}");
+                1
+            })
+    });
+    let memory = config.host_memory.unwrap_or_else(|| {
+        total_memory().unwrap_or_else(|err| {
+            warn!("Failed to detect the amount of memory: {err:#}");
+            u64::MAX
+        })
+    });
+    info!(
+        "Admitting up to {} concurrent jobs with {cpus} cpus and {memory} MB of memory",
+        config.max_concurrent_jobs
+    );
+    Resources {
+        jobs: config.max_concurrent_jobs as _,
+        cpus,
+        memory,
+    }
+}
+
+/// Read the total amount of memory (in MB) from `/proc/meminfo`.
+fn total_memory() -> Result<u64, std::io::Error> {
+    std::fs::read_to_string("/proc/meminfo")?
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix("MemTotal:")?
+                .trim()
+                .strip_suffix("kB")?
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kb| kb / 1024)
+        .ok_or_else(|| std::io::Error::other("MemTotal not found in /proc/meminfo"))
+}
+
+#[derive(Debug, Error)]
+pub enum QueueError {
+    #[error("too many requests are waiting for free resources")]
+    Full,
+    #[error("timed out waiting for free resources")]
+    Timeout,
+    #[error("the server is in drain mode")]
+    Draining,
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use sandkasten_client::schemas::{programs::ResourceUsage, RateLimit, RateLimitExceeded};
+
+use crate::config::Config;
+
+/// Per-client token buckets for requests and rolling budgets for the resources
+/// used by the client's programs. Clients are identified by the name of their
+/// api key or, if authentication is disabled, by their ip address.
+#[derive(Debug)]
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    clients: HashMap<String, Client>,
+    last_prune: Instant,
+}
+
+#[derive(Debug)]
+struct Client {
+    /// The number of requests the client can send right now.
+    tokens: f64,
+    /// The last time `tokens` has been updated.
+    updated: Instant,
+    /// The resources that have been used within the budget window, ordered by
+    /// the time they have been recorded.
+    usage: VecDeque<Usage>,
+}
+
+#[derive(Debug)]
+struct Usage {
+    at: Instant,
+    /// The cpu time in milliseconds.
+    cpu_time: u64,
+    /// The memory-time in kilobyte-milliseconds.
+    memory_time: u64,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                clients: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Take a token from the client's bucket. Fail if the bucket is empty.
+    pub fn check_request(
+        &self,
+        config: &Config,
+        client: &str,
+        now: Instant,
+    ) -> Result<(), RateLimitExceeded> {
+        if config.rate_limit_burst == 0 {
+            return Ok(());
+        }
+
+        let burst = config.rate_limit_burst as f64;
+        let mut state = self.state.lock().unwrap();
+        state.prune(config, now);
+        let client = state.client(config, client, now);
+        client.tokens = (client.tokens
+            + now.duration_since(client.updated).as_secs_f64() * config.rate_limit_per_second)
+            .min(burst);
+        client.updated = now;
+
+        if client.tokens >= 1.0 {
+            client.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(RateLimitExceeded {
+            limit: RateLimit::Requests,
+            retry_after: retry_after(Duration::from_secs_f64(
+                (1.0 - client.tokens) / config.rate_limit_per_second,
+            )),
+        })
+    }
+
+    /// Check whether the client has exhausted its cpu time or memory-time
+    /// budget within the budget window.
+    pub fn check_budget(
+        &self,
+        config: &Config,
+        client: &str,
+        now: Instant,
+    ) -> Result<(), RateLimitExceeded> {
+        if config.cpu_budget == 0 && config.memory_budget == 0 {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let Some(client) = state.clients.get_mut(client) else {
+            return Ok(());
+        };
+        let window = Duration::from_secs(config.budget_window);
+        client.expire_usage(window, now);
+
+        client.check_budget(
+            RateLimit::CpuTime,
+            config.cpu_budget * 1000,
+            window,
+            now,
+            |usage| usage.cpu_time,
+        )?;
+        client.check_budget(
+            RateLimit::MemoryTime,
+            config.memory_budget * 1024 * 1000,
+            window,
+            now,
+            |usage| usage.memory_time,
+        )?;
+        Ok(())
+    }
+
+    /// Add the resources used by one of the client's programs to its budgets.
+    pub fn record(&self, config: &Config, client: &str, usage: &ResourceUsage, now: Instant) {
+        if config.cpu_budget == 0 && config.memory_budget == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.prune(config, now);
+        let client = state.client(config, client, now);
+        client.expire_usage(Duration::from_secs(config.budget_window), now);
+        client.usage.push_back(Usage {
+            at: now,
+            cpu_time: usage.time,
+            memory_time: usage.memory.saturating_mul(usage.time),
+        });
+    }
+}
+
+impl State {
+    fn client(&mut self, config: &Config, client: &str, now: Instant) -> &mut Client {
+        self.clients.entry(client.into()).or_insert_with(|| Client {
+            tokens: config.rate_limit_burst as f64,
+            updated: now,
+            usage: VecDeque::new(),
+        })
+    }
+
+    /// Forget clients whose token bucket has been refilled completely and
+    /// whose usage has left the budget window. This happens at most once per
+    /// budget window.
+    fn prune(&mut self, config: &Config, now: Instant) {
+        let window = Duration::from_secs(config.budget_window);
+        if now.saturating_duration_since(self.last_prune) < window {
+            return;
+        }
+        self.last_prune = now;
+
+        let burst = config.rate_limit_burst as f64;
+        self.clients.retain(|_, client| {
+            client.expire_usage(window, now);
+            let tokens = client.tokens
+                + now.duration_since(client.updated).as_secs_f64() * config.rate_limit_per_second;
+            tokens < burst || !client.usage.is_empty()
+        });
+    }
+}
+
+impl Client {
+    /// Fail if the total usage within the window has reached the budget. A
+    /// budget of zero is disabled.
+    fn check_budget(
+        &self,
+        limit: RateLimit,
+        budget: u64,
+        window: Duration,
+        now: Instant,
+        value: impl Fn(&Usage) -> u64,
+    ) -> Result<(), RateLimitExceeded> {
+        let mut total = self.usage.iter().map(&value).sum::<u64>();
+        if budget == 0 || total < budget {
+            return Ok(());
+        }
+
+        // find the first point in time at which enough usage has left the
+        // window to get below the budget again
+        let expires = self.usage.iter().find_map(|usage| {
+            total -= value(usage);
+            (total < budget).then_some(usage.at + window)
+        });
+        Err(RateLimitExceeded {
+            limit,
+            retry_after: retry_after(expires.unwrap_or(now).saturating_duration_since(now)),
+        })
+    }
+
+    fn expire_usage(&mut self, window: Duration, now: Instant) {
+        while self
+            .usage
+            .front()
+            .is_some_and(|usage| usage.at + window <= now)
+        {
+            self.usage.pop_front();
+        }
+    }
+}
+
+/// Convert a duration to the number of seconds for a `Retry-After` header.
+fn retry_after(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil().max(1.0) as u64
+}
@@ -1,19 +1,42 @@
 use std::{
     borrow::Cow,
     ffi::{OsStr, OsString},
+    future::Future,
     path::Path,
     process::Stdio,
     string::FromUtf8Error,
+    time::Instant,
 };
 
-use sandkasten_client::schemas::programs::{Limits, ResourceUsage, RunResult};
+use nix::{
+    sched::{sched_getaffinity, sched_setaffinity, CpuSet},
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use sandkasten_client::schemas::programs::{Limits, ResourceUsage, RunResult, TerminationReason};
 use thiserror::Error;
 use tokio::{
     fs,
     io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, Command},
 };
+use tokio_util::sync::CancellationToken;
 use tracing::error;
 
+tokio::task_local! {
+    /// The cancellation token of the asynchronous job the current task is
+    /// executing (see [`cancellable`]).
+    static CANCELLATION: CancellationToken;
+}
+
+/// Execute a future that can be cancelled using the given token. When the token
+/// is cancelled, all sandboxed processes started by the future are killed and
+/// report [`TerminationReason::Cancelled`], so the future still completes with
+/// the (partial) results of these processes.
+pub async fn cancellable<F: Future>(token: CancellationToken, future: F) -> F::Output {
+    CANCELLATION.scope(token, future).await
+}
+
 #[derive(Debug)]
 pub struct RunConfig<'a> {
     pub nsjail: &'a Path,
@@ -27,6 +50,9 @@ pub struct RunConfig<'a> {
     pub mounts: &'a [Mount<'a>],
     pub limits: Limits,
     pub use_cgroup: bool,
+    /// The cpu cores to pin the job to (see
+    /// [`Config::cpuset`](crate::config::Config::cpuset)).
+    pub cores: Option<&'a [usize]>,
 }
 
 #[derive(Debug)]
@@ -57,7 +83,7 @@ impl RunConfig<'_> {
         fs::write(&time_path, Vec::new()).await?;
 
         // construct the time/nsjail command
-        let mut cmd = tokio::process::Command::new(self.time);
+        let mut cmd = Command::new(self.time);
         cmd.arg("--quiet")
             .args(["--format", "%e %M %x"]) // elapsed time in seconds, max memory usage, exit code
             .arg("--output")
@@ -139,14 +165,35 @@ impl RunConfig<'_> {
             cmd.arg("-N").args(["-R", "/etc/resolv.conf"]);
         }
 
-        let mut child = cmd
-            .arg("--")
+        // the cpu affinity is inherited from nsjail (see `spawn_pinned`), so the
+        // sandboxed program must not be able to move to other cores
+        if self.cores.is_some() {
+            cmd.args([
+                "--seccomp_string",
+                "ERRNO(1) { sched_setaffinity } DEFAULT ALLOW",
+            ]);
+        }
+
+        // time and nsjail are started in a new process group, so they can be
+        // killed together if the job is cancelled (i.e. if this future is
+        // dropped or the cancellation token is cancelled). nsjail makes sure that the sandboxed process is killed as
+        // soon as nsjail itself exits.
+        cmd.arg("--")
             .arg(self.program)
             .args(self.args)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .stdin(Stdio::piped())
-            .spawn()?;
+            .process_group(0);
+        let cancellation = CANCELLATION
+            .try_with(CancellationToken::clone)
+            .unwrap_or_default();
+        let started_at = Instant::now();
+        let mut child = match self.cores {
+            Some(cores) => spawn_pinned(&mut cmd, cores)?,
+            None => cmd.spawn()?,
+        };
+        let mut process_group = KillOnDrop(child.id());
 
         // pass stdin to process
         if let Some(stdin) = &self.stdin {
@@ -158,7 +205,18 @@ impl RunConfig<'_> {
         let stdout_reader = BufReader::new(child.stdout.take().unwrap());
         let stderr_reader = BufReader::new(child.stderr.take().unwrap());
 
-        child.wait().await?;
+        let termination_reason = tokio::select! {
+            status = child.wait() => {
+                status?;
+                None
+            }
+            _ = cancellation.cancelled() => {
+                process_group.kill();
+                child.wait().await?;
+                Some(TerminationReason::Cancelled)
+            }
+        };
+        process_group.0 = None;
 
         // read stdout and stderr from process
         let mut stdout = Vec::new();
@@ -177,14 +235,24 @@ impl RunConfig<'_> {
         // read resource usage and status
         let time_file = fs::read_to_string(time_path).await?;
         let mut tf = time_file.split_whitespace();
-        let (time, memory, status) = (|| {
+        let usage = (|| {
             Some((
                 (tf.next()?.parse::<f32>().ok()? * 1000.0) as _,
                 tf.next()?.parse().ok()?,
                 tf.next()?.parse().ok()?,
             ))
-        })()
-        .ok_or(RunError::InvalidTimeFile)?;
+        })();
+        let (time, memory, status) = match (usage, termination_reason) {
+            (Some(usage), _) => usage,
+            // time is killed together with the sandbox, so it might not have
+            // written the resource usage
+            (None, Some(_)) => (
+                started_at.elapsed().as_millis() as _,
+                0,
+                128 + Signal::SIGKILL as i32,
+            ),
+            (None, None) => return Err(RunError::InvalidTimeFile),
+        };
 
         Ok(RunResult {
             status,
@@ -192,10 +260,51 @@ impl RunConfig<'_> {
             stderr,
             resource_usage: ResourceUsage { time, memory },
             limits: self.limits.clone(),
+            termination_reason,
         })
     }
 }
 
+/// Spawn a process that is pinned to the given cpu cores. The affinity of the
+/// current thread is changed temporarily, so it is inherited by the child.
+/// nsjail then restricts the sandboxed program to these cores, and seccomp
+/// prevents it from changing its affinity (`sched_setaffinity` fails with
+/// `EPERM`).
+fn spawn_pinned(cmd: &mut Command, cores: &[usize]) -> Result<Child, RunError> {
+    let mut cpuset = CpuSet::new();
+    for &core in cores {
+        cpuset.set(core)?;
+    }
+    let thread = Pid::from_raw(0);
+    let previous = sched_getaffinity(thread)?;
+    sched_setaffinity(thread, &cpuset)?;
+    let child = cmd.spawn();
+    if let Err(err) = sched_setaffinity(thread, &previous) {
+        error!("Failed to restore cpu affinity: {err:#}");
+    }
+    Ok(child?)
+}
+
+/// Kill a process group when dropped.
+struct KillOnDrop(Option<u32>);
+
+impl KillOnDrop {
+    fn kill(&mut self) {
+        let Some(pgid) = self.0.take() else {
+            return;
+        };
+        if let Err(err) = killpg(Pid::from_raw(pgid as _), Signal::SIGKILL) {
+            error!("Failed to kill process group {pgid}: {err:#}");
+        }
+    }
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum RunError {
     #[error("io error: {0}")]
@@ -204,4 +313,6 @@ pub enum RunError {
     StringConversionError(#[from] FromUtf8Error),
     #[error("time file has not been created correctly")]
     InvalidTimeFile,
+    #[error("failed to pin process to cpu cores: {0}")]
+    CpuAffinity(#[from] nix::Error),
 }
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use key_rwlock::KeyRwLock;
+use sandkasten_client::schemas::{
+    environments::SelfTest,
+    programs::{BuildRequest, File, RunRequest},
+};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    environments::Environments,
+    program::{
+        build::{build_program, BuildProgramError},
+        cache::CacheLock,
+        now,
+        run::run_program,
+        tenant::{StorageUsage, Tenant},
+    },
+    queue::{Demand, Pool, Resources},
+};
+
+/// The results of the most recent self-tests of all environments.
+pub struct SelfTests(HashMap<String, State>);
+
+#[derive(Default)]
+struct State {
+    result: RwLock<Option<SelfTest>>,
+    /// Held while a self-test of the environment is running.
+    running: Mutex<()>,
+}
+
+impl SelfTests {
+    pub fn new(environments: &Environments) -> Self {
+        Self(
+            environments
+                .keys()
+                .map(|id| (id.clone(), Default::default()))
+                .collect(),
+        )
+    }
+
+    /// Return the result of the most recent self-test of an environment.
+    pub fn get(&self, environment_id: &str) -> Option<SelfTest> {
+        self.0.get(environment_id)?.result.read().unwrap().clone()
+    }
+
+    /// Store the result of a self-test of an environment.
+    pub fn set(&self, environment_id: &str, result: SelfTest) {
+        if let Some(state) = self.0.get(environment_id) {
+            *state.result.write().unwrap() = Some(result);
+        }
+    }
+
+    /// Wait until no other self-test of an environment is running and return a
+    /// guard that prevents further self-tests of this environment from being
+    /// started until it is dropped. If a self-test has finished at or after
+    /// `since` (a unix timestamp) in the meantime, its result is returned
+    /// instead, so that concurrent requests don't run the same self-test
+    /// multiple times.
+    pub async fn lock(
+        &self,
+        environment_id: &str,
+        since: u64,
+    ) -> Option<Result<MutexGuard<'_, ()>, SelfTest>> {
+        let guard = self.0.get(environment_id)?.running.lock().await;
+        match self
+            .get(environment_id)
+            .filter(|result| result.tested_at >= since)
+        {
+            Some(result) => Some(Err(result)),
+            None => Some(Ok(guard)),
+        }
+    }
+
+    /// Return the reason why the most recent self-test of an environment has
+    /// failed. Environments that have not been tested yet are not considered
+    /// unhealthy.
+    pub fn failure(&self, environment_id: &str) -> Option<String> {
+        self.get(environment_id)
+            .filter(|result| !result.passed)
+            .map(|result| result.message.unwrap_or_default())
+    }
+}
+
+/// Return the resources to reserve for a self-test. The compile and run step
+/// share a single reservation.
+pub fn demand(config: &Config, priority: u32) -> Demand {
+    Demand {
+        pool: Pool::Run,
+        priority,
+        resources: Resources::job(&config.compile_limits).max(Resources::job(&config.run_limits)),
+    }
+}
+
+/// Return the input that is passed to the test programs of the environments.
+/// The test programs read it from stdin, the command line arguments and the
+/// file `test.txt` and fail if it is missing.
+pub fn test_input() -> RunRequest {
+    RunRequest {
+        stdin: Some("stdin".into()),
+        args: ["foo", "bar", "baz"].into_iter().map(Into::into).collect(),
+        files: vec![File {
+            name: "test.txt".into(),
+            content: "hello world".into(),
+        }],
+        ..Default::default()
+    }
+}
+
+/// Build and run the test program of an environment and check whether it
+/// produces the expected output.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_selftest(
+    config: Arc<Config>,
+    environments: Arc<Environments>,
+    storage_usage: &StorageUsage,
+    program_lock: Arc<KeyRwLock<Uuid>>,
+    job_lock: Arc<KeyRwLock<Uuid>>,
+    cache_lock: Arc<CacheLock>,
+    environment_id: &str,
+    cores: Option<&[usize]>,
+) -> SelfTest {
+    let result = selftest(
+        config,
+        environments,
+        storage_usage,
+        program_lock,
+        job_lock,
+        cache_lock,
+        environment_id,
+        cores,
+    )
+    .await;
+    if let Err(message) = &result {
+        warn!("Self-test of environment {environment_id} failed: {message}");
+    }
+    SelfTest {
+        passed: result.is_ok(),
+        message: result.err(),
+        tested_at: now(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn selftest(
+    config: Arc<Config>,
+    environments: Arc<Environments>,
+    storage_usage: &StorageUsage,
+    program_lock: Arc<KeyRwLock<Uuid>>,
+    job_lock: Arc<KeyRwLock<Uuid>>,
+    cache_lock: Arc<CacheLock>,
+    environment_id: &str,
+    cores: Option<&[usize]>,
+) -> Result<(), String> {
+    let Some(environment) = environments.get(environment_id) else {
+        return Err(format!("could not find environment {environment_id}"));
+    };
+    let expected = environment.test.expected.as_deref().unwrap_or("OK");
+
+    let (build, _guard) = match build_program(
+        Arc::clone(&config),
+        Arc::clone(&environments),
+        BuildRequest {
+            environment: environment_id.into(),
+            main_file: environment.test.main_file.clone(),
+            files: environment.test.files.clone(),
+            ..Default::default()
+        },
+        Tenant::default(),
+        storage_usage,
+        program_lock,
+        Arc::clone(&job_lock),
+        cache_lock,
+        cores,
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(BuildProgramError::CompilationFailed(err)) => {
+            return Err(format!(
+                "compilation failed (exit code {}): {}",
+                err.result.status,
+                err.result.stderr.trim()
+            ))
+        }
+        Err(err) => return Err(format!("build failed: {err}")),
+    };
+
+    let result = run_program(
+        config,
+        build.program_id,
+        test_input(),
+        Tenant::default(),
+        &_guard,
+        job_lock,
+        cores,
+    )
+    .await
+    .map_err(|err| format!("run failed: {err}"))?;
+    if result.status != 0 {
+        return Err(format!(
+            "program exited with code {}: {}",
+            result.status,
+            result.stderr.trim()
+        ));
+    }
+    if result.stdout.trim() != expected {
+        return Err(format!(
+            "expected output {expected:?}, got {:?}",
+            result.stdout.trim()
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{error, warn};
+use url::Url;
+
+use crate::config::Config;
+
+/// The name of the header that contains the signature of a webhook request.
+pub const SIGNATURE_HEADER: &str = "X-Sandkasten-Signature";
+
+/// Check whether a callback URL matches one of the allowed URL prefixes (see
+/// [`Config::webhook_allowed_urls`]).
+pub fn is_allowed_url(allowed_urls: &[String], url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    matches!(url.scheme(), "http" | "https")
+        && allowed_urls.iter().any(|allowed| {
+            Url::parse(allowed).is_ok_and(|allowed| {
+                url.origin() == allowed.origin() && url.path().starts_with(allowed.path())
+            })
+        })
+}
+
+/// Compute the signature of a webhook request body, i.e. the hex encoded
+/// HMAC-SHA256 of the body.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(body);
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// Create the http client used to deliver webhooks. Redirects are not
+/// followed, as they could lead to urls that are not allowed.
+pub fn client(config: &Config) -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(Duration::from_secs(config.webhook_timeout))
+        .build()
+        .expect("Failed to create http client")
+}
+
+/// Post a json body to a callback URL. Failed deliveries are retried with an
+/// exponential backoff until the maximum number of attempts is reached.
+pub async fn deliver(client: &reqwest::Client, config: &Config, url: &str, body: String) {
+    let signature = format!("sha256={}", sign(&config.webhook_secret, body.as_bytes()));
+    let mut delay = Duration::from_secs(config.webhook_retry_delay);
+    for attempt in 1..=config.webhook_attempts {
+        let result = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        let err = match result {
+            Ok(_) => return,
+            Err(err) => err,
+        };
+        if attempt == config.webhook_attempts {
+            error!("Failed to deliver webhook to {url} after {attempt} attempts: {err:#}");
+        } else {
+            warn!("Failed to deliver webhook to {url} (attempt {attempt}): {err:#}");
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
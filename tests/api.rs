@@ -1,17 +1,36 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::Arc,
+    time::Duration,
+};
 
-use indoc::formatdoc;
+use indoc::{formatdoc, indoc};
+use poem::{http::StatusCode, test::TestClient};
 use regex::Regex;
+use sandkasten::{
+    config::{self, ApiKey},
+    environments,
+    webhook::sign,
+};
 use sandkasten_client::{
     schemas::{
+        environments::{Environment, SelfTest},
+        jobs::{CancelJobError, CreateJobError, CreateJobRequest, GetJobError, JobStatus},
         programs::{
-            BuildError, BuildRequest, BuildRunError, BuildRunRequest, BuildRunResult, EnvVar, File,
-            LimitsOpt, MainFile, RunError, RunRequest, RunResult,
+            BuildArchiveRequest, BuildError, BuildRequest, BuildRunError, BuildRunRequest,
+            BuildRunResult, CheckError, DownloadFilesError, EnvVar, File, FormatError,
+            FormatRequest, LimitsOpt, MainFile, OptionValue, RunError, RunRequest, RunResult,
+            Severity, TerminationReason, TestError, TestRequest, TestStatus,
         },
         ErrorResponse,
     },
     Error,
 };
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
 
 use crate::common::client;
 
@@ -47,6 +66,75 @@ fn test_get_base_resource_usage() {
     client.get_base_resource_usage("python").unwrap();
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_selftest() {
+    // self-tests require an admin api key, so they are run against an api with
+    // the real environments instead of the server used by the other tests
+    let mut config = config::load().unwrap();
+    let dir = env::temp_dir().join(format!("sandkasten-test-{}", Uuid::new_v4()));
+    config.programs_dir = dir.join("programs");
+    config.jobs_dir = dir.join("jobs");
+    config.compile_cache_dir = dir.join("cache");
+    for path in [
+        &config.programs_dir,
+        &config.jobs_dir,
+        &config.compile_cache_dir,
+    ] {
+        fs::create_dir_all(path).unwrap();
+    }
+    config.api_keys = [("admin", true), ("user", false)]
+        .into_iter()
+        .map(|(name, admin)| ApiKey {
+            name: name.into(),
+            hash: format!("{:x}", Sha256::digest(name)),
+            compile_limits: Default::default(),
+            run_limits: Default::default(),
+            environments: None,
+            storage_quota: None,
+            priority: None,
+            admin,
+        })
+        .collect();
+    let environments = environments::load(&config.environments_path).unwrap();
+    let client = TestClient::new(common::app(
+        Arc::new(config),
+        Arc::new(environments),
+        Default::default(),
+    ));
+
+    client
+        .post("/environments/python/selftest")
+        .header("Authorization", "Bearer user")
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let response = client
+        .post("/environments/python/selftest")
+        .header("Authorization", "Bearer admin")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let result = response.json().await.value().deserialize::<SelfTest>();
+    assert!(result.passed, "{:?}", result.message);
+    assert_eq!(result.message, None);
+
+    let response = client
+        .get("/environments")
+        .header("Authorization", "Bearer admin")
+        .send()
+        .await;
+    let environments = response
+        .json()
+        .await
+        .value()
+        .deserialize::<BTreeMap<String, Environment>>();
+    assert_eq!(environments.get("python").unwrap().selftest, Some(result));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
 #[test]
 #[ignore]
 fn test_build_run_python() {
@@ -123,9 +211,12 @@ fn test_build_run_rust_compilation_error() {
     {
         sandkasten_client::Error::ErrorResponse(err) => match *err {
             ErrorResponse::Inner(BuildRunError::CompileError(response)) => {
-                assert_eq!(response.status, 1);
-                assert!(response.stdout.is_empty());
-                assert!(!response.stderr.is_empty());
+                assert_eq!(response.result.status, 1);
+                assert!(response.result.stdout.is_empty());
+                assert!(response
+                    .result
+                    .stderr
+                    .starts_with("error[E0425]: cannot find function `fn_not_found`"));
             }
             _ => panic!(),
         },
@@ -133,6 +224,464 @@ fn test_build_run_rust_compilation_error() {
     }
 }
 
+#[test]
+#[ignore]
+fn test_jobs() {
+    let client = client();
+    let request = |environment: &str| CreateJobRequest {
+        request: BuildRunRequest {
+            build: BuildRequest {
+                environment: environment.into(),
+                main_file: MainFile {
+                    name: Some("test.py".into()),
+                    content: "print(input())".into(),
+                },
+                ..Default::default()
+            },
+            run: RunRequest {
+                stdin: Some("hello".into()),
+                ..Default::default()
+            },
+        },
+        callback_url: None,
+    };
+    let wait = |id| loop {
+        let job = client.get_job(id).unwrap();
+        if job.status == JobStatus::Finished {
+            break job;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+
+    let job = client.create_job(&request("python")).unwrap();
+    let job = wait(job.id);
+    assert!(job.finished_at.is_some());
+    assert!(job.error.is_none());
+    assert_eq!(job.result.unwrap().run.stdout, "hello\n");
+
+    let job = client.create_job(&request("does_not_exist")).unwrap();
+    let error = wait(job.id).error.unwrap();
+    assert_eq!(error.status, 404);
+    assert!(matches!(
+        serde_json::from_value(error.response).unwrap(),
+        ErrorResponse::Inner(BuildRunError::EnvironmentNotFound)
+    ));
+
+    let Error::ErrorResponse(err) = client.get_job(Uuid::new_v4()).unwrap_err() else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(GetJobError::JobNotFound)
+    ));
+}
+
+#[test]
+#[ignore]
+fn test_cancel_job() {
+    let client = client();
+    let job = client
+        .create_job(&CreateJobRequest {
+            request: BuildRunRequest {
+                build: BuildRequest {
+                    environment: "python".into(),
+                    main_file: MainFile {
+                        name: Some("test.py".into()),
+                        content: "import time\ntime.sleep(5)\nprint('done')".into(),
+                    },
+                    ..Default::default()
+                },
+                run: Default::default(),
+            },
+            callback_url: None,
+        })
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+
+    let job = client.cancel_job(job.id).unwrap();
+    assert_eq!(job.status, JobStatus::Cancelled);
+    assert!(job.finished_at.is_some());
+    assert!(job.result.is_none());
+
+    // the results of the killed processes are stored as soon as they exit
+    std::thread::sleep(Duration::from_secs(1));
+    let job = client.get_job(job.id).unwrap();
+    assert_eq!(job.status, JobStatus::Cancelled);
+    let run = job.result.unwrap().run;
+    assert_eq!(run.termination_reason, Some(TerminationReason::Cancelled));
+    assert_eq!(run.stdout, "");
+
+    let Error::ErrorResponse(err) = client.cancel_job(Uuid::new_v4()).unwrap_err() else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(CancelJobError::JobNotFound)
+    ));
+}
+
+#[test]
+#[ignore]
+fn test_job_webhook() {
+    // stand-in receiver, see `webhook_allowed_urls` in the dev shell config
+    let listener = TcpListener::bind("127.0.0.1:8001").unwrap();
+    let client = client();
+    let mut request = CreateJobRequest {
+        request: BuildRunRequest {
+            build: BuildRequest {
+                environment: "python".into(),
+                main_file: MainFile {
+                    name: Some("test.py".into()),
+                    content: "print(42)".into(),
+                },
+                ..Default::default()
+            },
+            run: Default::default(),
+        },
+        callback_url: Some("http://127.0.0.1:8001/callback".into()),
+    };
+    let job = client.create_job(&request).unwrap();
+
+    // the first delivery fails and has to be retried
+    let receive = |status: &str| {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut headers = BTreeMap::new();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "POST /callback HTTP/1.1\r\n");
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            let Some((name, value)) = line.trim().split_once(": ") else {
+                break;
+            };
+            headers.insert(name.to_lowercase(), value.to_owned());
+        }
+        let mut body = vec![0; headers["content-length"].parse().unwrap()];
+        reader.read_exact(&mut body).unwrap();
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+        )
+        .unwrap();
+        (headers, body)
+    };
+    receive("500 Internal Server Error");
+    let (headers, body) = receive("200 OK");
+
+    assert_eq!(
+        headers["x-sandkasten-signature"],
+        format!("sha256={}", sign("sandkasten", &body))
+    );
+    let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+    assert_eq!(body["id"], job.id.to_string());
+    assert_eq!(body["status"], "finished");
+    assert_eq!(body["result"]["run"]["stdout"], "42\n");
+
+    request.callback_url = Some("http://127.0.0.1:8002/callback".into());
+    let Error::ErrorResponse(err) = client.create_job(&request).unwrap_err() else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(CreateJobError::CallbackUrlNotAllowed)
+    ));
+}
+
+#[test]
+#[ignore]
+fn test_build_diagnostics() {
+    let Error::ErrorResponse(err) = client()
+        .build(&BuildRequest {
+            environment: "c".into(),
+            main_file: MainFile {
+                name: Some("test.c".into()),
+                content: "int main() {\n  return x;\n}\n".into(),
+            },
+            ..Default::default()
+        })
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    let ErrorResponse::Inner(BuildError::CompileError(err)) = *err else {
+        panic!()
+    };
+    let diagnostic = &err.diagnostics[0];
+    assert_eq!(diagnostic.file.as_deref(), Some("test.c"));
+    assert_eq!(diagnostic.line, Some(2));
+    assert_eq!(diagnostic.column, Some(10));
+    assert_eq!(diagnostic.severity, Severity::Error);
+}
+
+#[test]
+#[ignore]
+fn test_check() {
+    let client = client();
+    let result = client
+        .check(&BuildRequest {
+            environment: "c".into(),
+            main_file: MainFile {
+                name: Some("test.c".into()),
+                content: "int main() {\n  return x;\n}\n".into(),
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    assert_ne!(result.result.status, 0);
+    assert_eq!(result.diagnostics[0].line, Some(2));
+
+    let result = client
+        .check(&BuildRequest {
+            environment: "python".into(),
+            main_file: MainFile {
+                name: Some("test.py".into()),
+                content: "print(42)".into(),
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(result.result.status, 0);
+
+    let Error::ErrorResponse(err) = client
+        .check(&BuildRequest {
+            environment: "bash".into(),
+            ..Default::default()
+        })
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(CheckError::CheckNotSupported)
+    ));
+}
+
+#[test]
+#[ignore]
+fn test_format() {
+    let client = client();
+    let result = client
+        .format(&FormatRequest {
+            environment: "rust".into(),
+            main_file: MainFile {
+                name: Some("test.rs".into()),
+                content: "fn main(){println!(\"Hello\")}".into(),
+            },
+            files: vec![File {
+                name: "foo/mod.rs".into(),
+                content: "pub fn bar( )->i32{42}".into(),
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(result.main_file.name, "test.rs");
+    assert_eq!(
+        result.main_file.content,
+        "fn main() {\n    println!(\"Hello\")\n}\n"
+    );
+    assert_eq!(result.files[0].name, "foo/mod.rs");
+    assert_eq!(
+        result.files[0].content,
+        "pub fn bar() -> i32 {\n    42\n}\n"
+    );
+
+    let Error::ErrorResponse(err) = client
+        .format(&FormatRequest {
+            environment: "rust".into(),
+            main_file: MainFile {
+                name: None,
+                content: "fn main() {".into(),
+            },
+            ..Default::default()
+        })
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    let ErrorResponse::Inner(FormatError::FormatFailed(result)) = *err else {
+        panic!()
+    };
+    assert_ne!(result.status, 0);
+}
+
+#[test]
+#[ignore]
+fn test_unit_tests() {
+    let client = client();
+    let program = client
+        .build(&BuildRequest {
+            environment: "python".into(),
+            main_file: MainFile {
+                name: Some("code.py".into()),
+                content: "def add(a, b):\n    return a + b\n".into(),
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    let result = client
+        .test(
+            program.program_id,
+            &TestRequest {
+                files: vec![File {
+                    name: "test_code.py".into(),
+                    content: indoc! {"
+                        from code import add
+
+                        def test_ok():
+                            assert add(1, 2) == 3
+
+                        def test_fail():
+                            assert add(1, 2) == 4
+                    "}
+                    .into(),
+                }],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_ne!(result.result.status, 0);
+    let tests = result
+        .tests
+        .iter()
+        .map(|test| (test.name.as_str(), test.status))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tests,
+        [
+            ("test_code.test_ok", TestStatus::Passed),
+            ("test_code.test_fail", TestStatus::Failed),
+        ]
+    );
+
+    let program = client
+        .build(&BuildRequest {
+            environment: "bash".into(),
+            main_file: MainFile {
+                name: None,
+                content: "echo hi".into(),
+            },
+            ..Default::default()
+        })
+        .unwrap();
+    let Error::ErrorResponse(err) = client
+        .test(program.program_id, &TestRequest::default())
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(TestError::TestsNotSupported)
+    ));
+}
+
+#[test]
+#[ignore]
+fn test_build_compilation_error_cached() {
+    let client = client();
+    let request = BuildRequest {
+        environment: "rust".into(),
+        main_file: MainFile {
+            name: Some("test.rs".into()),
+            content: format!(
+                "fn main() {{ fn_not_found(); }} // {}",
+                uuid::Uuid::new_v4()
+            ),
+        },
+        ..Default::default()
+    };
+
+    let Error::ErrorResponse(err) = client.build(&request).unwrap_err() else {
+        panic!()
+    };
+    let ErrorResponse::Inner(BuildError::CompileError(first)) = *err else {
+        panic!()
+    };
+    assert!(!first.cached);
+    assert_eq!(first.result.status, 1);
+
+    let Error::ErrorResponse(err) = client.build(&request).unwrap_err() else {
+        panic!()
+    };
+    let ErrorResponse::Inner(BuildError::CompileError(second)) = *err else {
+        panic!()
+    };
+    assert!(second.cached);
+    assert_eq!(second.result, first.result);
+}
+
+#[test]
+#[ignore]
+fn test_build_compilation_error_limits_not_cached() {
+    let client = client();
+    let mut request = BuildRequest {
+        environment: "rust".into(),
+        main_file: MainFile {
+            name: Some("test.rs".into()),
+            content: format!("fn main() {{}} // {}", Uuid::new_v4()),
+        },
+        compile_limits: LimitsOpt {
+            memory: Some(16),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    for _ in 0..2 {
+        let Error::ErrorResponse(err) = client.build(&request).unwrap_err() else {
+            panic!()
+        };
+        let ErrorResponse::Inner(BuildError::CompileError(err)) = *err else {
+            panic!()
+        };
+        assert!(!err.cached);
+    }
+
+    // retrying with higher limits compiles the program
+    request.compile_limits = Default::default();
+    let result = client.build(&request).unwrap();
+    assert!(!result.cached);
+    assert_eq!(result.compile_result.unwrap().status, 0);
+}
+
+#[test]
+#[ignore]
+fn test_build_compilation_error_higher_limits() {
+    let client = client();
+    let mut request = BuildRequest {
+        environment: "rust".into(),
+        main_file: MainFile {
+            name: Some("test.rs".into()),
+            content: format!("fn main() {{}} // {}", Uuid::new_v4()),
+        },
+        compile_limits: LimitsOpt {
+            filesize: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    // the binary is larger than the file size limit
+    for cached in [false, true] {
+        let Error::ErrorResponse(err) = client.build(&request).unwrap_err() else {
+            panic!()
+        };
+        let ErrorResponse::Inner(BuildError::CompileError(err)) = *err else {
+            panic!()
+        };
+        assert_eq!(err.cached, cached);
+    }
+
+    // the cached error is not returned for requests with higher limits
+    request.compile_limits = Default::default();
+    let result = client.build(&request).unwrap();
+    assert!(!result.cached);
+    assert_eq!(result.compile_result.unwrap().status, 0);
+}
+
 #[test]
 #[ignore]
 fn test_build_run_rust_ok() {
@@ -205,6 +754,7 @@ fn test_build_cached() {
         ttl: _,
         cached,
         build,
+        diagnostics: _,
         run,
     }: BuildRunResult = client.build_and_run(&request).unwrap();
     let build = build.unwrap();
@@ -379,6 +929,254 @@ fn test_build_run_errors() {
     assert!(les.pop().is_none());
 }
 
+#[test]
+#[ignore]
+fn test_download_files() {
+    let client = client();
+    let build = client
+        .build(&BuildRequest {
+            environment: "rust".into(),
+            main_file: MainFile {
+                name: Some("test.rs".into()),
+                content: "fn main() { println!(\"test_download_files\"); }".into(),
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+    let archive = client.download_files(build.program_id).unwrap();
+    let mut archive = tar::Archive::new(archive.as_slice());
+    let names = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().into_owned())
+        .collect::<Vec<_>>();
+    assert!(names.iter().any(|n| n.ends_with("binary")));
+
+    let Error::ErrorResponse(err) = client
+        .download_files("00000000-0000-0000-0000-000000000000")
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(DownloadFilesError::ProgramNotFound)
+    ));
+}
+
+#[test]
+#[ignore]
+fn test_nested_files() {
+    let result = client()
+        .build_and_run(&BuildRunRequest {
+            build: BuildRequest {
+                environment: "python".into(),
+                main_file: MainFile {
+                    name: Some("app/main.py".into()),
+                    content: indoc! {"
+                        from pkg.sub.mod import hello
+                        hello()
+                        print(open('data/input.txt').read())
+                    "}
+                    .into(),
+                },
+                files: vec![File {
+                    name: "app/pkg/sub/mod.py".into(),
+                    content: "def hello():\n    print('hello')\n".into(),
+                }],
+                ..Default::default()
+            },
+            run: RunRequest {
+                files: vec![File {
+                    name: "data/input.txt".into(),
+                    content: "world".into(),
+                }],
+                ..Default::default()
+            },
+        })
+        .unwrap();
+    assert_eq!(result.run.status, 0);
+    assert_eq!(result.run.stdout, "hello\nworld\n");
+}
+
+#[test]
+#[ignore]
+fn test_options() {
+    let client = client();
+    let result = client
+        .build_and_run(&BuildRunRequest {
+            build: BuildRequest {
+                environment: "cpp".into(),
+                main_file: MainFile {
+                    name: None,
+                    content: indoc! {"
+                        #include <iostream>
+                        int main() { std::cout << __cplusplus; }
+                    "}
+                    .into(),
+                },
+                options: BTreeMap::from([("std".into(), OptionValue::String("c++17".into()))]),
+                ..Default::default()
+            },
+            run: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(result.run.stdout, "201703");
+
+    let result = client
+        .build_and_run(&BuildRunRequest {
+            build: BuildRequest {
+                environment: "python".into(),
+                main_file: MainFile {
+                    name: None,
+                    content: "import sys; print(sys.flags.dev_mode)".into(),
+                },
+                ..Default::default()
+            },
+            run: RunRequest {
+                options: BTreeMap::from([("dev_mode".into(), OptionValue::Bool(true))]),
+                ..Default::default()
+            },
+        })
+        .unwrap();
+    assert_eq!(result.run.stdout, "True\n");
+
+    let Error::ErrorResponse(err) = client
+        .build(&BuildRequest {
+            environment: "cpp".into(),
+            options: BTreeMap::from([("std".into(), OptionValue::String("c++98".into()))]),
+            ..Default::default()
+        })
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(BuildError::InvalidOptions(_))
+    ));
+
+    let Error::ErrorResponse(err) = client
+        .run(
+            result.program_id,
+            &RunRequest {
+                options: BTreeMap::from([("foo".into(), OptionValue::Bool(true))]),
+                ..Default::default()
+            },
+        )
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(RunError::InvalidOptions(_))
+    ));
+}
+
+#[test]
+#[ignore]
+fn test_packages() {
+    let client = client();
+    let result = client
+        .build_and_run(&BuildRunRequest {
+            build: BuildRequest {
+                environment: "python".into(),
+                main_file: MainFile {
+                    name: None,
+                    content: "import pandas; print(pandas.Series([1, 2, 3]).sum())".into(),
+                },
+                packages: vec!["pandas".into()],
+                ..Default::default()
+            },
+            run: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(result.run.status, 0);
+    assert_eq!(result.run.stdout, "6\n");
+
+    let result = client
+        .build_and_run(&BuildRunRequest {
+            build: BuildRequest {
+                environment: "python".into(),
+                main_file: MainFile {
+                    name: None,
+                    content: "import pandas; print(pandas.Series([1, 2, 3]).sum())".into(),
+                },
+                ..Default::default()
+            },
+            run: Default::default(),
+        })
+        .unwrap();
+    assert_ne!(result.run.status, 0);
+
+    let Error::ErrorResponse(err) = client
+        .build(&BuildRequest {
+            environment: "python".into(),
+            packages: vec!["this_package_does_not_exist".into()],
+            ..Default::default()
+        })
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(BuildError::UnknownPackage(_))
+    ));
+}
+
+#[test]
+#[ignore]
+fn test_build_archive() {
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    ));
+    for (path, content) in [
+        ("./main.py", "from pkg.mod import hello\nhello()\n"),
+        ("pkg/mod.py", "def hello():\n    print('hello from pkg')\n"),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as _);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, content.as_bytes())
+            .unwrap();
+    }
+    let archive = builder.into_inner().unwrap().finish().unwrap();
+
+    let client = client();
+    let build = client
+        .build_archive(&BuildArchiveRequest {
+            environment: "python".into(),
+            main_file: Some("main.py".into()),
+            archive,
+            ..Default::default()
+        })
+        .unwrap();
+    let run = client.run(build.program_id, &Default::default()).unwrap();
+    assert_eq!(run.status, 0);
+    assert_eq!(run.stdout, "hello from pkg\n");
+
+    let Error::ErrorResponse(err) = client
+        .build_archive(&BuildArchiveRequest {
+            environment: "python".into(),
+            archive: b"not an archive".to_vec(),
+            ..Default::default()
+        })
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(BuildError::InvalidArchive(_))
+    ));
+}
+
 #[test]
 #[ignore]
 fn test_build_errors() {
@@ -438,6 +1236,55 @@ fn test_build_errors() {
         ErrorResponse::Inner(BuildError::InvalidFileNames)
     ));
 
+    for name in [
+        "../test.py",
+        "foo/./test.py",
+        "foo//test.py",
+        "foo/test.py/",
+    ] {
+        let Error::ErrorResponse(err) = client
+            .build(&BuildRequest {
+                environment: "python".into(),
+                files: vec![File {
+                    name: name.into(),
+                    content: "".into(),
+                }],
+                ..Default::default()
+            })
+            .unwrap_err()
+        else {
+            panic!()
+        };
+        assert!(matches!(
+            *err,
+            ErrorResponse::Inner(BuildError::InvalidFileNames)
+        ));
+    }
+
+    let Error::ErrorResponse(err) = client
+        .build(&BuildRequest {
+            environment: "python".into(),
+            files: vec![
+                File {
+                    name: "foo".into(),
+                    content: "".into(),
+                },
+                File {
+                    name: "foo/bar.py".into(),
+                    content: "".into(),
+                },
+            ],
+            ..Default::default()
+        })
+        .unwrap_err()
+    else {
+        panic!()
+    };
+    assert!(matches!(
+        *err,
+        ErrorResponse::Inner(BuildError::InvalidFileNames)
+    ));
+
     let Error::ErrorResponse(err) = client
         .build(&BuildRequest {
             environment: "python".into(),
@@ -0,0 +1,112 @@
+use std::{sync::Arc, time::Instant};
+
+use poem::{http::StatusCode, test::TestClient, Endpoint};
+use sandkasten::{
+    config::{ApiKey, Config},
+    rate_limit::RateLimiter,
+};
+use sandkasten_client::schemas::programs::ResourceUsage;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    config.rate_limit_burst = 0;
+    config.cpu_budget = 1;
+    config.max_archive_size = 16;
+    config.api_keys = vec![ApiKey {
+        name: "foo".into(),
+        hash: format!("{:x}", Sha256::digest("foo")),
+        compile_limits: Default::default(),
+        run_limits: Default::default(),
+        environments: None,
+        storage_quota: None,
+        priority: None,
+        admin: false,
+    }];
+    config
+}
+
+fn app(config: Arc<Config>, rate_limiter: Arc<RateLimiter>) -> impl Endpoint {
+    common::app(config, Default::default(), rate_limiter)
+}
+
+/// Encode a multipart request that uploads a source archive.
+fn multipart(archive: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        b"--boundary\r\n\
+          Content-Disposition: form-data; name=\"environment\"\r\n\r\n\
+          python\r\n\
+          --boundary\r\n\
+          Content-Disposition: form-data; name=\"archive\"; filename=\"src.tar\"\r\n\
+          Content-Type: application/octet-stream\r\n\r\n",
+    );
+    body.extend_from_slice(archive);
+    body.extend_from_slice(b"\r\n--boundary--\r\n");
+    body
+}
+
+async fn build_archive(client: &TestClient<impl Endpoint>, archive: &[u8]) -> StatusCode {
+    let body = multipart(archive);
+    client
+        .post("/programs")
+        .header("Authorization", "Bearer foo")
+        .content_type("multipart/form-data; boundary=boundary")
+        .header("Content-Length", body.len())
+        .body(body)
+        .send()
+        .await
+        .0
+        .status()
+}
+
+#[tokio::test]
+async fn size_limit() {
+    let config = Arc::new(config());
+    let client = TestClient::new(app(config, Default::default()));
+
+    // the request is processed (but the environment does not exist here)
+    assert_eq!(
+        build_archive(&client, b"not an archive").await,
+        StatusCode::NOT_FOUND
+    );
+
+    // the body of large requests is not received at all
+    assert_eq!(
+        build_archive(&client, &vec![0; 2 << 20]).await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+
+    // json requests are not affected (even without a content length)
+    client
+        .post("/programs")
+        .header("Authorization", "Bearer foo")
+        .body_json(&json!({"environment": "python", "main_file": {"content": ""}}))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn budget_before_extraction() {
+    let config = Arc::new(config());
+    let rate_limiter = Arc::new(RateLimiter::default());
+    rate_limiter.record(
+        &config,
+        "foo",
+        &ResourceUsage {
+            time: 2000,
+            memory: 0,
+        },
+        Instant::now(),
+    );
+    let client = TestClient::new(app(config, rate_limiter));
+
+    assert_eq!(
+        build_archive(&client, b"not an archive").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use poem::{http::StatusCode, test::TestClient, Endpoint};
+use sandkasten::{
+    auth::Caller,
+    config::{ApiKey, Config},
+};
+use sandkasten_client::schemas::programs::LimitsOpt;
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    config.compile_limits.network = true;
+    config.run_limits.network = true;
+    config.api_keys = vec![
+        ApiKey {
+            name: "unrestricted".into(),
+            // sha256("foo")
+            hash: "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae".into(),
+            compile_limits: Default::default(),
+            run_limits: Default::default(),
+            environments: None,
+            storage_quota: None,
+            priority: None,
+            admin: true,
+        },
+        ApiKey {
+            name: "restricted".into(),
+            // sha256("bar")
+            hash: "FCDE2B2EDBA56BF408601FB721FE9B5C338D10EE429EA04FAE5511B68FBF8FB9".into(),
+            compile_limits: LimitsOpt {
+                network: Some(false),
+                ..Default::default()
+            },
+            run_limits: LimitsOpt {
+                time: Some(1),
+                memory: Some(u64::MAX),
+                network: Some(false),
+                ..Default::default()
+            },
+            environments: Some(vec!["python".into()]),
+            storage_quota: Some(1 << 20),
+            priority: Some("batch".into()),
+            admin: false,
+        },
+    ];
+    config
+}
+
+#[test]
+fn authentication_disabled() {
+    let mut config = config();
+    config.api_keys.clear();
+    let caller = Caller::authenticate(&config, None, "127.0.0.1").unwrap();
+    assert_eq!(caller.client, "127.0.0.1");
+    assert_eq!(caller.name, None);
+    assert_eq!(caller.tenant().name, None);
+    assert_eq!(caller.tenant().storage_quota, None);
+    assert_eq!(caller.compile_limits, config.compile_limits);
+    assert_eq!(caller.run_limits, config.run_limits);
+    assert!(caller.can_use_environment("rust"));
+    assert!(!caller.admin);
+    assert!(Caller::authenticate(&config, Some("foo"), "127.0.0.1").is_some());
+}
+
+#[test]
+fn priority() {
+    let mut config = config();
+    let mut caller = Caller::authenticate(&config, Some("foo"), "127.0.0.1").unwrap();
+    assert_eq!(caller.priority, 1);
+    assert!(!caller.select_priority(&config, "interactive"));
+    assert!(!caller.select_priority(&config, "unknown"));
+    assert!(caller.select_priority(&config, "batch"));
+    assert_eq!(caller.priority, 0);
+
+    let mut caller = Caller::authenticate(&config, Some("bar"), "127.0.0.1").unwrap();
+    assert_eq!(caller.priority, 0);
+    assert!(!caller.select_priority(&config, "normal"));
+
+    config.api_keys.clear();
+    let mut caller = Caller::authenticate(&config, None, "127.0.0.1").unwrap();
+    assert_eq!(caller.priority, 1);
+    assert!(caller.select_priority(&config, "interactive"));
+    assert_eq!(caller.priority, 2);
+}
+
+#[test]
+fn invalid_api_key() {
+    let config = config();
+    assert!(Caller::authenticate(&config, None, "127.0.0.1").is_none());
+    assert!(Caller::authenticate(&config, Some(""), "127.0.0.1").is_none());
+    assert!(Caller::authenticate(&config, Some("baz"), "127.0.0.1").is_none());
+    assert!(Caller::authenticate(
+        &config,
+        Some("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"),
+        "127.0.0.1"
+    )
+    .is_none());
+}
+
+#[test]
+fn unrestricted_api_key() {
+    let config = config();
+    let caller = Caller::authenticate(&config, Some("foo"), "127.0.0.1").unwrap();
+    assert_eq!(caller.client, "unrestricted");
+    assert_eq!(caller.name.as_deref(), Some("unrestricted"));
+    assert!(caller.admin);
+    assert_eq!(caller.compile_limits, config.compile_limits);
+    assert_eq!(caller.run_limits, config.run_limits);
+    assert!(caller.can_use_environment("python"));
+    assert!(caller.can_use_environment("rust"));
+}
+
+#[test]
+fn restricted_api_key() {
+    let config = config();
+    let caller = Caller::authenticate(&config, Some("bar"), "127.0.0.1").unwrap();
+    assert_eq!(caller.name.as_deref(), Some("restricted"));
+    assert!(!caller.admin);
+    assert_eq!(caller.tenant().name, Some("restricted"));
+    assert_eq!(caller.tenant().storage_quota, Some(1 << 20));
+    assert!(!caller.compile_limits.network);
+    assert!(!caller.run_limits.network);
+    assert_eq!(caller.run_limits.time, 1);
+    assert_eq!(caller.run_limits.memory, config.run_limits.memory);
+    assert_eq!(caller.run_limits.cpus, config.run_limits.cpus);
+    assert!(caller.can_use_environment("python"));
+    assert!(!caller.can_use_environment("rust"));
+
+    let mut limits = LimitsOpt::default();
+    caller.check_run_limits(&mut limits).unwrap();
+    assert_eq!(limits.time, Some(1));
+    assert_eq!(limits.network, Some(false));
+
+    let mut limits = LimitsOpt {
+        time: Some(2),
+        network: Some(true),
+        ..Default::default()
+    };
+    let errors = caller.check_run_limits(&mut limits).unwrap_err();
+    let errors = errors
+        .iter()
+        .map(|err| (err.name.as_str(), err.max_value))
+        .collect::<Vec<_>>();
+    assert_eq!(errors, [("time", 1), ("network", 0)]);
+}
+
+fn app(config: Config) -> impl Endpoint {
+    common::app(Arc::new(config), Default::default(), Default::default())
+}
+
+#[tokio::test]
+async fn admin_endpoints() {
+    let mut config = config();
+    config.rate_limit_burst = 0;
+    let client = TestClient::new(app(config));
+    for (key, status) in [
+        ("foo", StatusCode::OK),
+        ("bar", StatusCode::FORBIDDEN),
+        ("baz", StatusCode::UNAUTHORIZED),
+    ] {
+        client
+            .put("/admin/drain")
+            .header("Authorization", format!("Bearer {key}"))
+            .send()
+            .await
+            .assert_status(status);
+    }
+    for (key, status) in [
+        ("foo", StatusCode::NOT_FOUND),
+        ("bar", StatusCode::FORBIDDEN),
+    ] {
+        client
+            .post("/environments/foo/selftest")
+            .header("Authorization", format!("Bearer {key}"))
+            .send()
+            .await
+            .assert_status(status);
+    }
+}
+
+#[tokio::test]
+async fn admin_endpoints_authentication_disabled() {
+    let mut config = config();
+    config.api_keys.clear();
+    config.rate_limit_burst = 0;
+    let client = TestClient::new(app(config));
+    client
+        .put("/admin/drain")
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    client
+        .get("/admin/status")
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    client
+        .post("/environments/foo/selftest")
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+}
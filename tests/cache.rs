@@ -0,0 +1,123 @@
+use std::{env, fs, time::Duration};
+
+use key_rwlock::KeyRwLock;
+use sandkasten::{config::Config, program::cache::CacheShard};
+use sandkasten_client::schemas::programs::{ResourceUsage, RunResult};
+use uuid::Uuid;
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    config.compile_cache_dir = env::temp_dir().join(format!("sandkasten-test-{}", Uuid::new_v4()));
+    config.compile_cache_max_size = 16;
+    config.compile_cache_shards = 2;
+    config
+}
+
+fn result(config: &Config, status: i32) -> RunResult {
+    RunResult {
+        status,
+        stdout: String::new(),
+        stderr: String::new(),
+        resource_usage: ResourceUsage { time: 0, memory: 0 },
+        limits: config.compile_limits.clone(),
+        termination_reason: None,
+    }
+}
+
+#[tokio::test]
+async fn shard_selection() {
+    let config = config();
+    let lock = KeyRwLock::new();
+
+    // concurrent compile steps use different shards
+    let first = CacheShard::acquire(&config, &lock, Some("foo"), "rust")
+        .await
+        .unwrap();
+    let second = CacheShard::acquire(&config, &lock, Some("foo"), "rust")
+        .await
+        .unwrap();
+    assert_ne!(first.path(), second.path());
+
+    // shards are not shared between tenants and environments
+    let mut paths = vec![first.path().clone(), second.path().clone()];
+    for (tenant, environment) in [(Some("bar"), "rust"), (None, "rust"), (Some("foo"), "go")] {
+        let shard = CacheShard::acquire(&config, &lock, tenant, environment)
+            .await
+            .unwrap();
+        paths.push(shard.path().clone());
+    }
+    for (i, a) in paths.iter().enumerate() {
+        assert!(a.starts_with(&config.compile_cache_dir));
+        for b in &paths[i + 1..] {
+            assert!(!a.starts_with(b) && !b.starts_with(a));
+        }
+    }
+
+    // released shards are reused
+    let path = first.path().clone();
+    first.release(Some(&result(&config, 0))).await;
+    let third = CacheShard::acquire(&config, &lock, Some("foo"), "rust")
+        .await
+        .unwrap();
+    assert_eq!(third.path(), &path);
+
+    fs::remove_dir_all(&config.compile_cache_dir).unwrap();
+}
+
+#[tokio::test]
+async fn clear() {
+    let config = config();
+    let lock = KeyRwLock::new();
+    let acquire = || CacheShard::acquire(&config, &lock, Some("foo"), "rust");
+
+    // shards are kept if the compile step has finished properly
+    let shard = acquire().await.unwrap();
+    let path = shard.path().clone();
+    fs::write(path.join("small"), [0; 8]).unwrap();
+    shard.release(Some(&result(&config, 1))).await;
+    assert!(path.join("small").exists());
+
+    // shards that exceed the maximum size are cleared
+    let shard = acquire().await.unwrap();
+    fs::write(shard.path().join("large"), [0; 16]).unwrap();
+    shard.release(Some(&result(&config, 0))).await;
+    assert!(!path.exists());
+
+    // shards are cleared after killed or failed compile steps
+    for result in [Some(&result(&config, 137)), None] {
+        let shard = acquire().await.unwrap();
+        assert_eq!(shard.path(), &path);
+        fs::write(path.join("small"), [0; 8]).unwrap();
+        shard.release(result).await;
+        assert!(!path.exists());
+    }
+
+    fs::remove_dir_all(&config.compile_cache_dir).unwrap();
+}
+
+#[tokio::test]
+async fn clear_cancelled() {
+    let mut config = config();
+    // with a single shard, the next compile step has to wait until the shard
+    // has been cleared
+    config.compile_cache_shards = 1;
+    let lock = KeyRwLock::new();
+    let acquire = || CacheShard::acquire(&config, &lock, Some("foo"), "rust");
+
+    // shards are cleared if the compile step has been cancelled
+    let shard = acquire().await.unwrap();
+    let path = shard.path().clone();
+    fs::write(path.join("small"), [0; 8]).unwrap();
+    drop(shard);
+    let shard = tokio::time::timeout(Duration::from_secs(1), acquire())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(shard.path(), &path);
+    assert!(!path.join("small").exists());
+    shard.release(Some(&result(&config, 0))).await;
+
+    fs::remove_dir_all(&config.compile_cache_dir).unwrap();
+}
@@ -1,5 +1,27 @@
+#![allow(dead_code)]
+
+use std::{
+    env,
+    sync::{Arc, Mutex},
+};
+
+use key_rwlock::KeyRwLock;
+use poem::{Endpoint, EndpointExt, Route};
+use poem_openapi::OpenApiService;
+use sandkasten::{
+    api::get_api,
+    config::{self, Config},
+    environments::Environments,
+    metrics::Metrics,
+    queue::JobQueue,
+    rate_limit::RateLimiter,
+    selftest::SelfTests,
+};
 use sandkasten_client::BlockingSandkastenClient;
 
+/// Held while the environment variables for the test configuration are set.
+static LOCK: Mutex<()> = Mutex::new(());
+
 pub fn client() -> BlockingSandkastenClient {
     BlockingSandkastenClient::new(
         option_env!("TARGET_HOST")
@@ -8,3 +30,41 @@ pub fn client() -> BlockingSandkastenClient {
             .unwrap(),
     )
 }
+
+/// Load the default configuration from `config.toml` for tests that don't
+/// need a running server.
+pub fn config() -> Config {
+    let _guard = LOCK.lock().unwrap();
+    env::set_var("NSJAIL_PATH", "/");
+    env::set_var("TIME_PATH", "/");
+    env::set_var(
+        "CONFIG_PATH",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml"),
+    );
+    config::load().unwrap()
+}
+
+/// Create the api endpoint for in-process tests.
+pub fn app(
+    config: Arc<Config>,
+    environments: Arc<Environments>,
+    rate_limiter: Arc<RateLimiter>,
+) -> impl Endpoint {
+    let api = get_api(
+        Arc::clone(&config),
+        Arc::clone(&environments),
+        Arc::new(KeyRwLock::new()),
+        Arc::new(KeyRwLock::new()),
+        Arc::new(KeyRwLock::new()),
+        Arc::clone(&rate_limiter),
+        Arc::new(JobQueue::new(&config)),
+        Arc::new(SelfTests::new(&environments)),
+        Default::default(),
+        Default::default(),
+    );
+    Route::new()
+        .nest("/", OpenApiService::new(api, "Sandkasten", "test"))
+        .data(Arc::new(Metrics::new().unwrap()))
+        .data(config)
+        .data(rate_limiter)
+}
@@ -59,6 +59,31 @@ fn cannot_canonicalize() {
     assert!(err.to_string().starts_with("Failed to resolve `time_path`"));
 }
 
+#[test]
+fn webhook_secret_required() {
+    let _guard = LOCK.lock().unwrap();
+    env::set_var("NSJAIL_PATH", "/");
+    env::set_var("TIME_PATH", "/");
+    env::set_var("WEBHOOK_ALLOWED_URLS", "http://127.0.0.1:8001/");
+    env::set_var(
+        "CONFIG_PATH",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml"),
+    );
+    let err = config::load().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "`webhook_secret` must be set if `webhook_allowed_urls` is not empty"
+    );
+
+    env::set_var("WEBHOOK_SECRET", "secret");
+    let conf = config::load().unwrap();
+    assert_eq!(conf.webhook_allowed_urls, ["http://127.0.0.1:8001/"]);
+    assert_eq!(conf.webhook_secret, "secret");
+
+    env::remove_var("WEBHOOK_ALLOWED_URLS");
+    env::remove_var("WEBHOOK_SECRET");
+}
+
 #[test]
 fn environments_path_from_env() {
     let _guard = LOCK.lock().unwrap();
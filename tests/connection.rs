@@ -0,0 +1,94 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use poem::{
+    handler,
+    listener::{Acceptor, Listener, TcpListener},
+    post,
+    web::Data,
+    EndpointExt, Route, Server,
+};
+use sandkasten::connection::{CancelOnDisconnect, ConnectionAcceptor, Connections};
+
+/// Sets a flag when the request handler is dropped before it has finished.
+struct Cancelled(Arc<AtomicBool>);
+
+impl Drop for Cancelled {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[handler]
+async fn sleep(body: String, cancelled: Data<&Arc<AtomicBool>>) -> String {
+    let guard = Cancelled(Arc::clone(&cancelled));
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    std::mem::forget(guard);
+    body
+}
+
+/// Start a server and return its address and a flag that is set when a request
+/// has been cancelled.
+async fn server() -> (String, Arc<AtomicBool>) {
+    let acceptor = TcpListener::bind("127.0.0.1:0")
+        .into_acceptor()
+        .await
+        .unwrap();
+    let addr = acceptor.local_addr()[0]
+        .as_socket_addr()
+        .unwrap()
+        .to_string();
+    let connections = Arc::new(Connections::default());
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let app = Route::new()
+        .at("/", post(sleep))
+        .data(Arc::clone(&cancelled))
+        .with(CancelOnDisconnect(Arc::clone(&connections)));
+    tokio::spawn(
+        Server::new_with_acceptor(ConnectionAcceptor::new(acceptor, connections)).run(app),
+    );
+    (addr, cancelled)
+}
+
+fn send_request(addr: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nHost: sandkasten\r\nContent-Length: 2\r\n\r\nok")
+        .unwrap();
+    stream
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancel_on_disconnect() {
+    let (addr, cancelled) = server().await;
+
+    let stream = send_request(&addr);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(cancelled.load(Ordering::SeqCst));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connected_clients_are_not_cancelled() {
+    let (addr, cancelled) = server().await;
+
+    let response = tokio::task::spawn_blocking(move || {
+        let mut stream = send_request(&addr);
+        let mut response = [0; 1024];
+        let n = stream.read(&mut response).unwrap();
+        String::from_utf8_lossy(&response[..n]).into_owned()
+    })
+    .await
+    .unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nok"), "{response}");
+    assert!(!cancelled.load(Ordering::SeqCst));
+}
@@ -0,0 +1,228 @@
+use indoc::indoc;
+use sandkasten::program::diagnostics::{
+    parse_diagnostics, parse_output, render_output, DiagnosticsFormat,
+};
+use sandkasten_client::schemas::programs::{
+    Diagnostic, Limits, ResourceUsage, RunResult, Severity,
+};
+
+fn diagnostic(
+    file: &str,
+    line: u32,
+    column: Option<u32>,
+    severity: Severity,
+    message: &str,
+    code: Option<&str>,
+) -> Diagnostic {
+    Diagnostic {
+        file: Some(file.into()),
+        line: Some(line),
+        column,
+        severity,
+        message: message.into(),
+        code: code.map(Into::into),
+    }
+}
+
+#[test]
+fn gcc() {
+    let output = indoc! {"
+        code.c: In function 'main':
+        code.c:3:9: warning: unused variable 'x' [-Wunused-variable]
+            3 |     int x;
+              |         ^
+        /box/foo/bar.c:5:5: error: 'y' undeclared (first use in this function)
+        code.c:5:5: note: each undeclared identifier is reported only once
+        code.c:1:10: fatal error: foo.h: No such file or directory
+        compilation terminated.
+    "};
+    assert_eq!(
+        parse_output(DiagnosticsFormat::Gcc, output),
+        [
+            diagnostic(
+                "code.c",
+                3,
+                Some(9),
+                Severity::Warning,
+                "unused variable 'x'",
+                Some("-Wunused-variable")
+            ),
+            diagnostic(
+                "foo/bar.c",
+                5,
+                Some(5),
+                Severity::Error,
+                "'y' undeclared (first use in this function)",
+                None
+            ),
+            diagnostic(
+                "code.c",
+                5,
+                Some(5),
+                Severity::Note,
+                "each undeclared identifier is reported only once",
+                None
+            ),
+            diagnostic(
+                "code.c",
+                1,
+                Some(10),
+                Severity::Error,
+                "foo.h: No such file or directory",
+                None
+            ),
+        ]
+    );
+}
+
+#[test]
+fn rustc_json() {
+    let output = [
+        r#"{"$message_type":"diagnostic","message":"mismatched types","code":{"code":"E0308","explanation":"..."},"level":"error","spans":[{"file_name":"code.rs","byte_start":30,"byte_end":32,"line_start":2,"line_end":2,"column_start":18,"column_end":20,"is_primary":true,"text":[],"label":"expected `u32`","suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[],"rendered":"error[E0308]: mismatched types\n"}"#,
+        r#"{"$message_type":"diagnostic","message":"unused variable: `x`","code":{"code":"unused_variables","explanation":null},"level":"warning","spans":[{"file_name":"code.rs","byte_start":20,"byte_end":21,"line_start":2,"line_end":2,"column_start":9,"column_end":10,"is_primary":true,"text":[],"label":null,"suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[],"rendered":"warning: unused variable\n"}"#,
+        r#"{"$message_type":"diagnostic","message":"aborting due to 1 previous error","code":null,"level":"error","spans":[],"children":[],"rendered":"error: aborting due to 1 previous error\n"}"#,
+    ]
+    .join("\n");
+    assert_eq!(
+        parse_output(DiagnosticsFormat::RustcJson, &output),
+        [
+            diagnostic(
+                "code.rs",
+                2,
+                Some(18),
+                Severity::Error,
+                "mismatched types",
+                Some("E0308")
+            ),
+            diagnostic(
+                "code.rs",
+                2,
+                Some(9),
+                Severity::Warning,
+                "unused variable: `x`",
+                Some("unused_variables")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn rustc_json_rendered() {
+    let stderr = [
+        r#"{"$message_type":"diagnostic","message":"mismatched types","code":{"code":"E0308","explanation":"..."},"level":"error","spans":[{"file_name":"code.rs","byte_start":30,"byte_end":32,"line_start":2,"line_end":2,"column_start":18,"column_end":20,"is_primary":true,"text":[],"label":"expected `u32`","suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[],"rendered":"error[E0308]: mismatched types\n --> code.rs:2:18\n\n"}"#,
+        r#"{"$message_type":"artifact","artifact":"/program/binary","emit":"link"}"#,
+        "note: some linker output",
+    ]
+    .join("\n");
+    let mut result = RunResult {
+        status: 1,
+        stdout: String::new(),
+        stderr,
+        resource_usage: ResourceUsage { time: 0, memory: 0 },
+        limits: Limits {
+            cpus: 1,
+            time: 1,
+            memory: 1,
+            tmpfs: 1,
+            filesize: 1,
+            file_descriptors: 1,
+            processes: 1,
+            stdout_max_size: 1,
+            stderr_max_size: 1,
+            network: false,
+        },
+        termination_reason: None,
+    };
+
+    let diagnostics = parse_diagnostics(DiagnosticsFormat::RustcJson, &result);
+    render_output(DiagnosticsFormat::RustcJson, &mut result);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        result.stderr,
+        "error[E0308]: mismatched types\n --> code.rs:2:18\n\nnote: some linker output\n"
+    );
+
+    // the output of other compilers is not changed
+    let stderr = result.stderr.clone();
+    render_output(DiagnosticsFormat::Gcc, &mut result);
+    assert_eq!(result.stderr, stderr);
+}
+
+#[test]
+fn javac() {
+    let output = indoc! {r#"
+        code.java:3: error: cannot find symbol
+                System.out.println(x);
+                                   ^
+          symbol:   variable x
+          location: class Main
+        code.java:5: warning: [removal] Integer(int) in Integer has been deprecated
+        1 error
+    "#};
+    assert_eq!(
+        parse_output(DiagnosticsFormat::Javac, output),
+        [
+            diagnostic(
+                "code.java",
+                3,
+                Some(28),
+                Severity::Error,
+                "cannot find symbol",
+                None
+            ),
+            diagnostic(
+                "code.java",
+                5,
+                None,
+                Severity::Warning,
+                "[removal] Integer(int) in Integer has been deprecated",
+                None
+            ),
+        ]
+    );
+}
+
+#[test]
+fn tsc() {
+    let output = indoc! {"
+        code.ts(1,5): error TS2322: Type 'string' is not assignable to type 'number'.
+        lib/foo.ts(10,1): error TS2304: Cannot find name 'bar'.
+    "};
+    assert_eq!(
+        parse_output(DiagnosticsFormat::Tsc, output),
+        [
+            diagnostic(
+                "code.ts",
+                1,
+                Some(5),
+                Severity::Error,
+                "Type 'string' is not assignable to type 'number'.",
+                Some("TS2322")
+            ),
+            diagnostic(
+                "lib/foo.ts",
+                10,
+                Some(1),
+                Severity::Error,
+                "Cannot find name 'bar'.",
+                Some("TS2304")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn generic() {
+    let output = indoc! {"
+        # command-line-arguments
+        ./code.go:5:2: undefined: x
+        code.go:7: warning: something
+    "};
+    assert_eq!(
+        parse_output(DiagnosticsFormat::Generic, output),
+        [
+            diagnostic("code.go", 5, Some(2), Severity::Error, "undefined: x", None),
+            diagnostic("code.go", 7, None, Severity::Warning, "something", None),
+        ]
+    );
+}
@@ -0,0 +1,167 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use sandkasten::{
+    config::Config, environments::Environments, health::check_readiness, queue::JobQueue,
+    selftest::SelfTests,
+};
+use sandkasten_client::schemas::{environments::SelfTest, health::HealthCheck};
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    let dir = env::temp_dir();
+    config.nsjail_path = PathBuf::from("/bin/sh").canonicalize().unwrap();
+    config.time_path = PathBuf::from("/bin/sh").canonicalize().unwrap();
+    config.programs_dir = dir.clone();
+    config.jobs_dir = dir;
+    config.min_free_space = 0;
+    config.use_cgroup = false;
+    config
+}
+
+fn failed(checks: &[HealthCheck]) -> Vec<&str> {
+    checks
+        .iter()
+        .filter(|check| !check.healthy)
+        .map(|check| {
+            assert!(check.message.is_some());
+            check.name.as_str()
+        })
+        .collect()
+}
+
+fn environments() -> Environments {
+    ["foo", "bar"]
+        .into_iter()
+        .map(|id| {
+            (
+                id.into(),
+                serde_json::from_value(serde_json::json!({
+                    "name": id,
+                    "version": "1",
+                    "meta": {},
+                    "default_main_file_name": "main",
+                    "run_script": "/bin/run",
+                    "closure": "/nix/store/closure",
+                    "test": { "main_file": { "content": "" }, "files": [] },
+                    "sandkasten_version": "0"
+                }))
+                .unwrap(),
+            )
+        })
+        .collect()
+}
+
+fn selftest(passed: bool) -> SelfTest {
+    SelfTest {
+        passed,
+        message: (!passed).then(|| "expected output \"OK\", got \"\"".into()),
+        tested_at: 0,
+    }
+}
+
+#[test]
+fn ready() {
+    let config = config();
+    let queue = JobQueue::new(&config);
+    let environments = environments();
+    let selftests = SelfTests::new(&environments);
+    selftests.set("foo", selftest(true));
+    selftests.set("bar", selftest(false));
+
+    let checks = check_readiness(&config, &environments, &selftests, &queue);
+    assert_eq!(failed(&checks), Vec::<&str>::new());
+}
+
+#[test]
+fn selftests() {
+    let mut config = config();
+    let queue = JobQueue::new(&config);
+    let environments = environments();
+    let selftests = SelfTests::new(&environments);
+    assert_eq!(selftests.get("foo"), None);
+    assert_eq!(selftests.failure("foo"), None);
+
+    // untested environments are only usable if self-tests are disabled
+    config.selftest_on_startup = true;
+    let checks = check_readiness(&config, &environments, &selftests, &queue);
+    assert_eq!(failed(&checks), ["environments"]);
+    config.selftest_on_startup = false;
+    let checks = check_readiness(&config, &environments, &selftests, &queue);
+    assert_eq!(failed(&checks), Vec::<&str>::new());
+
+    selftests.set("foo", selftest(false));
+    selftests.set("bar", selftest(false));
+    assert_eq!(
+        selftests.failure("foo").as_deref(),
+        Some("expected output \"OK\", got \"\"")
+    );
+    let checks = check_readiness(&config, &environments, &selftests, &queue);
+    assert_eq!(failed(&checks), ["environments"]);
+
+    selftests.set("foo", selftest(true));
+    assert_eq!(selftests.get("foo"), Some(selftest(true)));
+    assert_eq!(selftests.failure("foo"), None);
+    let checks = check_readiness(&config, &environments, &selftests, &queue);
+    assert_eq!(failed(&checks), Vec::<&str>::new());
+
+    // unknown environments are ignored
+    selftests.set("baz", selftest(false));
+    assert_eq!(selftests.get("baz"), None);
+}
+
+#[test]
+fn not_ready() {
+    let mut config = config();
+    config.nsjail_path = "/".into();
+    config.jobs_dir = "/nonexistent".into();
+    config.min_free_space = u64::MAX;
+    let queue = JobQueue::new(&config);
+    queue.set_draining(true);
+
+    let environments = Environments::new();
+    let checks = check_readiness(
+        &config,
+        &environments,
+        &SelfTests::new(&environments),
+        &queue,
+    );
+    assert_eq!(
+        failed(&checks),
+        [
+            "nsjail",
+            "programs_dir",
+            "jobs_dir",
+            "environments",
+            "drain"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn concurrent_selftests() {
+    let environments = environments();
+    let selftests = SelfTests::new(&environments);
+    assert!(selftests.lock("baz", 0).await.is_none());
+
+    // a second self-test has to wait for the running one to finish
+    let guard = selftests.lock("foo", 0).await.unwrap().unwrap();
+    let waiting = selftests.lock("foo", 0);
+    let other = selftests.lock("bar", 0).await.unwrap().unwrap();
+    drop(other);
+    tokio::pin!(waiting);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), &mut waiting)
+            .await
+            .is_err()
+    );
+
+    // and then reuses its result instead of running again
+    selftests.set("foo", selftest(true));
+    drop(guard);
+    assert_eq!(waiting.await.unwrap().unwrap_err(), selftest(true));
+
+    // older results are not reused
+    assert!(selftests.lock("foo", 1).await.unwrap().is_ok());
+}
@@ -0,0 +1,183 @@
+use std::{env, fs, path::PathBuf, sync::Arc};
+
+use key_rwlock::KeyRwLock;
+use poem::{http::StatusCode, test::TestClient};
+use sandkasten::{
+    config::Config,
+    environments::Environments,
+    program::{
+        manifest::{migrate_programs, Manifest, ManifestError},
+        prune::prune_programs,
+    },
+};
+use sandkasten_client::schemas::programs::{ResourceUsage, RunResult};
+use serde_json::json;
+use uuid::Uuid;
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    config.programs_dir = env::temp_dir().join(format!("sandkasten-test-{}", Uuid::new_v4()));
+    config.rate_limit_burst = 0;
+    fs::create_dir_all(&config.programs_dir).unwrap();
+    config
+}
+
+fn environments() -> Environments {
+    [(
+        "foo".into(),
+        serde_json::from_value(json!({
+            "name": "foo",
+            "version": "1",
+            "meta": {},
+            "default_main_file_name": "main",
+            "run_script": "/bin/run",
+            "closure": "/nix/store/closure",
+            "test_script": "/bin/test",
+            "test": { "main_file": { "content": "" }, "files": [] },
+            "sandkasten_version": "0"
+        }))
+        .unwrap(),
+    )]
+    .into()
+}
+
+/// Store a program in the legacy layout and return its directory.
+fn store_legacy_program(config: &Config, run_script: &str, ok: bool) -> PathBuf {
+    let path = config.programs_dir.join(Uuid::new_v4().to_string());
+    fs::create_dir_all(path.join("files")).unwrap();
+    fs::write(path.join("run_script"), run_script).unwrap();
+    fs::write(path.join("closure"), "/nix/store/closure").unwrap();
+    fs::write(path.join("main_file"), "main.py").unwrap();
+    fs::write(path.join("last_run"), "42").unwrap();
+    if ok {
+        fs::write(path.join("ok"), "").unwrap();
+    }
+    path
+}
+
+/// Store a program with a manifest that has been written by a different
+/// version of sandkasten and return its id.
+fn store_unsupported_program(config: &Config) -> Uuid {
+    let id = Uuid::new_v4();
+    let path = config.programs_dir.join(id.to_string());
+    fs::create_dir_all(path.join("files")).unwrap();
+    fs::write(path.join("manifest.json"), r#"{"version": 999}"#).unwrap();
+    id
+}
+
+#[tokio::test]
+async fn migrate() {
+    let config = config();
+    let environments = environments();
+
+    let legacy = store_legacy_program(&config, "/bin/run", true);
+    let compile_result = RunResult {
+        status: 0,
+        stdout: String::new(),
+        stderr: "warning".into(),
+        resource_usage: ResourceUsage { time: 1, memory: 2 },
+        limits: config.compile_limits.clone(),
+        termination_reason: None,
+    };
+    // the legacy layout stored results without a termination reason
+    let legacy_result = (
+        compile_result.status,
+        &compile_result.stdout,
+        &compile_result.stderr,
+        compile_result.resource_usage,
+        &compile_result.limits,
+    );
+    fs::write(
+        legacy.join("compile_result"),
+        postcard::to_stdvec(&legacy_result).unwrap(),
+    )
+    .unwrap();
+    let unknown = store_legacy_program(&config, "/bin/unknown", true);
+    let incomplete = store_legacy_program(&config, "/bin/run", false);
+    let unsupported = config
+        .programs_dir
+        .join(store_unsupported_program(&config).to_string());
+
+    migrate_programs(&config, &environments).await.unwrap();
+
+    let manifest = Manifest::load(&legacy).await.unwrap().unwrap();
+    assert_eq!(manifest.environment, "foo");
+    assert_eq!(manifest.main_file, "main.py");
+    assert_eq!(manifest.test_script.as_deref(), Some("/bin/test"));
+    assert_eq!(manifest.compile_result, Some(compile_result));
+    assert_eq!((manifest.created_at, manifest.last_run), (42, 42));
+    assert_eq!(manifest.tenant, None);
+    for file in ["run_script", "closure", "main_file", "compile_result", "ok"] {
+        assert!(!legacy.join(file).exists(), "{file}");
+    }
+
+    // programs of unknown environments are deleted, incomplete programs are
+    // left for the next prune
+    assert!(!unknown.exists());
+    assert!(incomplete.join("run_script").exists());
+    assert_eq!(
+        fs::read_to_string(unsupported.join("manifest.json")).unwrap(),
+        r#"{"version": 999}"#
+    );
+
+    // migrating again does not change anything
+    migrate_programs(&config, &environments).await.unwrap();
+    let migrated = Manifest::load(&legacy).await.unwrap().unwrap();
+    assert_eq!(migrated.compile_result, manifest.compile_result);
+
+    fs::remove_dir_all(&config.programs_dir).unwrap();
+}
+
+#[tokio::test]
+async fn unsupported_version() {
+    let config = config();
+    let id = store_unsupported_program(&config);
+    let path = config.programs_dir.join(id.to_string());
+
+    assert!(matches!(
+        Manifest::load(&path).await,
+        Err(ManifestError::UnsupportedVersion(999))
+    ));
+    assert!(Manifest::load_supported(&path).await.unwrap().is_none());
+
+    // programs with an unsupported manifest version are treated as missing
+    let programs_dir = config.programs_dir.clone();
+    let client = TestClient::new(common::app(
+        Arc::new(config),
+        Arc::new(environments()),
+        Default::default(),
+    ));
+    for endpoint in ["run", "test"] {
+        let response = client
+            .post(format!("/programs/{id}/{endpoint}"))
+            .body_json(&json!({}))
+            .send()
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        response
+            .assert_json(json!({"error": "program_not_found"}))
+            .await;
+    }
+    client
+        .get(format!("/programs/{id}/files"))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    fs::remove_dir_all(programs_dir).unwrap();
+}
+
+#[tokio::test]
+async fn prune_unsupported_version() {
+    let config = config();
+    let id = store_unsupported_program(&config);
+
+    prune_programs(&config, Arc::new(KeyRwLock::new()), Default::default())
+        .await
+        .unwrap();
+    assert!(!config.programs_dir.join(id.to_string()).exists());
+
+    fs::remove_dir_all(&config.programs_dir).unwrap();
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use sandkasten::program::options::{option_env_vars, resolve_options, InvalidOptionError};
+use sandkasten_client::schemas::{
+    environments::{BoolOption, EnumOption, EnvironmentOption, IntOption},
+    programs::OptionValue,
+};
+
+fn declared() -> BTreeMap<String, EnvironmentOption> {
+    BTreeMap::from([
+        (
+            "debug".into(),
+            EnvironmentOption::Bool(BoolOption {
+                description: None,
+                default: false,
+            }),
+        ),
+        (
+            "opt_level".into(),
+            EnvironmentOption::Int(IntOption {
+                description: None,
+                default: 2,
+                min: Some(0),
+                max: Some(3),
+            }),
+        ),
+        (
+            "std".into(),
+            EnvironmentOption::Enum(EnumOption {
+                description: None,
+                default: "c++20".into(),
+                values: vec!["c++17".into(), "c++20".into()],
+            }),
+        ),
+    ])
+}
+
+#[test]
+fn defaults() {
+    let options = resolve_options(&declared(), &BTreeMap::new()).unwrap();
+    assert_eq!(
+        options,
+        BTreeMap::from([
+            ("debug".into(), "false".into()),
+            ("opt_level".into(), "2".into()),
+            ("std".into(), "c++20".into()),
+        ])
+    );
+    assert_eq!(
+        option_env_vars(&options),
+        [
+            ("SANDKASTEN_OPTION_DEBUG".into(), "false"),
+            ("SANDKASTEN_OPTION_OPT_LEVEL".into(), "2"),
+            ("SANDKASTEN_OPTION_STD".into(), "c++20"),
+        ]
+    );
+}
+
+#[test]
+fn values() {
+    let options = resolve_options(
+        &declared(),
+        &BTreeMap::from([
+            ("debug".into(), OptionValue::Bool(true)),
+            ("opt_level".into(), OptionValue::Int(0)),
+            ("std".into(), OptionValue::String("c++17".into())),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(
+        options,
+        BTreeMap::from([
+            ("debug".into(), "true".into()),
+            ("opt_level".into(), "0".into()),
+            ("std".into(), "c++17".into()),
+        ])
+    );
+}
+
+#[test]
+fn invalid() {
+    let resolve = |name: &str, value| {
+        resolve_options(&declared(), &BTreeMap::from([(name.into(), value)])).unwrap_err()
+    };
+    assert!(matches!(
+        resolve("foo", OptionValue::Bool(true)),
+        InvalidOptionError::UnknownOption(_)
+    ));
+    assert!(matches!(
+        resolve("debug", OptionValue::Int(1)),
+        InvalidOptionError::InvalidType(_)
+    ));
+    assert!(matches!(
+        resolve("opt_level", OptionValue::String("3".into())),
+        InvalidOptionError::InvalidType(_)
+    ));
+    assert!(matches!(
+        resolve("opt_level", OptionValue::Int(4)),
+        InvalidOptionError::OutOfRange(_)
+    ));
+    assert!(matches!(
+        resolve("std", OptionValue::String("c++23".into())),
+        InvalidOptionError::InvalidValue(_)
+    ));
+}
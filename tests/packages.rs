@@ -1,8 +1,11 @@
 #![cfg(feature = "nix")]
 
-use sandkasten::environments::{self, Environment};
+use sandkasten::{
+    environments::{self, Environment},
+    selftest::test_input,
+};
 use sandkasten_client::schemas::programs::{
-    BuildRequest, BuildRunRequest, BuildRunResult, File, MainFile, RunRequest,
+    BuildRequest, BuildRunRequest, BuildRunResult, MainFile, RunRequest,
 };
 
 use crate::common::client;
@@ -19,15 +22,7 @@ fn test_package(id: &str) {
             files: environment.test.files,
             ..Default::default()
         },
-        run: RunRequest {
-            stdin: Some("stdin".into()),
-            args: ["foo", "bar", "baz"].into_iter().map(Into::into).collect(),
-            files: vec![File {
-                name: "test.txt".into(),
-                content: "hello world".into(),
-            }],
-            ..Default::default()
-        },
+        run: test_input(),
     })) {
         Ok(response) => {
             assert_ok(&response, environment.compile_script.is_some());
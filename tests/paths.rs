@@ -0,0 +1,57 @@
+use sandkasten::program::{check_path, check_paths, normalize_path};
+
+#[test]
+fn normalize() {
+    assert_eq!(normalize_path("foo.py").as_deref(), Some("foo.py"));
+    assert_eq!(
+        normalize_path("./foo/bar.py").as_deref(),
+        Some("foo/bar.py")
+    );
+    assert_eq!(normalize_path("foo//bar/").as_deref(), Some("foo/bar"));
+    assert_eq!(normalize_path(".foo/.bar").as_deref(), Some(".foo/.bar"));
+}
+
+#[test]
+fn normalize_invalid() {
+    assert_eq!(normalize_path(""), None);
+    assert_eq!(normalize_path("."), None);
+    assert_eq!(normalize_path("/etc/passwd"), None);
+    assert_eq!(normalize_path("../foo"), None);
+    assert_eq!(normalize_path("foo/../../bar"), None);
+    assert_eq!(normalize_path("foo/.../bar"), None);
+    assert_eq!(normalize_path("foo\0bar"), None);
+}
+
+#[test]
+fn paths() {
+    assert!(check_paths([]));
+    assert!(check_paths(["a", "b", "c/d", "c/e", "f/g/h"]));
+    assert!(!check_paths(["a", "a"]));
+    assert!(!check_paths(["a", "a/b"]));
+    assert!(!check_paths(["a/b/c", "a/b"]));
+}
+
+#[test]
+fn valid_paths() {
+    assert!(check_path("main.py"));
+    assert!(check_path("com/example/Main.java"));
+    assert!(check_path(".hidden/file"));
+    assert!(check_path(&["a"; 8].join("/")));
+    assert!(check_path(&"a".repeat(64)));
+}
+
+#[test]
+fn invalid_paths() {
+    assert!(!check_path(""));
+    assert!(!check_path("."));
+    assert!(!check_path(".."));
+    assert!(!check_path("./main.py"));
+    assert!(!check_path("foo//bar"));
+    assert!(!check_path("foo/"));
+    assert!(!check_path("/foo"));
+    assert!(!check_path("foo/../bar"));
+    assert!(!check_path("foo bar"));
+    assert!(!check_path(&["a"; 9].join("/")));
+    assert!(!check_path(&"a".repeat(65)));
+    assert!(!check_path(&format!("{0}/{0}/{0}/{0}", "a".repeat(64))));
+}
@@ -0,0 +1,371 @@
+use std::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    task::Poll,
+    time::Duration,
+};
+
+use sandkasten::{
+    config::Config,
+    metrics::Metrics,
+    queue::{Demand, JobQueue, Pool, QueueError, Resources},
+};
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    config.max_concurrent_jobs = 2;
+    config.max_queue_length = 1;
+    config.max_queue_wait = 60;
+    config.host_cpus = Some(4);
+    config.host_memory = Some(1024);
+    config.priority_aging = 0;
+    config
+}
+
+fn demand(pool: Pool, priority: u32, resources: Resources) -> Demand {
+    Demand {
+        pool,
+        priority,
+        resources,
+    }
+}
+
+fn jobs(jobs: u64) -> Demand {
+    demand(
+        Pool::Run,
+        0,
+        Resources {
+            jobs,
+            cpus: 1,
+            memory: 1,
+        },
+    )
+}
+
+fn job(cpus: u64, memory: u64) -> Demand {
+    demand(
+        Pool::Run,
+        0,
+        Resources {
+            jobs: 1,
+            cpus,
+            memory,
+        },
+    )
+}
+
+#[tokio::test]
+async fn queue_full() {
+    let config = config();
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+
+    let permit = queue.acquire(&metrics, jobs(2)).await.unwrap();
+    let waiting = queue.acquire(&metrics, jobs(1));
+    tokio::pin!(waiting);
+    assert!(poll_once(&mut waiting).await.is_none());
+    assert_eq!(metrics.queue.length.get(), 1);
+    assert!(matches!(
+        queue.acquire(&metrics, jobs(1)).await,
+        Err(QueueError::Full)
+    ));
+    assert_eq!(
+        metrics
+            .queue
+            .rejected
+            .with_label_values(&["queue_full"])
+            .get(),
+        1
+    );
+
+    drop(permit);
+    let _permit = waiting.await.unwrap();
+    assert_eq!(metrics.queue.length.get(), 0);
+    let _permit = queue.acquire(&metrics, jobs(1)).await.unwrap();
+}
+
+#[tokio::test]
+async fn queue_timeout() {
+    let mut config = config();
+    config.max_queue_wait = 0;
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+
+    // free slots are still acquired, but requests that would have to wait are
+    // rejected immediately
+    let _permit = queue.acquire(&metrics, jobs(2)).await.unwrap();
+    assert!(matches!(
+        queue.acquire(&metrics, jobs(1)).await,
+        Err(QueueError::Timeout)
+    ));
+    assert_eq!(metrics.queue.length.get(), 0);
+    assert_eq!(
+        metrics
+            .queue
+            .rejected
+            .with_label_values(&["queue_timeout"])
+            .get(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn resources() {
+    let config = config();
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+    assert_eq!(
+        queue.capacity(),
+        Resources {
+            jobs: 2,
+            cpus: 4,
+            memory: 1024
+        }
+    );
+
+    let first = queue.acquire(&metrics, job(3, 512)).await.unwrap();
+    assert_eq!(metrics.queue.reserved.cpus.get(), 3);
+    assert_eq!(metrics.queue.reserved.memory.get(), 512);
+
+    // not enough cpus left
+    let waiting = queue.acquire(&metrics, job(2, 256));
+    tokio::pin!(waiting);
+    assert!(poll_once(&mut waiting).await.is_none());
+
+    drop(first);
+    let second = waiting.await.unwrap();
+    assert_eq!(metrics.queue.reserved.jobs.get(), 1);
+    assert_eq!(metrics.queue.reserved.cpus.get(), 2);
+    drop(second);
+    assert_eq!(metrics.queue.reserved.jobs.get(), 0);
+    assert_eq!(metrics.queue.reserved.cpus.get(), 0);
+    assert_eq!(metrics.queue.reserved.memory.get(), 0);
+}
+
+#[tokio::test]
+async fn fifo() {
+    let mut config = config();
+    config.max_queue_length = 2;
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+
+    let first = queue.acquire(&metrics, job(3, 1)).await.unwrap();
+    let large = queue.acquire(&metrics, job(4, 1));
+    tokio::pin!(large);
+    assert!(poll_once(&mut large).await.is_none());
+
+    // a small job would fit, but has to wait behind the large one
+    let small = queue.acquire(&metrics, job(1, 1));
+    tokio::pin!(small);
+    assert!(poll_once(&mut small).await.is_none());
+    assert_eq!(metrics.queue.length.get(), 2);
+
+    drop(first);
+    let large = large.await.unwrap();
+    assert!(poll_once(&mut small).await.is_none());
+    drop(large);
+    let _small = small.await.unwrap();
+}
+
+#[tokio::test]
+async fn cancelled() {
+    let mut config = config();
+    config.max_queue_length = 2;
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+
+    let first = queue.acquire(&metrics, job(3, 1)).await.unwrap();
+    let mut large = Box::pin(queue.acquire(&metrics, job(4, 1)));
+    assert!(poll_once(&mut large).await.is_none());
+    let small = queue.acquire(&metrics, job(1, 1));
+    tokio::pin!(small);
+    assert!(poll_once(&mut small).await.is_none());
+
+    // the small job is admitted as soon as the large one stops waiting
+    drop(large);
+    assert_eq!(metrics.queue.length.get(), 1);
+    let _small = small.await.unwrap();
+    drop(first);
+}
+
+#[tokio::test]
+async fn clamped() {
+    let config = config();
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+
+    // jobs requesting more than the host provides can still run alone
+    let permit = queue.acquire(&metrics, job(8, 4096)).await.unwrap();
+    assert_eq!(permit.cores(), None);
+    assert_eq!(metrics.queue.reserved.cpus.get(), 4);
+    assert_eq!(metrics.queue.reserved.memory.get(), 1024);
+}
+
+#[tokio::test]
+async fn priority() {
+    let mut config = config();
+    config.max_concurrent_jobs = 1;
+    config.max_queue_length = 2;
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+
+    let first = queue.acquire(&metrics, jobs(1)).await.unwrap();
+    let low = queue.acquire(&metrics, jobs(1));
+    tokio::pin!(low);
+    assert!(poll_once(&mut low).await.is_none());
+    let high = queue.acquire(
+        &metrics,
+        Demand {
+            priority: 1,
+            ..jobs(1)
+        },
+    );
+    tokio::pin!(high);
+    assert!(poll_once(&mut high).await.is_none());
+
+    // the request with the higher priority is admitted first
+    drop(first);
+    assert!(poll_once(&mut low).await.is_none());
+    let high = high.await.unwrap();
+    assert!(poll_once(&mut low).await.is_none());
+    drop(high);
+    let _low = low.await.unwrap();
+}
+
+#[tokio::test]
+async fn aging() {
+    let mut config = config();
+    config.max_concurrent_jobs = 1;
+    config.max_queue_length = 2;
+    config.priority_aging = 1;
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+
+    let first = queue.acquire(&metrics, jobs(1)).await.unwrap();
+    let low = queue.acquire(&metrics, jobs(1));
+    tokio::pin!(low);
+    assert!(poll_once(&mut low).await.is_none());
+
+    // after waiting for more than one second, the priority of the first
+    // request is higher than the priority of the second one
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let high = queue.acquire(
+        &metrics,
+        Demand {
+            priority: 1,
+            ..jobs(1)
+        },
+    );
+    tokio::pin!(high);
+    assert!(poll_once(&mut high).await.is_none());
+
+    drop(first);
+    assert!(poll_once(&mut high).await.is_none());
+    let low = low.await.unwrap();
+    drop(low);
+    let _high = high.await.unwrap();
+}
+
+#[tokio::test]
+async fn pools() {
+    let mut config = config();
+    config.max_concurrent_jobs = 3;
+    config.max_concurrent_compile_jobs = Some(1);
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+    let compile = || demand(Pool::Compile, 1, jobs(1).resources);
+
+    let first = queue.acquire(&metrics, compile()).await.unwrap();
+    let waiting = queue.acquire(&metrics, compile());
+    tokio::pin!(waiting);
+    assert!(poll_once(&mut waiting).await.is_none());
+
+    // a full compile pool does not block run steps
+    let _run = queue.acquire(&metrics, jobs(1)).await.unwrap();
+    let _run = queue.acquire(&metrics, jobs(1)).await.unwrap();
+    assert_eq!(metrics.queue.reserved.jobs.get(), 3);
+
+    // but the total number of jobs is still limited
+    assert!(poll_once(&mut waiting).await.is_none());
+    assert!(matches!(
+        queue.acquire(&metrics, jobs(1)).await,
+        Err(QueueError::Full)
+    ));
+    drop(first);
+    let _compile = waiting.await.unwrap();
+}
+
+#[tokio::test]
+async fn cpuset() {
+    let mut config = config();
+    config.max_concurrent_jobs = 4;
+    config.cpuset = Some(vec![2, 3, 5]);
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+    assert_eq!(queue.capacity().cpus, 3);
+
+    let first = queue.acquire(&metrics, job(2, 1)).await.unwrap();
+    let second = queue.acquire(&metrics, job(0, 1)).await.unwrap();
+    let mut cores = [first.cores().unwrap(), second.cores().unwrap()].concat();
+    cores.sort();
+    assert_eq!(cores, [2, 3, 5]);
+
+    // no cores are left
+    let waiting = queue.acquire(&metrics, job(1, 1));
+    tokio::pin!(waiting);
+    assert!(poll_once(&mut waiting).await.is_none());
+
+    let freed = second.cores().unwrap().to_vec();
+    drop(second);
+    let third = waiting.await.unwrap();
+    assert_eq!(third.cores().unwrap(), freed);
+    drop(first);
+}
+
+#[tokio::test]
+async fn drain() {
+    let config = config();
+    let metrics = Metrics::new().unwrap();
+    let queue = JobQueue::new(&config);
+
+    let permit = queue.acquire(&metrics, jobs(1)).await.unwrap();
+    queue.set_draining(true);
+    assert!(queue.is_draining());
+    assert!(matches!(
+        queue.acquire(&metrics, jobs(1)).await,
+        Err(QueueError::Draining)
+    ));
+    assert_eq!(
+        metrics
+            .queue
+            .rejected
+            .with_label_values(&["draining"])
+            .get(),
+        1
+    );
+    assert_eq!(queue.status(), (1, 0));
+
+    // running jobs are not affected
+    let idle = queue.wait_idle();
+    tokio::pin!(idle);
+    assert!(poll_once(&mut idle).await.is_none());
+    drop(permit);
+    idle.await;
+    assert_eq!(queue.status(), (0, 0));
+
+    queue.set_draining(false);
+    let _permit = queue.acquire(&metrics, jobs(1)).await.unwrap();
+}
+
+/// Poll a future once and return its output if it is ready.
+async fn poll_once<F: Future + Unpin>(future: &mut F) -> Option<F::Output> {
+    poll_fn(|cx| {
+        Poll::Ready(match Pin::new(&mut *future).poll(cx) {
+            Poll::Ready(output) => Some(output),
+            Poll::Pending => None,
+        })
+    })
+    .await
+}
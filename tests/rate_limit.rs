@@ -0,0 +1,167 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use poem::{http::StatusCode, test::TestClient};
+use sandkasten::{
+    config::{ApiKey, Config},
+    rate_limit::RateLimiter,
+};
+use sandkasten_client::schemas::{programs::ResourceUsage, RateLimit, RateLimitExceeded};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    config.rate_limit_burst = 3;
+    config.rate_limit_per_second = 0.5;
+    config.budget_window = 60;
+    config.cpu_budget = 2;
+    config.memory_budget = 1;
+    config
+}
+
+fn usage(time: u64, memory: u64) -> ResourceUsage {
+    ResourceUsage { time, memory }
+}
+
+#[test]
+fn token_bucket() {
+    let config = config();
+    let limiter = RateLimiter::default();
+    let start = Instant::now();
+    for _ in 0..3 {
+        limiter.check_request(&config, "foo", start).unwrap();
+    }
+    assert_eq!(
+        limiter.check_request(&config, "foo", start),
+        Err(RateLimitExceeded {
+            limit: RateLimit::Requests,
+            retry_after: 2
+        })
+    );
+    limiter.check_request(&config, "bar", start).unwrap();
+
+    let later = start + Duration::from_secs(1);
+    assert_eq!(
+        limiter.check_request(&config, "foo", later),
+        Err(RateLimitExceeded {
+            limit: RateLimit::Requests,
+            retry_after: 1
+        })
+    );
+    let later = start + Duration::from_secs(2);
+    limiter.check_request(&config, "foo", later).unwrap();
+    assert!(limiter.check_request(&config, "foo", later).is_err());
+
+    // the bucket never holds more than `rate_limit_burst` tokens
+    let later = start + Duration::from_secs(3600);
+    for _ in 0..3 {
+        limiter.check_request(&config, "foo", later).unwrap();
+    }
+    assert!(limiter.check_request(&config, "foo", later).is_err());
+}
+
+#[test]
+fn cpu_budget() {
+    let mut config = config();
+    config.memory_budget = 0;
+    let limiter = RateLimiter::default();
+    let start = Instant::now();
+    limiter.check_budget(&config, "foo", start).unwrap();
+    limiter.record(&config, "foo", &usage(1500, 1 << 20), start);
+    limiter.check_budget(&config, "foo", start).unwrap();
+
+    let later = start + Duration::from_secs(10);
+    limiter.record(&config, "foo", &usage(500, 1 << 20), later);
+    assert_eq!(
+        limiter.check_budget(&config, "foo", later),
+        Err(RateLimitExceeded {
+            limit: RateLimit::CpuTime,
+            retry_after: 50
+        })
+    );
+    limiter.check_budget(&config, "bar", later).unwrap();
+
+    limiter
+        .check_budget(&config, "foo", start + Duration::from_secs(60))
+        .unwrap();
+}
+
+#[test]
+fn memory_budget() {
+    let config = config();
+    let limiter = RateLimiter::default();
+    let start = Instant::now();
+    limiter.record(&config, "foo", &usage(500, 1024), start);
+    limiter.check_budget(&config, "foo", start).unwrap();
+    limiter.record(&config, "foo", &usage(500, 1024), start);
+    assert_eq!(
+        limiter.check_budget(&config, "foo", start),
+        Err(RateLimitExceeded {
+            limit: RateLimit::MemoryTime,
+            retry_after: 60
+        })
+    );
+}
+
+#[test]
+fn disabled() {
+    let mut config = config();
+    config.rate_limit_burst = 0;
+    config.cpu_budget = 0;
+    config.memory_budget = 0;
+    let limiter = RateLimiter::default();
+    let now = Instant::now();
+    for _ in 0..100 {
+        limiter.check_request(&config, "foo", now).unwrap();
+        limiter.record(&config, "foo", &usage(u64::MAX, u64::MAX), now);
+        limiter.check_budget(&config, "foo", now).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn budget_checked_first() {
+    let mut config = config();
+    config.rate_limit_burst = 0;
+    config.api_keys = vec![ApiKey {
+        name: "foo".into(),
+        hash: format!("{:x}", Sha256::digest("foo")),
+        compile_limits: Default::default(),
+        run_limits: Default::default(),
+        environments: None,
+        storage_quota: None,
+        priority: None,
+        admin: false,
+    }];
+    let config = Arc::new(config);
+    let limiter = Arc::new(RateLimiter::default());
+    limiter.record(&config, "foo", &usage(2000, 0), Instant::now());
+    let client = TestClient::new(common::app(config, Default::default(), limiter));
+
+    // the budget is checked before the request is validated
+    let id = Uuid::new_v4();
+    let source = json!({"environment": "python", "main_file": {"name": "../main", "content": ""}});
+    let files = json!({"files": [{"name": "../test", "content": ""}]});
+    for (path, body) in [
+        ("/run".into(), json!({"build": source, "run": files})),
+        ("/jobs".into(), json!({"build": source, "run": files})),
+        ("/programs".into(), source.clone()),
+        ("/check".into(), source.clone()),
+        ("/format".into(), source),
+        (format!("/programs/{id}/run"), files.clone()),
+        (format!("/programs/{id}/test"), files),
+    ] {
+        client
+            .post(&path)
+            .header("Authorization", "Bearer foo")
+            .body_json(&body)
+            .send()
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    }
+}
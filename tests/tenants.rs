@@ -0,0 +1,195 @@
+use std::{env, fs, sync::Arc};
+
+use key_rwlock::KeyRwLock;
+use poem::{http::StatusCode, test::TestClient, Endpoint};
+use sandkasten::{
+    config::{ApiKey, Config},
+    program::{
+        manifest::{Manifest, MANIFEST_VERSION},
+        prune::prune_programs,
+        tenant::{StorageUsage, Tenant},
+    },
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    config.programs_dir = env::temp_dir().join(format!("sandkasten-test-{}", Uuid::new_v4()));
+    config.rate_limit_burst = 0;
+    config.api_keys = ["foo", "bar"]
+        .into_iter()
+        .map(|name| ApiKey {
+            name: name.into(),
+            hash: format!("{:x}", Sha256::digest(name)),
+            compile_limits: Default::default(),
+            run_limits: Default::default(),
+            environments: None,
+            storage_quota: None,
+            priority: None,
+            admin: false,
+        })
+        .collect();
+    config
+}
+
+fn app(config: Config) -> impl Endpoint {
+    common::app(Arc::new(config), Default::default(), Default::default())
+}
+
+/// Store a program of the given size that has been built by the given tenant.
+async fn store_program(config: &Config, tenant: &str, size: u64) -> Uuid {
+    let id = Uuid::new_v4();
+    let path = config.programs_dir.join(id.to_string());
+    fs::create_dir_all(&path).unwrap();
+    Manifest {
+        version: MANIFEST_VERSION,
+        environment: "python".into(),
+        environment_version: "1".into(),
+        run_script: "/bin/run".into(),
+        closure: "/nix/store/closure".into(),
+        test_script: Some("/bin/test".into()),
+        run_options: Default::default(),
+        packages: Default::default(),
+        main_file: "main.py".into(),
+        files: Vec::new(),
+        hash: None,
+        compile_result: None,
+        created_at: 0,
+        last_run: 0,
+        tenant: Some(tenant.into()),
+        size,
+    }
+    .save(&path)
+    .await
+    .unwrap();
+    id
+}
+
+#[tokio::test]
+async fn programs() {
+    let config = config();
+    let id = store_program(&config, "foo", 0).await;
+    let programs_dir = config.programs_dir.clone();
+    let client = TestClient::new(app(config));
+
+    for endpoint in ["run", "test"] {
+        let response = client
+            .post(format!("/programs/{id}/{endpoint}"))
+            .header("Authorization", "Bearer bar")
+            .body_json(&json!({}))
+            .send()
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        response
+            .assert_json(json!({"error": "program_not_found"}))
+            .await;
+
+        // the owner can access the program (but cannot actually run it here)
+        let response = client
+            .post(format!("/programs/{id}/{endpoint}"))
+            .header("Authorization", "Bearer foo")
+            .body_json(&json!({}))
+            .send()
+            .await;
+        assert_ne!(response.0.status(), StatusCode::NOT_FOUND);
+    }
+    client
+        .get(format!("/programs/{id}/files"))
+        .header("Authorization", "Bearer bar")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    fs::remove_dir_all(programs_dir).unwrap();
+}
+
+#[tokio::test]
+async fn jobs() {
+    let client = TestClient::new(app(config()));
+
+    let response = client
+        .post("/jobs")
+        .header("Authorization", "Bearer foo")
+        .body_json(&json!({
+            "build": {"environment": "python", "main_file": {"content": ""}},
+            "run": {}
+        }))
+        .send()
+        .await;
+    response.assert_status(StatusCode::ACCEPTED);
+    let id = response
+        .json()
+        .await
+        .value()
+        .object()
+        .get("id")
+        .string()
+        .to_owned();
+
+    for (key, status) in [("bar", StatusCode::NOT_FOUND), ("foo", StatusCode::OK)] {
+        client
+            .get(format!("/jobs/{id}"))
+            .header("Authorization", format!("Bearer {key}"))
+            .send()
+            .await
+            .assert_status(status);
+        client
+            .delete(format!("/jobs/{id}"))
+            .header("Authorization", format!("Bearer {key}"))
+            .send()
+            .await
+            .assert_status(status);
+    }
+}
+
+#[tokio::test]
+async fn storage_usage() {
+    let mut config = config();
+    store_program(&config, "foo", 3).await;
+    store_program(&config, "foo", 4).await;
+    store_program(&config, "bar", 5).await;
+
+    let usage = Arc::new(StorageUsage::load(&config).await.unwrap());
+    assert_eq!(usage.get(Some("foo")), 7);
+    assert_eq!(usage.get(Some("bar")), 5);
+    assert_eq!(usage.get(None), 0);
+
+    let foo = Tenant {
+        name: Some("foo"),
+        storage_quota: Some(10),
+    };
+
+    // concurrent reservations cannot exceed the quota together
+    let mut first = usage.reserve(foo, 2).unwrap();
+    assert_eq!(usage.reserve(foo, 2).unwrap_err(), 10);
+    assert_eq!(usage.get(Some("foo")), 9);
+
+    // shrinking a reservation always succeeds, growing it only within the quota
+    first.resize(1).unwrap();
+    assert_eq!(first.resize(4).unwrap_err(), 10);
+    first.resize(3).unwrap();
+    assert_eq!(usage.get(Some("foo")), 10);
+
+    // dropped reservations are released, committed ones are kept
+    let second = usage.reserve(foo, 0).unwrap();
+    drop(first);
+    assert_eq!(usage.get(Some("foo")), 7);
+    second.commit();
+    usage.reserve(foo, 3).unwrap().commit();
+    assert_eq!(usage.get(Some("foo")), 10);
+    assert_eq!(usage.get(Some("bar")), 5);
+
+    // pruned programs are released
+    config.program_ttl = 0;
+    prune_programs(&config, Arc::new(KeyRwLock::new()), Arc::clone(&usage))
+        .await
+        .unwrap();
+    assert_eq!(usage.get(Some("foo")), 3);
+    assert_eq!(usage.get(Some("bar")), 0);
+
+    fs::remove_dir_all(&config.programs_dir).unwrap();
+}
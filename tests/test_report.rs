@@ -0,0 +1,190 @@
+use std::{env, fs, os::unix::fs::symlink};
+
+use indoc::indoc;
+use sandkasten::program::{
+    read_sandbox_file,
+    test_report::{parse_junit, parse_tap, parse_test_report},
+};
+use sandkasten_client::schemas::programs::{TestCase, TestStatus};
+
+fn test_case(
+    name: &str,
+    status: TestStatus,
+    duration: Option<u64>,
+    message: Option<&str>,
+) -> TestCase {
+    TestCase {
+        name: name.into(),
+        status,
+        duration,
+        message: message.map(Into::into),
+    }
+}
+
+#[test]
+fn junit() {
+    let report = indoc! {r#"
+        <?xml version="1.0" encoding="utf-8"?>
+        <testsuites>
+          <testsuite name="pytest" errors="1" failures="1" skipped="1" tests="4" time="0.031">
+            <testcase classname="test_code" name="test_ok" time="0.001" />
+            <testcase classname="test_code" name="test_fail" time="0.0125">
+              <failure message="assert 1 == 2">def test_fail():
+        &gt;       assert 1 == 2</failure>
+            </testcase>
+            <testcase classname="test_code" name="test_error" time="0.002">
+              <error>RuntimeError: boom</error>
+            </testcase>
+            <testcase classname="" name="test_skip">
+              <skipped type="pytest.skip" message="not implemented" />
+            </testcase>
+          </testsuite>
+        </testsuites>
+    "#};
+    assert_eq!(
+        parse_junit(report).unwrap(),
+        [
+            test_case("test_code.test_ok", TestStatus::Passed, Some(1), None),
+            test_case(
+                "test_code.test_fail",
+                TestStatus::Failed,
+                Some(13),
+                Some("assert 1 == 2")
+            ),
+            test_case(
+                "test_code.test_error",
+                TestStatus::Error,
+                Some(2),
+                Some("RuntimeError: boom")
+            ),
+            test_case(
+                "test_skip",
+                TestStatus::Skipped,
+                None,
+                Some("not implemented")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn junit_invalid() {
+    assert_eq!(parse_junit("<testsuite>"), None);
+    assert_eq!(parse_test_report("<testsuite><testcase></testsuite>"), None);
+}
+
+#[test]
+fn tap() {
+    let report = indoc! {"
+        TAP version 13
+        1..5
+        ok 1 - first test
+        not ok 2 - second test
+        ok 3 # SKIP not supported
+        not ok 4 - fourth test # TODO implement later
+        ok 5 last test
+        # tests 5
+    "};
+    assert_eq!(
+        parse_tap(report),
+        [
+            test_case("first test", TestStatus::Passed, None, None),
+            test_case("second test", TestStatus::Failed, None, None),
+            test_case("", TestStatus::Skipped, None, Some("not supported")),
+            test_case(
+                "fourth test",
+                TestStatus::Skipped,
+                None,
+                Some("implement later")
+            ),
+            test_case("last test", TestStatus::Passed, None, None),
+        ]
+    );
+}
+
+#[test]
+fn tap_node() {
+    let report = indoc! {"
+        TAP version 13
+        # Subtest: math
+            # Subtest: adds
+            ok 1 - adds
+              ---
+              duration_ms: 0.5
+              ...
+            1..1
+        ok 1 - math
+          ---
+          duration_ms: 1.6
+          type: 'suite'
+          ...
+        # Subtest: fails
+        not ok 2 - fails
+          ---
+          duration_ms: 2.4
+          failureType: 'testCodeFailure'
+          error: |-
+            Expected values to be strictly equal:
+
+            1 !== 2
+          code: 'ERR_ASSERTION'
+          ...
+        # Subtest: throws
+        not ok 3 - throws
+          ---
+          duration_ms: 0.3
+          error: 'boom'
+          ...
+        1..3
+    "};
+    assert_eq!(
+        parse_test_report(report).unwrap(),
+        [
+            test_case("math", TestStatus::Passed, Some(2), None),
+            test_case(
+                "fails",
+                TestStatus::Failed,
+                Some(2),
+                Some("Expected values to be strictly equal:\n\n1 !== 2")
+            ),
+            test_case("throws", TestStatus::Failed, Some(0), Some("boom")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn symlinked_report() {
+    let dir = env::temp_dir().join(format!("sandkasten-test-{}", uuid::Uuid::new_v4()));
+    let report = dir.join("report");
+    let secret = dir.join("secret");
+    fs::create_dir_all(report.join("dir")).unwrap();
+    fs::write(&secret, "secret").unwrap();
+    fs::write(report.join("report"), "ok 1 - foo").unwrap();
+    symlink(&secret, report.join("link")).unwrap();
+    symlink(&dir, report.join("dirlink")).unwrap();
+    fs::write(report.join("large"), [0; 17]).unwrap();
+
+    assert_eq!(
+        read_sandbox_file(&report, "report", 16).await.unwrap(),
+        Some(b"ok 1 - foo".to_vec())
+    );
+    assert_eq!(read_sandbox_file(&report, "link", 16).await.unwrap(), None);
+    assert_eq!(
+        read_sandbox_file(&report, "dirlink/secret", 16)
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(read_sandbox_file(&report, "dir", 16).await.unwrap(), None);
+    assert_eq!(read_sandbox_file(&report, "large", 16).await.unwrap(), None);
+    assert_eq!(
+        read_sandbox_file(&report, "missing", 16).await.unwrap(),
+        None
+    );
+    assert_eq!(
+        read_sandbox_file(&report, "../secret", 16).await.unwrap(),
+        None
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
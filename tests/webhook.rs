@@ -0,0 +1,89 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::TcpListener,
+    thread,
+};
+
+use sandkasten::{
+    config::Config,
+    webhook::{client, deliver, is_allowed_url, sign},
+};
+
+mod common;
+
+fn config() -> Config {
+    let mut config = common::config();
+    config.webhook_secret = "secret".into();
+    config.webhook_attempts = 1;
+    config
+}
+
+#[test]
+fn allowed_urls() {
+    let allowed = [
+        "http://127.0.0.1:8001/".to_owned(),
+        "https://grader.example.com/hooks/".to_owned(),
+    ];
+    for url in [
+        "http://127.0.0.1:8001/",
+        "http://127.0.0.1:8001/foo?bar=baz",
+        "https://grader.example.com/hooks/sandkasten",
+        "https://grader.example.com:443/hooks/",
+    ] {
+        assert!(is_allowed_url(&allowed, url), "{url}");
+    }
+    for url in [
+        "",
+        "127.0.0.1:8001/",
+        "http://127.0.0.1:8002/",
+        "https://127.0.0.1:8001/",
+        "http://localhost:8001/",
+        "https://grader.example.com/",
+        "https://grader.example.com/hooks",
+        "https://grader.example.com.evil.com/hooks/",
+        "http://grader.example.com/hooks/",
+    ] {
+        assert!(!is_allowed_url(&allowed, url), "{url}");
+    }
+    assert!(!is_allowed_url(&[], "http://127.0.0.1:8001/"));
+}
+
+#[test]
+fn signature() {
+    // test case 2 from RFC 4231
+    assert_eq!(
+        sign("Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[tokio::test]
+async fn redirects_not_followed() {
+    let config = config();
+    let target = TcpListener::bind("127.0.0.1:0").unwrap();
+    target.set_nonblocking(true).unwrap();
+    let target_port = target.local_addr().unwrap().port();
+
+    // stand-in receiver that redirects to the target
+    let receiver = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/callback", receiver.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (mut stream, _) = receiver.accept().unwrap();
+        // skip the request headers
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        write!(
+            stream,
+            "HTTP/1.1 307 Temporary Redirect\r\nLocation: http://127.0.0.1:{target_port}/\r\nContent-Length: 0\r\n\r\n"
+        )
+        .unwrap();
+    });
+
+    deliver(&client(&config), &config, &url, "{}".into()).await;
+    server.join().unwrap();
+    assert_eq!(target.accept().unwrap_err().kind(), ErrorKind::WouldBlock);
+}